@@ -0,0 +1,160 @@
+use crate::xattr;
+use std::path::Path;
+
+pub(crate) const ACCESS_XATTR: &str = "system.posix_acl_access";
+pub(crate) const DEFAULT_XATTR: &str = "system.posix_acl_default";
+
+const ACL_VERSION: u32 = 2;
+const HEADER_LEN: usize = 4;
+const ENTRY_LEN: usize = 8;
+
+/// Entry tags of the kernel's POSIX ACL xattr format.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Tag {
+  UserObj = 0x01,
+  User = 0x02,
+  GroupObj = 0x04,
+  Group = 0x08,
+  Mask = 0x10,
+  Other = 0x20,
+}
+
+#[derive(Clone)]
+pub(crate) struct AclEntry {
+  pub(crate) tag: Tag,
+  /// UID or GID for named user and group entries
+  pub(crate) id: Option<u32>,
+  /// rwx bits, 0o4 | 0o2 | 0o1
+  pub(crate) perms: u32,
+}
+
+impl Tag {
+  /// Whether the ACL mask limits entries with this tag.
+  pub(crate) fn is_masked(self) -> bool {
+    matches!(self, Tag::User | Tag::GroupObj | Tag::Group)
+  }
+
+  fn from_raw(raw: u16) -> Option<Self> {
+    Some(match raw {
+      0x01 => Tag::UserObj,
+      0x02 => Tag::User,
+      0x04 => Tag::GroupObj,
+      0x08 => Tag::Group,
+      0x10 => Tag::Mask,
+      0x20 => Tag::Other,
+      _ => return None,
+    })
+  }
+
+  pub(crate) fn name(self) -> &'static str {
+    match self {
+      Tag::UserObj | Tag::User => "user",
+      Tag::GroupObj | Tag::Group => "group",
+      Tag::Mask => "mask",
+      Tag::Other => "other",
+    }
+  }
+}
+
+/// Renders rwx bits as in getfacl, e.g. `r-x`.
+pub(crate) fn perms_string(perms: u32) -> String {
+  [(0o4, 'r'), (0o2, 'w'), (0o1, 'x')]
+    .iter()
+    .map(|(bit, c)| if perms & bit != 0 { *c } else { '-' })
+    .collect()
+}
+
+/// The minimal ACL equivalent to the permission bits of `mode`.
+pub(crate) fn from_mode(mode: u32) -> Vec<AclEntry> {
+  [(Tag::UserObj, 6), (Tag::GroupObj, 3), (Tag::Other, 0)]
+    .into_iter()
+    .map(|(tag, shift)| AclEntry {
+      tag,
+      id: None,
+      perms: (mode >> shift) & 0o7,
+    })
+    .collect()
+}
+
+/// Whether the ACL has nothing beyond the owner, group and other entries.
+pub(crate) fn is_minimal(entries: &[AclEntry]) -> bool {
+  entries
+    .iter()
+    .all(|entry| matches!(entry.tag, Tag::UserObj | Tag::GroupObj | Tag::Other))
+}
+
+/// Sets the mask to the union of the entries it limits, as setfacl does,
+/// or drops it when there are no named entries.
+pub(crate) fn recompute_mask(entries: &mut Vec<AclEntry>) {
+  entries.retain(|entry| entry.tag != Tag::Mask);
+  if is_minimal(entries) {
+    return;
+  }
+  let perms = entries
+    .iter()
+    .filter(|entry| entry.tag.is_masked())
+    .fold(0, |perms, entry| perms | entry.perms);
+  entries.push(AclEntry {
+    tag: Tag::Mask,
+    id: None,
+    perms,
+  });
+}
+
+/// Encodes entries in the xattr format, sorted the way the kernel requires.
+fn encode(entries: &[AclEntry]) -> Vec<u8> {
+  let mut sorted = entries.to_vec();
+  sorted.sort_by_key(|entry| (entry.tag, entry.id));
+
+  let mut data = ACL_VERSION.to_le_bytes().to_vec();
+  for entry in sorted {
+    data.extend((entry.tag as u16).to_le_bytes());
+    data.extend((entry.perms as u16).to_le_bytes());
+    data.extend(entry.id.unwrap_or(u32::MAX).to_le_bytes());
+  }
+  data
+}
+
+/// Decodes an ACL xattr value. Returns `None` for malformed data.
+pub(crate) fn decode(data: &[u8]) -> Option<Vec<AclEntry>> {
+  let version = u32::from_le_bytes(data.get(..HEADER_LEN)?.try_into().ok()?);
+  if version != ACL_VERSION || !(data.len() - HEADER_LEN).is_multiple_of(ENTRY_LEN) {
+    return None;
+  }
+
+  data[HEADER_LEN..]
+    .chunks_exact(ENTRY_LEN)
+    .map(|chunk| {
+      let tag = Tag::from_raw(u16::from_le_bytes([chunk[0], chunk[1]]))?;
+      let perms = u16::from_le_bytes([chunk[2], chunk[3]]) as u32 & 0o7;
+      let id = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]);
+      Some(AclEntry {
+        tag,
+        id: matches!(tag, Tag::User | Tag::Group).then_some(id),
+        perms,
+      })
+    })
+    .collect()
+}
+
+/// Reads the access ACL (or the default ACL when `default` is set) of
+/// `path`. Files without one, or on filesystems without ACL support, yield an
+/// empty list.
+pub(crate) fn read(path: &Path, default: bool) -> Result<Vec<AclEntry>, String> {
+  let name = if default { DEFAULT_XATTR } else { ACCESS_XATTR };
+  match xattr::get(path, name)? {
+    Some(data) => decode(&data).ok_or_else(|| format!("Malformed ACL on {}", path.display())),
+    None => Ok(Vec::new()),
+  }
+}
+
+/// Replaces the access or default ACL of `path`. An empty list removes it;
+/// the kernel keeps the file mode in step with a written access ACL.
+pub(crate) fn write(path: &Path, default: bool, entries: &[AclEntry]) -> Result<(), String> {
+  let name = if default { DEFAULT_XATTR } else { ACCESS_XATTR };
+  if entries.is_empty() {
+    xattr::remove(path, name)
+  } else {
+    xattr::set(path, name, &encode(entries))
+  }
+}
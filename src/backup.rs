@@ -0,0 +1,503 @@
+use crate::cli::ListFormat;
+use crate::config::Config;
+use crate::db::{Database, GROUP_PATH, GSHADOW_PATH, PASSWD_PATH, SHADOW_PATH};
+use crate::guest::GUEST_STORE_PATH;
+use crate::manifest::MANAGED_STORE_PATH;
+use crate::role::ROLE_STORE_PATH;
+use crate::root;
+use crate::sudo::SUDO_STORE_PATH;
+use crate::sudoers::SUDOERS_DIR;
+use crate::{sudo, time, user};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tabled::{Table, Tabled};
+
+const META_FILE: &str = "backup.toml";
+
+/// Files a backup covers besides those in sudoers.d. The stores go with
+/// the account files so that a restore leaves guests, manifest-managed
+/// entries, roles and sudo grants describing the accounts they describe.
+const FILES: [&str; 8] = [
+  PASSWD_PATH,
+  SHADOW_PATH,
+  GROUP_PATH,
+  GSHADOW_PATH,
+  GUEST_STORE_PATH,
+  MANAGED_STORE_PATH,
+  ROLE_STORE_PATH,
+  SUDO_STORE_PATH,
+];
+
+/// The backup taken by this process, if any, or why it failed.
+static TAKEN: OnceLock<Result<Option<String>, String>> = OnceLock::new();
+
+#[derive(Serialize, Deserialize)]
+struct Meta {
+  /// Seconds since the epoch
+  created: u64,
+  /// The command that was about to write
+  command: String,
+  /// Absolute paths of the files that existed, which are the ones saved
+  files: Vec<String>,
+}
+
+/// A saved file as read back for restoring.
+struct Saved {
+  path: String,
+  content: Vec<u8>,
+  mode: u32,
+  uid: u32,
+  gid: u32,
+}
+
+/// Paths covered by a backup taken now. They are recorded as seen from
+/// the `--root` directory.
+fn current_files() -> Vec<String> {
+  let mut files: Vec<String> = FILES
+    .iter()
+    .filter(|path| root::path(path).is_file())
+    .map(|path| path.to_string())
+    .collect();
+  if let Ok(entries) = fs::read_dir(root::path(SUDOERS_DIR)) {
+    let mut dropins: Vec<String> = entries
+      .flatten()
+      .filter(|entry| entry.file_type().is_ok_and(|t| t.is_file()))
+      .map(|entry| {
+        Path::new(SUDOERS_DIR)
+          .join(entry.file_name())
+          .to_string_lossy()
+          .into_owned()
+      })
+      .collect();
+    dropins.sort();
+    files.extend(dropins);
+  }
+  files
+}
+
+/// Where a file is kept inside a backup: under its absolute path.
+fn saved_path(dir: &Path, path: &str) -> PathBuf {
+  dir.join(path.trim_start_matches('/'))
+}
+
+fn backups_dir(config: &Config) -> PathBuf {
+  root::path(&config.backup.dir)
+}
+
+fn create_private_dir(dir: &Path) -> Result<(), String> {
+  fs::DirBuilder::new()
+    .recursive(true)
+    .mode(0o700)
+    .create(dir)
+    .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))
+}
+
+/// Copies a file keeping its mode and owner.
+fn copy_file(from: &Path, to: &Path) -> Result<(), String> {
+  let meta = fs::metadata(from).map_err(|e| format!("Failed to stat {}: {}", from.display(), e))?;
+  if let Some(dir) = to.parent() {
+    create_private_dir(dir)?;
+  }
+  fs::copy(from, to)
+    .and_then(|_| std::os::unix::fs::chown(to, Some(meta.uid()), Some(meta.gid())))
+    .map_err(|e| {
+      format!(
+        "Failed to copy {} to {}: {}",
+        from.display(),
+        to.display(),
+        e
+      )
+    })?;
+  Ok(())
+}
+
+/// Saves the covered files into a new timestamped directory and removes
+/// backups beyond `backup.keep`. The directory only gets its final name
+/// once every file is in it.
+fn take(config: &Config) -> Result<String, String> {
+  let base = backups_dir(config);
+  create_private_dir(&base)?;
+  let created = time::now();
+  let stamp = time::format_stamp(created);
+  // Number past every backup of the same second, even pruned ones' gaps,
+  // so that IDs keep increasing
+  let last = fs::read_dir(&base)
+    .map_err(|e| format!("Failed to read {}: {}", base.display(), e))?
+    .flatten()
+    .filter_map(|entry| {
+      let name = entry.file_name().to_string_lossy().into_owned();
+      let (id_stamp, sequence) = id_order(&name);
+      (id_stamp == stamp).then_some(sequence)
+    })
+    .max();
+  let id = match last {
+    None => stamp,
+    Some(last) => format!("{}-{}", stamp, last + 1),
+  };
+
+  let partial = base.join(format!(".{}.partial", id));
+  let _ = fs::remove_dir_all(&partial);
+  create_private_dir(&partial)?;
+  let files = current_files();
+  let result = (|| {
+    for path in &files {
+      copy_file(&root::path(path), &saved_path(&partial, path))?;
+    }
+    let meta = Meta {
+      created,
+      command: std::env::args().skip(1).collect::<Vec<_>>().join(" "),
+      files: files.clone(),
+    };
+    let content = toml::to_string_pretty(&meta)
+      .map_err(|e| format!("Failed to serialize backup details: {}", e))?;
+    let meta_path = partial.join(META_FILE);
+    fs::write(&meta_path, content)
+      .map_err(|e| format!("Failed to write to {}: {}", meta_path.display(), e))?;
+    fs::rename(&partial, base.join(&id))
+      .map_err(|e| format!("Failed to rename {}: {}", partial.display(), e))
+  })();
+  if let Err(e) = result {
+    let _ = fs::remove_dir_all(&partial);
+    return Err(e);
+  }
+
+  prune(config, &id);
+  Ok(id)
+}
+
+/// Removes the oldest backups beyond `backup.keep`, never `current`.
+fn prune(config: &Config, current: &str) {
+  let keep = config.backup.keep as usize;
+  if keep == 0 {
+    return;
+  }
+  let Ok(ids) = backup_ids(config) else {
+    return;
+  };
+  let excess = ids.len().saturating_sub(keep);
+  for id in ids.iter().take(excess).filter(|id| *id != current) {
+    let _ = fs::remove_dir_all(backups_dir(config).join(id));
+  }
+}
+
+/// Backs up the covered files the first time a command is about to change
+/// one of them, so each command that writes gets one backup of the state
+/// before it. Does nothing when backups are disabled.
+pub(crate) fn before_write() -> Result<(), String> {
+  taken().map(|_| ())
+}
+
+/// The ID of the backup this process took, taking it if needed.
+fn taken() -> Result<Option<String>, String> {
+  TAKEN
+    .get_or_init(|| {
+      let config = Config::load()?;
+      if !config.backup.enabled {
+        return Ok(None);
+      }
+      take(&config)
+        .map(Some)
+        .map_err(|e| format!("Backup failed, so nothing was changed: {}", e))
+    })
+    .clone()
+}
+
+/// IDs of the complete backups, oldest first.
+fn backup_ids(config: &Config) -> Result<Vec<String>, String> {
+  let base = backups_dir(config);
+  let entries = match fs::read_dir(&base) {
+    Ok(entries) => entries,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(e) => return Err(format!("Failed to read {}: {}", base.display(), e)),
+  };
+  let mut ids: Vec<String> = entries
+    .flatten()
+    .filter(|entry| entry.path().join(META_FILE).is_file())
+    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+    .filter(|id| !id.starts_with('.'))
+    .collect();
+  ids.sort_by(|a, b| id_order(a).cmp(&id_order(b)));
+  Ok(ids)
+}
+
+/// Orders backup IDs by time, then by the sequence number added to backups
+/// taken in the same second, compared as a number so that `-10` comes after
+/// `-9`.
+fn id_order(id: &str) -> (&str, u32) {
+  match id.rsplit_once('-') {
+    Some((stamp, sequence)) if stamp.contains('-') => (stamp, sequence.parse().unwrap_or(0)),
+    _ => (id, 1),
+  }
+}
+
+fn load_meta(config: &Config, id: &str) -> Result<(PathBuf, Meta), String> {
+  let dir = backups_dir(config).join(id);
+  if id.contains('/') || id.starts_with('.') || !dir.join(META_FILE).is_file() {
+    return Err(format!("Backup {} does not exist", id));
+  }
+  let path = dir.join(META_FILE);
+  let content =
+    fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+  let meta =
+    toml::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))?;
+  Ok((dir, meta))
+}
+
+#[derive(Tabled, Serialize)]
+struct BackupRow {
+  #[tabled(rename = "ID")]
+  id: String,
+  #[tabled(rename = "Created")]
+  created: String,
+  #[tabled(rename = "Command")]
+  command: String,
+  #[tabled(rename = "Files")]
+  files: usize,
+}
+
+pub(crate) fn list(format: &ListFormat) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+  let rows = Config::load().and_then(|config| {
+    backup_ids(&config)?
+      .into_iter()
+      .map(|id| {
+        let (_, meta) = load_meta(&config, &id)?;
+        Ok(BackupRow {
+          id,
+          created: time::format_timestamp(meta.created),
+          command: meta.command,
+          files: meta.files.len(),
+        })
+      })
+      .collect::<Result<Vec<_>, String>>()
+  });
+  let rows = match rows {
+    Ok(rows) => rows,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  match format {
+    ListFormat::Table => {
+      if rows.is_empty() {
+        println!("No backups");
+      } else {
+        println!("Backups:");
+        println!("{}", Table::new(rows));
+      }
+    }
+    ListFormat::Json => match serde_json::to_string_pretty(&rows) {
+      Ok(json) => println!("{}", json),
+      Err(e) => {
+        eprintln!("Error: Failed to serialize backups to JSON: {}", e);
+        return false;
+      }
+    },
+    ListFormat::Csv => {
+      println!("id,created,command,files");
+      let quote = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+      for row in rows {
+        println!(
+          "{},{},{},{}",
+          row.id,
+          quote(&row.created),
+          quote(&row.command),
+          row.files
+        );
+      }
+    }
+  }
+  true
+}
+
+/// The lines removed from `old` and added in `new`, in order, found with a
+/// longest-common-subsequence table after trimming the common ends.
+fn diff_lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<(char, &'a str)> {
+  let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+  let suffix = old[prefix..]
+    .iter()
+    .rev()
+    .zip(new[prefix..].iter().rev())
+    .take_while(|(a, b)| a == b)
+    .count();
+  let old = &old[prefix..old.len() - suffix];
+  let new = &new[prefix..new.len() - suffix];
+
+  let mut lcs = vec![vec![0u32; new.len() + 1]; old.len() + 1];
+  for i in (0..old.len()).rev() {
+    for j in (0..new.len()).rev() {
+      lcs[i][j] = if old[i] == new[j] {
+        lcs[i + 1][j + 1] + 1
+      } else {
+        lcs[i + 1][j].max(lcs[i][j + 1])
+      };
+    }
+  }
+  let (mut i, mut j) = (0, 0);
+  let mut changes = Vec::new();
+  while i < old.len() || j < new.len() {
+    if i < old.len() && j < new.len() && old[i] == new[j] {
+      i += 1;
+      j += 1;
+    } else if j < new.len() && (i == old.len() || lcs[i][j + 1] >= lcs[i + 1][j]) {
+      changes.push(('+', new[j]));
+      j += 1;
+    } else {
+      changes.push(('-', old[i]));
+      i += 1;
+    }
+  }
+  changes
+}
+
+/// Shows how the current files differ from a backup: lines starting with
+/// `-` are only in the backup, those with `+` only in the current files.
+pub(crate) fn diff(id: &str) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+  let result = Config::load().and_then(|config| load_meta(&config, id));
+  let (dir, meta) = match result {
+    Ok(result) => result,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  let paths: BTreeSet<String> = meta.files.iter().cloned().chain(current_files()).collect();
+  let mut differs = false;
+  for path in &paths {
+    let read = |file: &Path| fs::read_to_string(file).ok();
+    let saved = meta
+      .files
+      .contains(path)
+      .then(|| read(&saved_path(&dir, path)))
+      .flatten();
+    let current = read(&root::path(path));
+    if saved == current {
+      continue;
+    }
+    differs = true;
+    let label = |content: &Option<String>| if content.is_some() { "" } else { " (absent)" };
+    println!("--- {} (backup {}){}", path, id, label(&saved));
+    println!("+++ {} (current){}", path, label(&current));
+    let saved_lines: Vec<&str> = saved.as_deref().unwrap_or_default().lines().collect();
+    let current_lines: Vec<&str> = current.as_deref().unwrap_or_default().lines().collect();
+    for (sign, line) in diff_lines(&saved_lines, &current_lines) {
+      println!("{}{}", sign, line);
+    }
+  }
+  if !differs {
+    println!("No differences between backup {} and the current files", id);
+  }
+  true
+}
+
+/// Writes a file through a temporary sibling and renames it into place.
+fn restore_file(saved: &Saved) -> Result<(), String> {
+  let path = root::path(&saved.path);
+  let dir = path.parent().unwrap_or(Path::new("/"));
+  let name = path
+    .file_name()
+    .map_or_else(String::new, |n| n.to_string_lossy().to_string());
+  // A name with a dot keeps sudo from reading a half-written drop-in
+  let temp = dir.join(format!(".{}.restore", name));
+  let write = || -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let _ = fs::remove_file(&temp);
+    let mut file = fs::OpenOptions::new()
+      .write(true)
+      .create_new(true)
+      .mode(0o600)
+      .open(&temp)?;
+    std::io::Write::write_all(&mut file, &saved.content)?;
+    std::os::unix::fs::fchown(&file, Some(saved.uid), Some(saved.gid))?;
+    file.set_permissions(fs::Permissions::from_mode(saved.mode & 0o7777))?;
+    file.sync_all()?;
+    fs::rename(&temp, &path)
+  };
+  write().map_err(|e| {
+    let _ = fs::remove_file(&temp);
+    format!("Failed to restore {}: {}", path.display(), e)
+  })
+}
+
+/// Puts the covered files back as they were in a backup, removing those
+/// userkit writes that did not exist then. The state before the restore is backed up
+/// first, so a restore can be undone the same way.
+pub(crate) fn restore(id: &str) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  let result = (|| -> Result<Option<String>, String> {
+    let config = Config::load()?;
+    let (dir, meta) = load_meta(&config, id)?;
+    // Read everything first, as the backup taken below may prune this one
+    let saved = meta
+      .files
+      .iter()
+      .map(|path| {
+        let file = saved_path(&dir, path);
+        let content =
+          fs::read(&file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+        let file_meta =
+          fs::metadata(&file).map_err(|e| format!("Failed to stat {}: {}", file.display(), e))?;
+        Ok(Saved {
+          path: path.clone(),
+          content,
+          mode: file_meta.mode(),
+          uid: file_meta.uid(),
+          gid: file_meta.gid(),
+        })
+      })
+      .collect::<Result<Vec<_>, String>>()?;
+
+    // Hold the account database lock while the files are replaced
+    let _db = Database::open()?;
+    let before = taken()?;
+    // Only files userkit writes are removed; a drop-in installed since by
+    // anything else stays.
+    let stale: Vec<String> = current_files()
+      .into_iter()
+      .filter(|path| !meta.files.contains(path))
+      .filter(|path| FILES.contains(&path.as_str()) || sudo::is_managed_dropin(&root::path(path)))
+      .collect();
+    for file in &saved {
+      restore_file(file)?;
+    }
+    for path in &stale {
+      fs::remove_file(root::path(path)).map_err(|e| format!("Failed to remove {}: {}", path, e))?;
+    }
+    Ok(before)
+  })();
+
+  match result {
+    Ok(before) => {
+      println!("Restored backup {}", id);
+      if let Some(before) = before {
+        println!(
+          "The files as they were before the restore are in backup {}",
+          before
+        );
+      }
+      true
+    }
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      false
+    }
+  }
+}
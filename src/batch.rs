@@ -0,0 +1,478 @@
+use crate::cli::{Cli, Domains, GroupCommands, UserCommands};
+use crate::config::Config;
+use crate::db::{self, Database, GroupEntry, GshadowEntry, PasswdEntry};
+use crate::import::{self, Row};
+use crate::user;
+use clap::Parser;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// One step of a batch, with where it came from for error reports.
+struct Step {
+  /// Line number in a script, or position in a JSON array
+  at: String,
+  text: String,
+  args: Vec<String>,
+}
+
+/// Work on the filesystem that cannot be part of the transaction and is
+/// done once it has been committed.
+enum FollowUp {
+  CreateHome(PasswdEntry),
+  RemoveHome(PasswdEntry),
+}
+
+/// Splits a command line into words the way a shell would for plain
+/// arguments: whitespace separates words, quotes group them and a backslash
+/// escapes the next character outside single quotes.
+fn split_words(line: &str) -> Result<Vec<String>, String> {
+  let mut words = Vec::new();
+  let mut word = String::new();
+  let mut in_word = false;
+  let mut quote = None;
+  let mut chars = line.chars();
+  while let Some(c) = chars.next() {
+    match (quote, c) {
+      (Some(q), c) if c == q => quote = None,
+      (Some('"'), '\\') | (None, '\\') => match chars.next() {
+        Some(next) => {
+          word.push(next);
+          in_word = true;
+        }
+        None => return Err(String::from("Trailing backslash")),
+      },
+      (Some(_), c) => word.push(c),
+      (None, '\'' | '"') => {
+        quote = Some(c);
+        in_word = true;
+      }
+      (None, c) if c.is_whitespace() => {
+        if in_word {
+          words.push(std::mem::take(&mut word));
+          in_word = false;
+        }
+      }
+      (None, c) => {
+        word.push(c);
+        in_word = true;
+      }
+    }
+  }
+  if quote.is_some() {
+    return Err(String::from("Unterminated quote"));
+  }
+  if in_word {
+    words.push(word);
+  }
+  Ok(words)
+}
+
+/// Reads the steps of a script: one command per line without the leading
+/// `userkit`, or a JSON array whose items are command lines or arrays of
+/// arguments. Blank lines and lines starting with `#` are skipped.
+fn parse_steps(content: &str) -> Result<Vec<Step>, String> {
+  if content.trim_start().starts_with('[') {
+    let items: Vec<serde_json::Value> =
+      serde_json::from_str(content).map_err(|e| format!("Invalid JSON batch: {}", e))?;
+    return items
+      .into_iter()
+      .enumerate()
+      .map(|(i, item)| {
+        let at = format!("item {}", i + 1);
+        let args = match &item {
+          serde_json::Value::String(line) => {
+            split_words(line).map_err(|e| format!("Invalid {}: {}", at, e))?
+          }
+          serde_json::Value::Array(args) => args
+            .iter()
+            .map(|arg| arg.as_str().map(str::to_string))
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("Invalid {}: arguments must be strings", at))?,
+          _ => {
+            return Err(format!(
+              "Invalid {}: expected a command line or an array of arguments",
+              at
+            ));
+          }
+        };
+        Ok(Step {
+          text: args.join(" "),
+          at,
+          args,
+        })
+      })
+      .collect();
+  }
+
+  content
+    .lines()
+    .enumerate()
+    .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+    .map(|(i, line)| {
+      let at = format!("line {}", i + 1);
+      let args = split_words(line).map_err(|e| format!("Invalid {}: {}", at, e))?;
+      Ok(Step {
+        text: line.trim().to_string(),
+        at,
+        args,
+      })
+    })
+    .collect()
+}
+
+fn check_path(path: &str) -> Result<(), String> {
+  if !path.starts_with('/') || !db::is_valid_field(path) {
+    return Err(format!("Invalid path '{}': use an absolute path", path));
+  }
+  Ok(())
+}
+
+fn find_user(db: &Database, name: &str) -> Result<PasswdEntry, String> {
+  db.user(name)
+    .ok_or_else(|| format!("User {} does not exist", name))
+}
+
+fn find_group(db: &Database, name: &str) -> Result<GroupEntry, String> {
+  db.group(name)
+    .ok_or_else(|| format!("Group {} does not exist", name))
+}
+
+/// Applies one step to the databases in memory, returning what it did.
+fn run_step(
+  db: &mut Database,
+  config: &Config,
+  step: &Step,
+  follow_ups: &mut Vec<FollowUp>,
+) -> Result<String, String> {
+  let cli =
+    Cli::try_parse_from(std::iter::once("userkit").chain(step.args.iter().map(String::as_str)))
+      .map_err(|e| {
+        let message = e.to_string();
+        let first = message.lines().next().unwrap_or_default();
+        first.trim_start_matches("error: ").to_string()
+      })?;
+
+  match &cli.domain {
+    Domains::User(UserCommands::Add {
+      username,
+      home_dir,
+      shell,
+      uid,
+      gid,
+      gecos,
+    }) => {
+      let entry = import::add_row(
+        db,
+        &Row {
+          username: username.clone(),
+          home: home_dir.clone(),
+          shell: shell.clone(),
+          uid: *uid,
+          gid: gid.map(|gid| gid.to_string()),
+          gecos: gecos.clone(),
+          ..Row::default()
+        },
+        config,
+      )?;
+      let message = format!("User {} created with UID {}", username, entry.uid);
+      follow_ups.push(FollowUp::CreateHome(entry));
+      Ok(message)
+    }
+    Domains::User(UserCommands::Remove {
+      username,
+      remove_home,
+    }) => {
+      let entry = user::remove_account(db, username)
+        .ok_or_else(|| format!("User {} does not exist", username))?;
+      if *remove_home {
+        follow_ups.push(FollowUp::RemoveHome(entry));
+      }
+      Ok(format!("User {} removed", username))
+    }
+    Domains::User(UserCommands::Modify {
+      username,
+      home_dir,
+      shell,
+      uid,
+      gid,
+      gecos,
+    }) => {
+      let mut entry = find_user(db, username)?;
+      let mut changed = Vec::new();
+      if let Some(home) = home_dir {
+        check_path(home)?;
+        entry.home = home.clone();
+        changed.push("home directory");
+      }
+      if let Some(shell) = shell {
+        check_path(shell)?;
+        entry.shell = shell.clone();
+        changed.push("shell");
+      }
+      if let Some(uid) = *uid {
+        if let Some(owner) = db
+          .users()
+          .into_iter()
+          .find(|u| u.uid == uid && u.name != *username)
+        {
+          return Err(format!("UID {} is taken by user {}", uid, owner.name));
+        }
+        entry.uid = uid;
+        changed.push("UID");
+      }
+      if let Some(gid) = *gid {
+        if !db.groups().iter().any(|g| g.gid == gid) {
+          return Err(format!("No group has GID {}", gid));
+        }
+        entry.gid = gid;
+        changed.push("primary group");
+      }
+      if let Some(gecos) = gecos {
+        if !db::is_valid_field(gecos) {
+          return Err(String::from(
+            "GECOS field cannot contain ':' or control characters",
+          ));
+        }
+        entry.gecos = gecos.clone();
+        changed.push("GECOS");
+      }
+      if changed.is_empty() {
+        return Err(format!("Nothing to change for user {}", username));
+      }
+      db.set_user(&entry);
+      Ok(format!("User {} {} changed", username, changed.join(", ")))
+    }
+    Domains::User(UserCommands::Lock { username }) => {
+      let mut entry = db
+        .shadow_entry(username)
+        .ok_or_else(|| format!("User {} has no shadow entry", username))?;
+      if !entry.password.starts_with('!') {
+        entry.password.insert(0, '!');
+        db.set_shadow_entry(&entry);
+      }
+      Ok(format!("User {} locked", username))
+    }
+    Domains::User(UserCommands::Unlock { username }) => {
+      let mut entry = db
+        .shadow_entry(username)
+        .ok_or_else(|| format!("User {} has no shadow entry", username))?;
+      let password = entry.password.trim_start_matches('!');
+      if password.is_empty() || password == "*" {
+        return Err(format!(
+          "User {} has no password; unlocking would leave it without one",
+          username
+        ));
+      }
+      entry.password = password.to_string();
+      db.set_shadow_entry(&entry);
+      Ok(format!("User {} unlocked", username))
+    }
+    Domains::Group(GroupCommands::New { groupname, gid }) => {
+      if !db::is_valid_name(groupname) {
+        return Err(format!("Invalid group name '{}'", groupname));
+      }
+      if db.group(groupname).is_some() {
+        return Err(format!("Group {} already exists", groupname));
+      }
+      let gid = match gid {
+        Some(gid) => match db.groups().into_iter().find(|g| g.gid == *gid) {
+          Some(owner) => return Err(format!("GID {} is taken by group {}", gid, owner.name)),
+          None => *gid,
+        },
+        None => db
+          .next_free_id(config.default.uid_min, config.default.uid_max)
+          .ok_or_else(|| format!("No free GID available for group {}", groupname))?,
+      };
+      db.add_group(
+        &GroupEntry {
+          name: groupname.clone(),
+          password: String::from("x"),
+          gid,
+          members: Vec::new(),
+        },
+        &GshadowEntry::locked(groupname),
+      );
+      Ok(format!("Group {} created with GID {}", groupname, gid))
+    }
+    Domains::Group(GroupCommands::Remove { groupname }) => {
+      let group = find_group(db, groupname)?;
+      if let Some(user) = db.users().into_iter().find(|u| u.gid == group.gid) {
+        return Err(format!(
+          "Group {} is the primary group of user {}",
+          groupname, user.name
+        ));
+      }
+      db.remove_group(groupname);
+      Ok(format!("Group {} removed", groupname))
+    }
+    Domains::Group(GroupCommands::Modify { groupname, gid }) => {
+      let mut group = find_group(db, groupname)?;
+      let Some(gid) = *gid else {
+        return Err(format!("Nothing to change for group {}", groupname));
+      };
+      if let Some(owner) = db
+        .groups()
+        .into_iter()
+        .find(|g| g.gid == gid && g.name != *groupname)
+      {
+        return Err(format!("GID {} is taken by group {}", gid, owner.name));
+      }
+      // Users keep this group as their primary group, as with groupmod
+      for mut member in db.users().into_iter().filter(|u| u.gid == group.gid) {
+        member.gid = gid;
+        db.set_user(&member);
+      }
+      group.gid = gid;
+      db.set_group(&group);
+      Ok(format!("Group {} GID changed to {}", groupname, gid))
+    }
+    Domains::Group(GroupCommands::AddUser {
+      groupname,
+      username,
+    }) => {
+      find_group(db, groupname)?;
+      find_user(db, username)?;
+      db.add_group_member(groupname, username);
+      Ok(format!("User {} added to group {}", username, groupname))
+    }
+    Domains::Group(GroupCommands::RemoveUser {
+      groupname,
+      username,
+    }) => {
+      find_group(db, groupname)?;
+      if !db.remove_group_member(groupname, username) {
+        return Err(format!(
+          "User {} is not a member of group {}",
+          username, groupname
+        ));
+      }
+      Ok(format!(
+        "User {} removed from group {}",
+        username, groupname
+      ))
+    }
+    _ => Err(String::from(
+      "Not supported in a batch: only user and group commands that change accounts can be batched",
+    )),
+  }
+}
+
+fn read_script(path: Option<&Path>) -> Result<String, String> {
+  match path {
+    Some(path) if path != Path::new("-") => {
+      fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+    }
+    _ => {
+      let mut content = String::new();
+      std::io::stdin()
+        .read_to_string(&mut content)
+        .map_err(|e| format!("Failed to read standard input: {}", e))?;
+      Ok(content)
+    }
+  }
+}
+
+/// Runs a script of account changes against one locked, in-memory copy of
+/// the account databases and commits it only if every step succeeds.
+/// Home directories are created or removed after the commit.
+pub(crate) fn run(path: Option<&PathBuf>, dry_run: bool) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  let result = read_script(path.map(PathBuf::as_path))
+    .and_then(|content| parse_steps(&content))
+    .and_then(|steps| Ok((steps, Config::load()?, Database::open()?)));
+  let (steps, config, mut db) = match result {
+    Ok(result) => result,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  let mut messages = Vec::new();
+  let mut follow_ups = Vec::new();
+  for (i, step) in steps.iter().enumerate() {
+    match run_step(&mut db, &config, step, &mut follow_ups) {
+      Ok(message) => messages.push(message),
+      Err(e) => {
+        eprintln!(
+          "Error: Step {} ({}: {}) failed: {}",
+          i + 1,
+          step.at,
+          step.text,
+          e
+        );
+        eprintln!("No changes were made");
+        return false;
+      }
+    }
+  }
+
+  if dry_run {
+    for message in &messages {
+      println!("{}", message);
+    }
+    println!(
+      "Dry run: all {} steps would succeed; no changes were made",
+      steps.len()
+    );
+    return true;
+  }
+
+  if let Err(e) = db.commit() {
+    eprintln!("Error: {}", e);
+    return false;
+  }
+  drop(db);
+  for message in &messages {
+    println!("{}", message);
+  }
+
+  let mut success = true;
+  let skel = crate::root::path(&config.default.skel);
+  for follow_up in follow_ups {
+    let result = match &follow_up {
+      FollowUp::CreateHome(entry) => {
+        let home = crate::root::path(&entry.home);
+        if home.exists() {
+          Ok(())
+        } else {
+          user::create_home(&home, entry.uid, entry.gid, &skel)
+        }
+      }
+      FollowUp::RemoveHome(entry) => remove_home(entry),
+    };
+    if let Err(e) = result {
+      eprintln!("Error: {}", e);
+      success = false;
+    }
+  }
+  println!("Batch complete: {} steps applied", steps.len());
+  success
+}
+
+/// Removes a deleted user's home. As with userdel, a home that is not a
+/// directory owned by the user, or is `/`, is left alone with a warning.
+fn remove_home(entry: &PasswdEntry) -> Result<(), String> {
+  use std::os::unix::fs::MetadataExt;
+
+  let home = crate::root::path(&entry.home);
+  let metadata = match fs::symlink_metadata(&home) {
+    Ok(metadata) => metadata,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+    Err(e) => return Err(format!("Failed to remove {}: {}", entry.home, e)),
+  };
+  if !metadata.is_dir() || metadata.uid() != entry.uid || Path::new(&entry.home).parent().is_none()
+  {
+    eprintln!(
+      "Warning: Not removing {}: it is not a directory owned by {}",
+      entry.home, entry.name
+    );
+    return Ok(());
+  }
+  fs::remove_dir_all(&home).map_err(|e| format!("Failed to remove {}: {}", entry.home, e))
+}
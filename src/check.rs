@@ -0,0 +1,575 @@
+use crate::config::Config;
+use crate::db::{self, Database, DbFile, GroupEntry, GshadowEntry, PasswdEntry, ShadowEntry};
+use crate::user;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{BufRead, Write};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Severity {
+  /// The databases contradict themselves
+  Error,
+  /// Something is off but the databases are usable as they are
+  Warning,
+}
+
+/// A repair that cannot lose information.
+enum Fix {
+  AddShadow(String),
+  RemoveShadow(String),
+  AddGshadow(String),
+  RemoveGshadow(String),
+  SyncGshadowMembers(String),
+  RemoveMember { group: String, user: String },
+  CreateHome(String),
+  SortPasswd,
+  SortGroup,
+}
+
+impl Fix {
+  fn describe(&self) -> String {
+    match self {
+      Fix::AddShadow(name) => format!("add a locked shadow entry for {}", name),
+      Fix::RemoveShadow(name) => format!("remove the shadow entry for {}", name),
+      Fix::AddGshadow(name) => format!("add a gshadow entry for group {}", name),
+      Fix::RemoveGshadow(name) => format!("remove the gshadow entry for {}", name),
+      Fix::SyncGshadowMembers(name) => {
+        format!("copy the members of group {} to gshadow", name)
+      }
+      Fix::RemoveMember { group, user } => {
+        format!("remove {} from the members of group {}", user, group)
+      }
+      Fix::CreateHome(name) => format!("create the home directory of {}", name),
+      Fix::SortPasswd => String::from("sort passwd by UID and shadow to match"),
+      Fix::SortGroup => String::from("sort group by GID and gshadow to match"),
+    }
+  }
+}
+
+struct Issue {
+  severity: Severity,
+  file: String,
+  /// Line number, for problems with one entry
+  line: Option<usize>,
+  message: String,
+  fix: Option<Fix>,
+}
+
+impl Issue {
+  fn print(&self) {
+    let location = match self.line {
+      Some(line) => format!("{} line {}", self.file, line),
+      None => self.file.clone(),
+    };
+    let mut tags = Vec::new();
+    if self.severity == Severity::Warning {
+      tags.push("warning");
+    }
+    if self.fix.is_some() {
+      tags.push("fixable");
+    }
+    if tags.is_empty() {
+      println!("{}: {}", location, self.message);
+    } else {
+      println!("{}: {} ({})", location, self.message, tags.join(", "));
+    }
+  }
+}
+
+/// Collects issues for one file, recording where each was found.
+struct Findings<'a> {
+  issues: &'a mut Vec<Issue>,
+  file: String,
+}
+
+impl Findings<'_> {
+  fn add(&mut self, severity: Severity, line: Option<usize>, message: String, fix: Option<Fix>) {
+    self.issues.push(Issue {
+      severity,
+      file: self.file.clone(),
+      line,
+      message,
+      fix,
+    });
+  }
+
+  fn error(&mut self, line: usize, message: String) {
+    self.add(Severity::Error, Some(line), message, None);
+  }
+}
+
+fn is_executable(path: &str) -> bool {
+  fs::metadata(crate::root::path(path))
+    .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+/// Entries that parse, with their line numbers, reporting the lines that
+/// do not.
+fn parse_lines<T>(
+  findings: &mut Findings,
+  lines: Vec<&str>,
+  parse: impl Fn(&str) -> Option<T>,
+  expected: &str,
+) -> Vec<(usize, T)> {
+  let mut entries = Vec::new();
+  for (i, line) in lines.into_iter().enumerate() {
+    match parse(line) {
+      Some(entry) => entries.push((i + 1, entry)),
+      None => findings.error(i + 1, format!("Malformed entry: expected {}", expected)),
+    }
+  }
+  entries
+}
+
+/// Reports names listed more than once, returning the line each name was
+/// first seen on.
+fn check_duplicates<'a>(
+  findings: &mut Findings,
+  entries: impl Iterator<Item = (usize, &'a str)>,
+  kind: &str,
+) -> HashMap<&'a str, usize> {
+  let mut seen = HashMap::new();
+  for (line, name) in entries {
+    if let Some(first) = seen.get(name) {
+      findings.error(
+        line,
+        format!("Duplicate {} {} (first on line {})", kind, name, first),
+      );
+    } else {
+      seen.insert(name, line);
+    }
+  }
+  seen
+}
+
+fn check_passwd(db: &Database, config: &Config, issues: &mut Vec<Issue>) {
+  let mut findings = Findings {
+    issues,
+    file: db.file(DbFile::Passwd).path().display().to_string(),
+  };
+  let entries = parse_lines(
+    &mut findings,
+    db.file(DbFile::Passwd).lines().collect(),
+    PasswdEntry::parse,
+    "7 fields with a numeric UID and GID",
+  );
+  check_duplicates(
+    &mut findings,
+    entries.iter().map(|(line, e)| (*line, e.name.as_str())),
+    "user",
+  );
+
+  let groups = db.groups();
+  let range = db::id_range(config.default.uid_min, config.default.uid_max);
+  let mut uids: HashMap<u32, &str> = HashMap::new();
+  for (line, entry) in &entries {
+    let line = *line;
+    if !db::is_valid_name(&entry.name) {
+      findings.add(
+        Severity::Warning,
+        Some(line),
+        format!("Invalid user name '{}'", entry.name),
+        None,
+      );
+    }
+    match uids.get(&entry.uid) {
+      Some(other) if *other != entry.name => findings.error(
+        line,
+        format!(
+          "User {} has UID {}, as does {}",
+          entry.name, entry.uid, other
+        ),
+      ),
+      _ => {
+        uids.insert(entry.uid, &entry.name);
+      }
+    }
+    if !groups.iter().any(|g| g.gid == entry.gid) {
+      findings.error(
+        line,
+        format!(
+          "Primary GID {} of user {} has no group",
+          entry.gid, entry.name
+        ),
+      );
+    }
+    if entry.password == "x"
+      && db.file(DbFile::Shadow).is_present()
+      && db.shadow_entry(&entry.name).is_none()
+    {
+      findings.add(
+        Severity::Error,
+        Some(line),
+        format!("User {} has no shadow entry", entry.name),
+        Some(Fix::AddShadow(entry.name.clone())),
+      );
+    }
+    if !entry.shell.is_empty() && !is_executable(&entry.shell) {
+      findings.add(
+        Severity::Warning,
+        Some(line),
+        format!(
+          "Shell {} of user {} does not exist or is not executable",
+          entry.shell, entry.name
+        ),
+        None,
+      );
+    }
+    // System accounts commonly have homes that do not exist, like
+    // /nonexistent, so only regular users are checked
+    if range.contains(&entry.uid) {
+      match fs::metadata(crate::root::path(&entry.home)) {
+        Err(_) => findings.add(
+          Severity::Warning,
+          Some(line),
+          format!(
+            "Home directory {} of user {} does not exist",
+            entry.home, entry.name
+          ),
+          Some(Fix::CreateHome(entry.name.clone())),
+        ),
+        // Reported only: shared homes and homes deliberately owned by
+        // someone else are legitimate, as pwck also assumes
+        Ok(meta) if meta.uid() != entry.uid => findings.add(
+          Severity::Warning,
+          Some(line),
+          format!(
+            "Home directory {} of user {} is owned by UID {}",
+            entry.home,
+            entry.name,
+            meta.uid()
+          ),
+          None,
+        ),
+        Ok(_) => {}
+      }
+    }
+  }
+
+  if entries.windows(2).any(|w| w[0].1.uid > w[1].1.uid) {
+    findings.add(
+      Severity::Warning,
+      None,
+      String::from("Entries are not sorted by UID"),
+      Some(Fix::SortPasswd),
+    );
+  }
+}
+
+fn check_shadow(db: &Database, issues: &mut Vec<Issue>) {
+  if !db.file(DbFile::Shadow).is_present() {
+    return;
+  }
+  let mut findings = Findings {
+    issues,
+    file: db.file(DbFile::Shadow).path().display().to_string(),
+  };
+  let entries = parse_lines(
+    &mut findings,
+    db.file(DbFile::Shadow).lines().collect(),
+    ShadowEntry::parse,
+    "9 fields",
+  );
+  let seen = check_duplicates(
+    &mut findings,
+    entries.iter().map(|(line, e)| (*line, e.name.as_str())),
+    "user",
+  );
+  for (line, entry) in &entries {
+    if seen.get(entry.name.as_str()) == Some(line) && !db.file(DbFile::Passwd).contains(&entry.name)
+    {
+      findings.add(
+        Severity::Error,
+        Some(*line),
+        format!("User {} is not in passwd", entry.name),
+        Some(Fix::RemoveShadow(entry.name.clone())),
+      );
+    }
+  }
+}
+
+fn check_group(db: &Database, issues: &mut Vec<Issue>) {
+  let mut findings = Findings {
+    issues,
+    file: db.file(DbFile::Group).path().display().to_string(),
+  };
+  let entries = parse_lines(
+    &mut findings,
+    db.file(DbFile::Group).lines().collect(),
+    GroupEntry::parse,
+    "4 fields with a numeric GID",
+  );
+  check_duplicates(
+    &mut findings,
+    entries.iter().map(|(line, e)| (*line, e.name.as_str())),
+    "group",
+  );
+
+  let mut gids: HashMap<u32, &str> = HashMap::new();
+  for (line, entry) in &entries {
+    let line = *line;
+    if !db::is_valid_name(&entry.name) {
+      findings.add(
+        Severity::Warning,
+        Some(line),
+        format!("Invalid group name '{}'", entry.name),
+        None,
+      );
+    }
+    match gids.get(&entry.gid) {
+      Some(other) if *other != entry.name => findings.error(
+        line,
+        format!(
+          "Group {} has GID {}, as does {}",
+          entry.name, entry.gid, other
+        ),
+      ),
+      _ => {
+        gids.insert(entry.gid, &entry.name);
+      }
+    }
+    for member in &entry.members {
+      if !db.file(DbFile::Passwd).contains(member) {
+        findings.add(
+          Severity::Error,
+          Some(line),
+          format!("Member {} of group {} does not exist", member, entry.name),
+          Some(Fix::RemoveMember {
+            group: entry.name.clone(),
+            user: member.clone(),
+          }),
+        );
+      }
+    }
+    if db.file(DbFile::Gshadow).is_present() && !db.file(DbFile::Gshadow).contains(&entry.name) {
+      findings.add(
+        Severity::Error,
+        Some(line),
+        format!("Group {} has no gshadow entry", entry.name),
+        Some(Fix::AddGshadow(entry.name.clone())),
+      );
+    }
+  }
+
+  if entries.windows(2).any(|w| w[0].1.gid > w[1].1.gid) {
+    findings.add(
+      Severity::Warning,
+      None,
+      String::from("Entries are not sorted by GID"),
+      Some(Fix::SortGroup),
+    );
+  }
+}
+
+fn check_gshadow(db: &Database, issues: &mut Vec<Issue>) {
+  if !db.file(DbFile::Gshadow).is_present() {
+    return;
+  }
+  let mut findings = Findings {
+    issues,
+    file: db.file(DbFile::Gshadow).path().display().to_string(),
+  };
+  let entries = parse_lines(
+    &mut findings,
+    db.file(DbFile::Gshadow).lines().collect(),
+    GshadowEntry::parse,
+    "4 fields",
+  );
+  check_duplicates(
+    &mut findings,
+    entries.iter().map(|(line, e)| (*line, e.name.as_str())),
+    "group",
+  );
+  for (line, entry) in &entries {
+    match db.group(&entry.name) {
+      None => findings.add(
+        Severity::Error,
+        Some(*line),
+        format!("Group {} is not in group", entry.name),
+        Some(Fix::RemoveGshadow(entry.name.clone())),
+      ),
+      Some(group) if group.members != entry.members => findings.add(
+        Severity::Error,
+        Some(*line),
+        format!("Members of group {} differ from those in group", entry.name),
+        Some(Fix::SyncGshadowMembers(entry.name.clone())),
+      ),
+      Some(_) => {}
+    }
+  }
+}
+
+fn find_issues(db: &Database, config: &Config) -> Vec<Issue> {
+  let mut issues = Vec::new();
+  check_passwd(db, config, &mut issues);
+  check_shadow(db, &mut issues);
+  check_group(db, &mut issues);
+  check_gshadow(db, &mut issues);
+  issues
+}
+
+/// Makes a repair in memory, or on the filesystem for home directories.
+fn apply_fix(db: &mut Database, config: &Config, fix: &Fix) -> Result<(), String> {
+  match fix {
+    Fix::AddShadow(name) => db
+      .file_mut(DbFile::Shadow)
+      .push(ShadowEntry::locked(name).to_line()),
+    Fix::RemoveShadow(name) => {
+      db.file_mut(DbFile::Shadow).remove(name);
+    }
+    Fix::AddGshadow(name) => {
+      let group = db
+        .group(name)
+        .ok_or_else(|| format!("Group {} does not exist", name))?;
+      db.file_mut(DbFile::Gshadow).push(
+        GshadowEntry {
+          members: group.members,
+          ..GshadowEntry::locked(name)
+        }
+        .to_line(),
+      );
+    }
+    Fix::RemoveGshadow(name) => {
+      db.file_mut(DbFile::Gshadow).remove(name);
+    }
+    Fix::SyncGshadowMembers(name) => {
+      let group = db
+        .group(name)
+        .ok_or_else(|| format!("Group {} does not exist", name))?;
+      if let Some(mut entry) = db
+        .file(DbFile::Gshadow)
+        .find(name)
+        .and_then(GshadowEntry::parse)
+      {
+        entry.members = group.members;
+        db.file_mut(DbFile::Gshadow).replace(name, entry.to_line());
+      }
+    }
+    Fix::RemoveMember { group, user } => {
+      db.remove_group_member(group, user);
+    }
+    Fix::CreateHome(name) => {
+      let entry = db
+        .user(name)
+        .ok_or_else(|| format!("User {} does not exist", name))?;
+      user::create_home(
+        &crate::root::path(&entry.home),
+        entry.uid,
+        entry.gid,
+        &crate::root::path(&config.default.skel),
+      )?;
+    }
+    Fix::SortPasswd => {
+      db.file_mut(DbFile::Passwd)
+        .sort_by_key(|line| PasswdEntry::parse(line).map_or(u32::MAX, |e| e.uid));
+      let order: Vec<String> = db.users().into_iter().map(|u| u.name).collect();
+      db.file_mut(DbFile::Shadow).sort_by_key(|line| {
+        let name = line.split(':').next().unwrap_or_default();
+        order.iter().position(|n| n == name).unwrap_or(usize::MAX)
+      });
+    }
+    Fix::SortGroup => {
+      db.file_mut(DbFile::Group)
+        .sort_by_key(|line| GroupEntry::parse(line).map_or(u32::MAX, |e| e.gid));
+      let order: Vec<String> = db.groups().into_iter().map(|g| g.name).collect();
+      db.file_mut(DbFile::Gshadow).sort_by_key(|line| {
+        let name = line.split(':').next().unwrap_or_default();
+        order.iter().position(|n| n == name).unwrap_or(usize::MAX)
+      });
+    }
+  }
+  Ok(())
+}
+
+fn confirm(question: &str) -> bool {
+  print!("{} [y/N] ", question);
+  let _ = std::io::stdout().flush();
+  let mut answer = String::new();
+  if std::io::stdin().lock().read_line(&mut answer).is_err() {
+    return false;
+  }
+  matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+fn plural(count: usize, word: &str) -> String {
+  format!("{} {}{}", count, word, if count == 1 { "" } else { "s" })
+}
+
+/// Checks passwd, shadow, group and gshadow for inconsistencies, like pwck
+/// and grpck. With `fix`, repairs the problems that can be repaired without
+/// losing anything, asking about each one unless `yes` is set. Fails when
+/// errors remain.
+pub(crate) fn check(fix: bool, yes: bool) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+  let opened = Config::load().and_then(|config| {
+    let db = if fix {
+      Database::open()?
+    } else {
+      Database::load()?
+    };
+    Ok((config, db))
+  });
+  let (config, mut db) = match opened {
+    Ok(opened) => opened,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  let issues = find_issues(&db, &config);
+  if issues.is_empty() {
+    println!("No problems found");
+    return true;
+  }
+  for issue in &issues {
+    issue.print();
+  }
+  let errors = issues
+    .iter()
+    .filter(|i| i.severity == Severity::Error)
+    .count();
+  let warnings = issues.len() - errors;
+  let fixable = issues.iter().filter(|i| i.fix.is_some()).count();
+  println!(
+    "Found {} and {}; {} can be repaired with --fix",
+    plural(errors, "error"),
+    plural(warnings, "warning"),
+    fixable
+  );
+  if !fix || fixable == 0 {
+    return errors == 0;
+  }
+
+  let mut remaining_errors = errors;
+  let mut fixed = 0;
+  for issue in &issues {
+    let Some(repair) = &issue.fix else {
+      continue;
+    };
+    let description = repair.describe();
+    if !yes && !confirm(&format!("Fix: {}?", description)) {
+      continue;
+    }
+    match apply_fix(&mut db, &config, repair) {
+      Ok(()) => {
+        println!("Fixed: {}", description);
+        fixed += 1;
+        if issue.severity == Severity::Error {
+          remaining_errors -= 1;
+        }
+      }
+      Err(e) => eprintln!("Error: {}", e),
+    }
+  }
+  if let Err(e) = db.commit() {
+    eprintln!("Error: {}", e);
+    return false;
+  }
+  println!("Repaired {} of {}", fixed, plural(issues.len(), "problem"));
+  remaining_errors == 0
+}
@@ -1,10 +1,24 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
 
 #[derive(Parser)]
 #[command(name = "userkit")]
 #[command(about = "A comprehensive CLI tool for user management across different operating systems", long_about = None)]
 #[command(version)]
 pub struct Cli {
+  /// Read configuration from this file on top of the system and user files
+  #[arg(long, global = true, value_name = "FILE")]
+  pub config: Option<PathBuf>,
+
+  /// Apply this configuration profile instead of `default.profile`
+  #[arg(long, global = true, value_name = "NAME")]
+  pub profile: Option<String>,
+
+  /// Work on the account databases and userkit files under this directory
+  #[arg(long, global = true, value_name = "DIR")]
+  pub root: Option<PathBuf>,
+
   #[command(subcommand)]
   pub domain: Domains,
 }
@@ -34,6 +48,67 @@ pub enum Domains {
   /// Tool configuration
   #[command(subcommand)]
   Config(ConfigCommands),
+
+  /// Backups of the account files taken before each change
+  #[command(subcommand)]
+  Backup(BackupCommands),
+
+  /// Put the account files, roles and sudo rules back as they were in a backup
+  Restore {
+    /// Backup ID, as shown by backup list
+    id: String,
+  },
+
+  /// Bring accounts, roles and sudo rules in line with a manifest
+  Apply {
+    /// Manifest file: TOML, or YAML when named *.yaml or *.yml
+    manifest: PathBuf,
+    /// Also remove managed accounts, roles and rules missing from the manifest
+    #[arg(long)]
+    prune: bool,
+  },
+
+  /// Show what apply would change without changing anything
+  Plan {
+    /// Manifest file: TOML, or YAML when named *.yaml or *.yml
+    manifest: PathBuf,
+    /// Include removals of managed entries missing from the manifest
+    #[arg(long)]
+    prune: bool,
+  },
+
+  /// Run user and group commands from a script as one transaction
+  Batch {
+    /// Script with one command per line or a JSON array; standard input when unset
+    file: Option<PathBuf>,
+    /// Check that every step would succeed without writing anything
+    #[arg(long)]
+    dry_run: bool,
+  },
+
+  /// Check passwd, shadow, group and gshadow for inconsistencies
+  Check {
+    /// Repair the problems that can be repaired safely, asking about each
+    #[arg(long)]
+    fix: bool,
+    /// Repair without asking
+    #[arg(long, short, requires = "fix")]
+    yes: bool,
+  },
+
+  /// Write the current accounts, roles and sudo rules as a manifest
+  Export {
+    /// Write to this file instead of standard output; YAML when named *.yaml
+    /// or *.yml, TOML otherwise
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Include system users and groups
+    #[arg(long)]
+    system: bool,
+    /// Include password hashes
+    #[arg(long)]
+    passwords: bool,
+  },
 }
 
 #[derive(Subcommand)]
@@ -59,6 +134,21 @@ pub enum UserCommands {
     gecos: Option<String>,
   },
 
+  /// Create users in bulk from a CSV or newusers-format file
+  Import {
+    /// Input file, or - for standard input
+    file: PathBuf,
+    /// Input format; detected from the file extension (.csv) when unset
+    #[arg(long, value_enum)]
+    input_format: Option<ImportFormat>,
+    /// Skip rows that fail instead of aborting the whole import
+    #[arg(long)]
+    continue_on_error: bool,
+    /// Output format of the per-row report
+    #[arg(long, short)]
+    format: Option<ListFormat>,
+  },
+
   /// Delete a user
   Remove {
     /// Username to remove
@@ -92,8 +182,8 @@ pub enum UserCommands {
   /// List users with filtering options
   List {
     /// Output format
-    #[arg(long, short, default_value = "table")]
-    format: ListFormat,
+    #[arg(long, short)]
+    format: Option<ListFormat>,
     /// Filter by UID range
     #[arg(long)]
     uid_range: Option<String>,
@@ -126,11 +216,23 @@ pub enum UserCommands {
     username: String,
   },
 
+  /// List a user's roles and the permissions they give
+  Roles {
+    /// Username to show roles for
+    username: String,
+    /// Output format
+    #[arg(long, short)]
+    format: Option<ListFormat>,
+  },
+
   /// Start a shell session
   Shell {
     /// Username to switch to
     #[arg(long)]
     username: Option<String>,
+    /// Create a temporary guest account for the session
+    #[arg(long, conflicts_with = "username")]
+    temp: bool,
     /// Command to execute
     command: Option<String>,
   },
@@ -165,8 +267,8 @@ pub enum GroupCommands {
   /// List groups with filtering
   List {
     /// Output format
-    #[arg(long, short, default_value = "table")]
-    format: ListFormat,
+    #[arg(long, short)]
+    format: Option<ListFormat>,
   },
 
   /// List members of a group
@@ -198,17 +300,33 @@ pub enum PermCommands {
   Set {
     /// Target file or directory
     target: String,
-    /// Permissions to set (e.g., "755")
-    permissions: String,
+    /// Permissions to set, octal (e.g., "755") or symbolic (e.g., "u+rwx,g-w,o=")
+    #[arg(required_unless_present_any = ["owner", "files_only", "dirs_only"])]
+    permissions: Option<String>,
     /// Apply recursively
     #[arg(long, short)]
     recursive: bool,
+    /// Follow symbolic links when recursing
+    #[arg(long, short = 'L', requires = "recursive")]
+    follow_symlinks: bool,
+    /// Change the owner as well (USER, USER:GROUP, :GROUP or USER:)
+    #[arg(long)]
+    owner: Option<String>,
+    /// Mode for files only, overriding PERMISSIONS for them
+    #[arg(long, value_name = "MODE")]
+    files_only: Option<String>,
+    /// Mode for directories only, overriding PERMISSIONS for them
+    #[arg(long, value_name = "MODE")]
+    dirs_only: Option<String>,
   },
 
   /// Get permissions for a target
   Get {
     /// Target file or directory
     target: String,
+    /// Output format
+    #[arg(long, short)]
+    format: Option<ListFormat>,
   },
 
   /// Check if user has permissions
@@ -217,15 +335,117 @@ pub enum PermCommands {
     username: String,
     /// Target file or directory
     target: String,
+    /// Check as if the user were also a member of this group
+    #[arg(long = "as-group", value_name = "GROUP")]
+    as_groups: Vec<String>,
   },
 
+  /// Manage POSIX ACLs
+  #[command(subcommand)]
+  Acl(AclCommands),
+
   /// Manage sudo access
+  #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
   Sudo {
+    #[command(subcommand)]
+    command: Option<SudoCommands>,
     /// Username to manage sudo access for
-    username: String,
+    #[arg(required = true)]
+    username: Option<String>,
     /// Enable or disable sudo access
-    #[arg(value_enum)]
-    action: SudoAction,
+    #[arg(value_enum, required = true)]
+    action: Option<SudoAction>,
+  },
+}
+
+#[derive(Subcommand)]
+pub enum SudoCommands {
+  /// Show whether and how a user has sudo rights
+  Status {
+    /// Username to check
+    username: String,
+  },
+
+  /// Show what every user, or one user, may run through sudo
+  Audit {
+    /// Only audit this user
+    username: Option<String>,
+    /// Output format
+    #[arg(long, short)]
+    format: Option<ListFormat>,
+  },
+
+  /// Grant a user or %group specific commands
+  Grant {
+    /// Username, or %group for a group
+    subject: String,
+    /// Command path with optional arguments, or a command alias from the config
+    #[arg(long = "command", value_name = "COMMAND", required = true)]
+    commands: Vec<String>,
+    /// User (and :group) to run the commands as; defaults to root
+    #[arg(long)]
+    runas: Option<String>,
+    /// Run without asking for a password
+    #[arg(long)]
+    nopasswd: bool,
+    /// Host or host pattern the rule applies on; defaults to all hosts
+    #[arg(long = "host", value_name = "HOST")]
+    hosts: Vec<String>,
+  },
+
+  /// List sudo rules managed by userkit
+  List {
+    /// Output format
+    #[arg(long, short)]
+    format: Option<ListFormat>,
+  },
+
+  /// Revoke a sudo rule by its ID
+  Revoke {
+    /// Rule ID as shown by `perm sudo list`
+    id: u32,
+  },
+}
+
+#[derive(Subcommand)]
+pub enum AclCommands {
+  /// Grant users or groups access (e.g., "u:alice:rwx", "g:devs:rX")
+  Add {
+    /// Target file or directory
+    target: String,
+    /// ACL entries to add or replace
+    #[arg(required = true)]
+    entries: Vec<String>,
+    /// Change the default ACL inherited by new files in a directory
+    #[arg(long, short)]
+    default: bool,
+    /// Apply recursively
+    #[arg(long, short)]
+    recursive: bool,
+  },
+
+  /// Remove user or group entries (e.g., "u:alice", "g:devs")
+  Remove {
+    /// Target file or directory
+    target: String,
+    /// ACL entries to remove
+    #[arg(required = true)]
+    entries: Vec<String>,
+    /// Change the default ACL inherited by new files in a directory
+    #[arg(long, short)]
+    default: bool,
+    /// Apply recursively
+    #[arg(long, short)]
+    recursive: bool,
+  },
+
+  /// List ACL entries of a target
+  List {
+    /// Target file or directory
+    target: String,
+    /// Output format
+    #[arg(long, short)]
+    format: Option<ListFormat>,
   },
 }
 
@@ -238,12 +458,18 @@ pub enum RoleCommands {
     /// Description of the role
     #[arg(long)]
     description: Option<String>,
+    /// Role to inherit permissions from; may be repeated or comma separated
+    #[arg(long, value_name = "ROLE", value_delimiter = ',')]
+    inherits: Vec<String>,
   },
 
   /// Delete a role
   Delete {
     /// Role name to delete
     rolename: String,
+    /// Revoke the role from its users first
+    #[arg(long)]
+    force: bool,
   },
 
   /// Assign role to user
@@ -252,6 +478,12 @@ pub enum RoleCommands {
     rolename: String,
     /// Username to assign role to
     username: String,
+    /// Revoke the role again after this long (e.g. "4h", "2d")
+    #[arg(long = "for", value_name = "DURATION", conflicts_with = "until")]
+    duration: Option<String>,
+    /// Revoke the role again at this time (e.g. "2026-11-01T17:00")
+    #[arg(long)]
+    until: Option<String>,
   },
 
   /// Revoke role from user
@@ -265,23 +497,70 @@ pub enum RoleCommands {
   /// List available roles
   List {
     /// Output format
-    #[arg(long, short, default_value = "table")]
-    format: ListFormat,
+    #[arg(long, short)]
+    format: Option<ListFormat>,
   },
 
   /// Show role details and permissions
   Info {
     /// Role name
     rolename: String,
+    /// Show inherited permissions too, with the role each comes from
+    #[arg(long)]
+    effective: bool,
+  },
+
+  /// Show the role inheritance hierarchy
+  Tree,
+
+  /// List users that get a permission through any role
+  WhoHas {
+    /// Permission, as added with add-perm
+    permission: String,
+    /// Output format
+    #[arg(long, short)]
+    format: Option<ListFormat>,
   },
 
-  /// Add permission to role
+  /// Compare the effective permissions of two roles
+  Diff {
+    /// First role
+    first: String,
+    /// Second role
+    second: String,
+    /// Output format
+    #[arg(long, short)]
+    format: Option<ListFormat>,
+  },
+
+  /// Add permission to role: group:NAME, sudo:COMMAND, path:PATH:PERMS,
+  /// shell:PATH, or a plain label
+  #[command(alias = "addperm")]
   AddPerm {
     /// Role name
     rolename: String,
     /// Permission to add
     permission: String,
   },
+
+  /// Remove permission from role
+  #[command(alias = "removeperm")]
+  RemovePerm {
+    /// Role name
+    rolename: String,
+    /// Permission to remove
+    permission: String,
+  },
+
+  /// Re-apply all roles to their users and report drift
+  Sync,
+
+  /// Revoke time-bound role assignments that have ended
+  Reap {
+    /// Show what would be revoked without revoking anything
+    #[arg(long)]
+    dry_run: bool,
+  },
 }
 
 #[derive(Subcommand)]
@@ -290,9 +569,12 @@ pub enum GuestCommands {
   Create {
     /// Guest account name (optional)
     name: Option<String>,
-    /// Expiration time in days
+    /// Expiration as a duration ("4h", "90m", "2d"; bare numbers are days)
+    /// or a local date and time ("2026-11-01", "2026-11-01T17:00")
     #[arg(long)]
-    expire: Option<u32>,
+    expire: Option<String>,
+    #[command(flatten)]
+    restrictions: GuestRestrictions,
   },
 
   /// Remove a guest account
@@ -311,17 +593,62 @@ pub enum GuestCommands {
   },
 
   /// Spawn a temporary shell with a one-off guest account
-  Shell,
+  Shell {
+    /// Mount a tmpfs as the guest's home directory
+    #[arg(long)]
+    tmpfs: bool,
+    #[command(flatten)]
+    restrictions: GuestRestrictions,
+    /// Command to execute
+    command: Option<String>,
+  },
 
   /// Set expiration for a guest account
   Expire {
     /// Guest account name
     name: String,
-    /// Expiration time in days
-    time: u32,
+    /// Expiration as a duration ("4h", "90m", "2d"; bare numbers are days)
+    /// or a local date and time ("2026-11-01", "2026-11-01T17:00")
+    time: String,
+  },
+
+  /// Remove guest accounts past their expiration
+  Reap {
+    /// How long to keep expired accounts before removing them (e.g. "1h", "2d")
+    #[arg(long)]
+    grace: Option<String>,
+    /// Show what would be removed without removing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Print example systemd service and timer units instead of reaping
+    #[arg(long)]
+    print_units: bool,
   },
 }
 
+#[derive(Args, Default)]
+pub struct GuestRestrictions {
+  /// Use a restricted shell limited to the configured command allowlist
+  #[arg(long)]
+  pub restricted_shell: bool,
+  /// Allow an additional command in the restricted shell (repeatable)
+  #[arg(
+    long = "allow-command",
+    value_name = "COMMAND",
+    requires = "restricted_shell"
+  )]
+  pub allow_commands: Vec<String>,
+  /// Limit disk usage of the home directory (e.g. "500M", "2G")
+  #[arg(long)]
+  pub quota: Option<String>,
+  /// Add the guest to a supplementary group (repeatable)
+  #[arg(long = "group", value_name = "GROUP")]
+  pub groups: Vec<String>,
+  /// Set a resource limit such as "nproc=100" or "nofile=256" (repeatable)
+  #[arg(long = "limit", value_name = "ITEM=VALUE")]
+  pub limits: Vec<String>,
+}
+
 #[derive(Subcommand)]
 pub enum ConfigCommands {
   /// Set configuration option
@@ -330,6 +657,9 @@ pub enum ConfigCommands {
     key: String,
     /// Configuration value
     value: String,
+    /// Write to your user configuration file instead of the system file
+    #[arg(long)]
+    user: bool,
   },
 
   /// Get configuration option
@@ -339,13 +669,47 @@ pub enum ConfigCommands {
   },
 
   /// List all configuration options
-  List,
+  List {
+    /// Output format
+    #[arg(long, short)]
+    format: Option<ListFormat>,
+    /// Show the file or environment variable each value comes from
+    #[arg(long)]
+    show_origin: bool,
+  },
 
   /// Reset configuration to defaults
-  Reset,
+  Reset {
+    /// Reset only this key
+    key: Option<String>,
+    /// Reset your user configuration file instead of the system file
+    #[arg(long)]
+    user: bool,
+  },
+
+  /// Inspect configuration profiles
+  #[command(subcommand)]
+  Profile(ProfileCommands),
 }
 
-#[derive(ValueEnum, Clone)]
+#[derive(Subcommand)]
+pub enum ProfileCommands {
+  /// List the profiles defined in the system configuration
+  List {
+    /// Output format
+    #[arg(long, short)]
+    format: Option<ListFormat>,
+  },
+
+  /// Show the settings a profile applies
+  Show {
+    /// Profile name
+    name: String,
+  },
+}
+
+#[derive(ValueEnum, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum ListFormat {
   /// Display as a table
   Table,
@@ -355,6 +719,14 @@ pub enum ListFormat {
   Csv,
 }
 
+#[derive(ValueEnum, Clone)]
+pub enum ImportFormat {
+  /// CSV with a header naming the columns
+  Csv,
+  /// name:password:uid:gid:gecos:dir:shell, as read by newusers(8)
+  Newusers,
+}
+
 #[derive(ValueEnum, Clone)]
 pub enum SudoAction {
   /// Enable sudo access
@@ -362,3 +734,19 @@ pub enum SudoAction {
   /// Disable sudo access
   Disable,
 }
+
+#[derive(Subcommand)]
+pub enum BackupCommands {
+  /// List backups, oldest first
+  List {
+    /// Output format
+    #[arg(long, short)]
+    format: Option<ListFormat>,
+  },
+
+  /// Show how the current files differ from a backup
+  Diff {
+    /// Backup ID, as shown by backup list
+    id: String,
+  },
+}
@@ -0,0 +1,1010 @@
+use crate::cli::ListFormat;
+use crate::{db, user};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tabled::settings::Remove;
+use tabled::settings::location::ByColumnName;
+use tabled::{Table, Tabled};
+
+pub(crate) const CONFIG_PATH: &str = "/etc/userkit/config.toml";
+
+/// Settings merged from the configuration layers. Missing keys fall back to
+/// their defaults.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub(crate) struct Config {
+  pub(crate) default: DefaultConfig,
+  pub(crate) password: PasswordConfig,
+  pub(crate) guest: GuestConfig,
+  pub(crate) sudo: SudoConfig,
+  pub(crate) backup: BackupConfig,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct DefaultConfig {
+  /// Login shell of new accounts
+  pub(crate) shell: String,
+  /// Directory new home directories are created in
+  pub(crate) home_base: String,
+  /// Skeleton directory copied into new home directories
+  pub(crate) skel: String,
+  /// Overrides UID_MIN from login.defs when allocating IDs
+  pub(crate) uid_min: Option<u32>,
+  /// Overrides UID_MAX from login.defs when allocating IDs
+  pub(crate) uid_max: Option<u32>,
+  /// Supplementary groups `user add` puts new users in
+  pub(crate) groups: Vec<String>,
+  /// Profile used when `--profile` is not given
+  pub(crate) profile: Option<String>,
+  /// Format of list commands run without `--format`
+  pub(crate) output_format: ListFormat,
+}
+
+impl Default for DefaultConfig {
+  fn default() -> Self {
+    let shell = if Path::new("/bin/bash").exists() {
+      "/bin/bash"
+    } else {
+      "/bin/sh"
+    };
+    DefaultConfig {
+      shell: String::from(shell),
+      home_base: String::from("/home"),
+      skel: String::from("/etc/skel"),
+      uid_min: None,
+      uid_max: None,
+      groups: Vec::new(),
+      profile: None,
+      output_format: ListFormat::Table,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct PasswordConfig {
+  /// Hash used for passwords set by userkit
+  pub(crate) hash_method: HashMethod,
+  /// Days before a password may be changed again
+  pub(crate) min_days: u32,
+  /// Days before a password must be changed
+  pub(crate) max_days: u32,
+  /// Days of warning before a password expires
+  pub(crate) warn_days: u32,
+}
+
+impl Default for PasswordConfig {
+  fn default() -> Self {
+    PasswordConfig {
+      hash_method: HashMethod::default(),
+      min_days: 0,
+      max_days: 99999,
+      warn_days: 7,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum HashMethod {
+  Yescrypt,
+  #[default]
+  Sha512,
+  Sha256,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct GuestConfig {
+  /// Shell given to guests created with `--restricted-shell`
+  pub(crate) restricted_shell: String,
+  /// Commands a restricted guest may run; empty leaves PATH untouched
+  pub(crate) allowed_commands: Vec<String>,
+  /// Groups a guest may never be a member of
+  pub(crate) excluded_groups: Vec<String>,
+  /// Resource limits every guest gets, as ITEM=VALUE, before `--limit`
+  pub(crate) limits: Vec<String>,
+}
+
+impl Default for GuestConfig {
+  fn default() -> Self {
+    GuestConfig {
+      restricted_shell: String::from("/usr/bin/rbash"),
+      allowed_commands: Vec::new(),
+      excluded_groups: [
+        "root", "wheel", "sudo", "admin", "adm", "shadow", "disk", "docker", "lxd", "libvirt",
+      ]
+      .into_iter()
+      .map(String::from)
+      .collect(),
+      limits: Vec::new(),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+pub(crate) struct SudoConfig {
+  /// How `perm sudo enable` grants access
+  pub(crate) method: SudoMethod,
+  /// Admin group used by the group method; detected when unset
+  pub(crate) admin_group: Option<String>,
+  /// Command aliases usable in `perm sudo grant --command`, by name
+  pub(crate) command_aliases: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum SudoMethod {
+  /// A rule in a per-user file in /etc/sudoers.d
+  #[default]
+  Dropin,
+  /// Membership in the admin group
+  Group,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct BackupConfig {
+  /// Whether commands back up the files they change before writing
+  pub(crate) enabled: bool,
+  /// Directory backups are kept in
+  pub(crate) dir: String,
+  /// Number of backups kept; older ones are removed, and 0 keeps all
+  pub(crate) keep: u32,
+}
+
+impl Default for BackupConfig {
+  fn default() -> Self {
+    BackupConfig {
+      enabled: true,
+      dir: String::from("/var/backups/userkit"),
+      keep: 20,
+    }
+  }
+}
+
+impl Config {
+  pub(crate) fn load() -> Result<Self, String> {
+    Config::from_table(merge(&layers()?)).map_err(|e| format!("Invalid configuration: {}", e))
+  }
+
+  /// Checks a raw table against the schema.
+  fn from_table(table: toml::Table) -> Result<Self, String> {
+    let config: Config = toml::Value::Table(table)
+      .try_into()
+      .map_err(|e: toml::de::Error| e.message().to_string())?;
+    if let (Some(min), Some(max)) = (config.default.uid_min, config.default.uid_max)
+      && min > max
+    {
+      return Err(format!(
+        "default.uid_min {} is above default.uid_max {}",
+        min, max
+      ));
+    }
+    Ok(config)
+  }
+}
+
+/// The kind of value a configuration key takes, checked by `config set`
+/// before the whole file is validated against the schema.
+#[derive(Clone, Copy)]
+enum Kind {
+  /// A string; enum values are checked by the schema
+  Text,
+  /// An absolute path
+  Path,
+  /// A user or group name
+  Name,
+  /// A user or group ID
+  Id,
+  /// A number of days
+  Days,
+  /// A comma-separated list
+  List,
+  /// `true` or `false`
+  Bool,
+  /// A non-negative number
+  Count,
+}
+
+struct Key {
+  name: &'static str,
+  kind: Kind,
+  /// Security-sensitive keys are only read from the system file, so that an
+  /// operator's environment cannot loosen what accounts are allowed to do.
+  restricted: bool,
+}
+
+/// Keys `config set` accepts, in the order `config list` shows them.
+const KEYS: [Key; 21] = [
+  Key {
+    name: "default.shell",
+    kind: Kind::Path,
+    restricted: true,
+  },
+  Key {
+    name: "default.home_base",
+    kind: Kind::Path,
+    restricted: true,
+  },
+  Key {
+    name: "default.skel",
+    kind: Kind::Path,
+    restricted: true,
+  },
+  Key {
+    name: "default.uid_min",
+    kind: Kind::Id,
+    restricted: true,
+  },
+  Key {
+    name: "default.uid_max",
+    kind: Kind::Id,
+    restricted: true,
+  },
+  Key {
+    name: "default.groups",
+    kind: Kind::List,
+    restricted: true,
+  },
+  Key {
+    name: "default.profile",
+    kind: Kind::Name,
+    restricted: true,
+  },
+  Key {
+    name: "default.output_format",
+    kind: Kind::Text,
+    restricted: false,
+  },
+  Key {
+    name: "password.hash_method",
+    kind: Kind::Text,
+    restricted: true,
+  },
+  Key {
+    name: "password.min_days",
+    kind: Kind::Days,
+    restricted: true,
+  },
+  Key {
+    name: "password.max_days",
+    kind: Kind::Days,
+    restricted: true,
+  },
+  Key {
+    name: "password.warn_days",
+    kind: Kind::Days,
+    restricted: true,
+  },
+  Key {
+    name: "guest.restricted_shell",
+    kind: Kind::Path,
+    restricted: true,
+  },
+  Key {
+    name: "guest.allowed_commands",
+    kind: Kind::List,
+    restricted: true,
+  },
+  Key {
+    name: "guest.excluded_groups",
+    kind: Kind::List,
+    restricted: true,
+  },
+  Key {
+    name: "guest.limits",
+    kind: Kind::List,
+    restricted: true,
+  },
+  Key {
+    name: "sudo.method",
+    kind: Kind::Text,
+    restricted: true,
+  },
+  Key {
+    name: "sudo.admin_group",
+    kind: Kind::Name,
+    restricted: true,
+  },
+  Key {
+    name: "backup.enabled",
+    kind: Kind::Bool,
+    restricted: true,
+  },
+  Key {
+    name: "backup.dir",
+    kind: Kind::Path,
+    restricted: true,
+  },
+  Key {
+    name: "backup.keep",
+    kind: Kind::Count,
+    restricted: true,
+  },
+];
+
+impl Kind {
+  fn parse(self, value: &str) -> Result<toml::Value, String> {
+    match self {
+      Kind::Text => Ok(toml::Value::String(value.to_string())),
+      Kind::Path if value.starts_with('/') => Ok(toml::Value::String(value.to_string())),
+      Kind::Path => Err(format!("{} is not an absolute path", value)),
+      Kind::Name if db::is_valid_name(value) => Ok(toml::Value::String(value.to_string())),
+      Kind::Name => Err(format!("{} is not a valid name", value)),
+      Kind::Id => value
+        .parse::<u32>()
+        .map(|id| toml::Value::Integer(id.into()))
+        .map_err(|_| format!("{} is not a valid ID", value)),
+      Kind::Days => value
+        .parse::<u32>()
+        .map(|days| toml::Value::Integer(days.into()))
+        .map_err(|_| format!("{} is not a number of days", value)),
+      Kind::Bool => value
+        .parse::<bool>()
+        .map(toml::Value::Boolean)
+        .map_err(|_| format!("{} is not true or false", value)),
+      Kind::Count => value
+        .parse::<u32>()
+        .map(|count| toml::Value::Integer(count.into()))
+        .map_err(|_| format!("{} is not a non-negative number", value)),
+      Kind::List => Ok(toml::Value::Array(
+        value
+          .split(',')
+          .map(str::trim)
+          .filter(|item| !item.is_empty())
+          .map(|item| toml::Value::String(item.to_string()))
+          .collect(),
+      )),
+    }
+  }
+}
+
+fn find_key(name: &str) -> Result<&'static Key, String> {
+  KEYS
+    .iter()
+    .find(|key| key.name == name)
+    .ok_or_else(|| format!("Unknown configuration option {}", name))
+}
+
+/// Where a configuration value comes from, in increasing precedence.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Source {
+  Default,
+  System,
+  Profile,
+  User,
+  File,
+  Env,
+}
+
+impl Source {
+  fn name(self) -> &'static str {
+    match self {
+      Source::Default => "default",
+      Source::System => "system file",
+      Source::Profile => "profile",
+      Source::User => "user file",
+      Source::File => "--config file",
+      Source::Env => "environment",
+    }
+  }
+}
+
+/// The settings one source contributes.
+struct Layer {
+  source: Source,
+  /// File, or profile section, the settings were read from; unset for the
+  /// environment
+  location: Option<String>,
+  table: toml::Table,
+}
+
+impl Layer {
+  /// Where `key` is set in this layer, for error messages and
+  /// `--show-origin`.
+  fn origin(&self, key: &str) -> String {
+    match &self.location {
+      Some(location) => location.clone(),
+      None => env_name(key),
+    }
+  }
+}
+
+/// A named set of settings from `[profile.NAME]` in the system file,
+/// applied on top of it when selected.
+struct Profile {
+  name: String,
+  description: Option<String>,
+  /// Settings laid out like the configuration file, e.g. `default.shell`
+  table: toml::Table,
+}
+
+impl Profile {
+  fn location(&self) -> String {
+    format!("{} [profile.{}]", CONFIG_PATH, self.name)
+  }
+}
+
+static CONFIG_FILE: OnceLock<PathBuf> = OnceLock::new();
+static PROFILE: OnceLock<String> = OnceLock::new();
+
+/// Adds the file given with `--config` as a layer above the system and user
+/// files.
+pub(crate) fn use_file(path: &Path) {
+  let _ = CONFIG_FILE.set(path.to_path_buf());
+}
+
+/// Selects the profile given with `--profile` in place of `default.profile`.
+pub(crate) fn use_profile(name: &str) {
+  let _ = PROFILE.set(name.to_string());
+}
+
+/// The per-operator configuration file, under `$XDG_CONFIG_HOME` or
+/// `~/.config`.
+fn user_config_path() -> Option<PathBuf> {
+  env::var_os("XDG_CONFIG_HOME")
+    .filter(|dir| Path::new(dir).is_absolute())
+    .map(PathBuf::from)
+    .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    .map(|dir| dir.join("userkit").join("config.toml"))
+}
+
+/// The environment variable overriding `key`, e.g. `USERKIT_DEFAULT_SHELL`.
+fn env_name(key: &str) -> String {
+  format!("USERKIT_{}", key.replace('.', "_").to_uppercase())
+}
+
+/// Reads a configuration file as a raw table, empty when it is missing.
+fn read_table(path: &Path) -> Result<toml::Table, String> {
+  match fs::read_to_string(path) {
+    Ok(content) => content
+      .parse()
+      .map_err(|e| format!("Failed to parse {}: {}", path.display(), e)),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(toml::Table::new()),
+    Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+  }
+}
+
+fn env_table() -> Result<toml::Table, String> {
+  let mut table = toml::Table::new();
+  for key in &KEYS {
+    let name = env_name(key.name);
+    let Some(value) = env::var_os(&name) else {
+      continue;
+    };
+    let value = value
+      .into_string()
+      .map_err(|_| format!("{} is not valid UTF-8", name))?;
+    let parsed = key
+      .kind
+      .parse(&value)
+      .map_err(|e| format!("Invalid value for {}: {}", name, e))?;
+    insert(&mut table, key.name, parsed)?;
+  }
+  Ok(table)
+}
+
+/// Profiles defined in the system file, by name.
+fn profiles(system: &toml::Table) -> Result<Vec<Profile>, String> {
+  let Some(value) = system.get("profile") else {
+    return Ok(Vec::new());
+  };
+  let Some(entries) = value.as_table() else {
+    return Err(format!("profile in {} is not a table", CONFIG_PATH));
+  };
+  entries
+    .iter()
+    .map(|(name, value)| {
+      let Some(mut table) = value.as_table().cloned() else {
+        return Err(format!(
+          "profile.{} in {} is not a table",
+          name, CONFIG_PATH
+        ));
+      };
+      let description = match table.remove("description") {
+        None => None,
+        Some(toml::Value::String(description)) => Some(description),
+        Some(_) => {
+          return Err(format!(
+            "profile.{}.description in {} is not a string",
+            name, CONFIG_PATH
+          ));
+        }
+      };
+      let profile = Profile {
+        name: name.clone(),
+        description,
+        table,
+      };
+      for key in setting_names(&profile.table) {
+        if key == "default.profile" || find_key(&key).is_err() {
+          return Err(format!(
+            "{} in {} is not a configuration option",
+            key,
+            profile.location()
+          ));
+        }
+      }
+      Ok(profile)
+    })
+    .collect()
+}
+
+/// The profile to apply: the one given with `--profile`, or else
+/// `default.profile` from the system file.
+fn selected_profile(system: &toml::Table) -> Result<Option<Profile>, String> {
+  let name = match PROFILE.get() {
+    Some(name) => name.as_str(),
+    None => match lookup(system, "default.profile").and_then(toml::Value::as_str) {
+      Some(name) => name,
+      None => return Ok(None),
+    },
+  };
+  profiles(system)?
+    .into_iter()
+    .find(|profile| profile.name == name)
+    .map(Some)
+    .ok_or_else(|| format!("Profile {} not found", name))
+}
+
+/// The configuration layers in increasing precedence: the system file, the
+/// selected profile, the user file, the `--config` file and `USERKIT_*`
+/// environment variables. Each is checked on its own, so errors point at the
+/// layer at fault.
+fn layers() -> Result<Vec<Layer>, String> {
+  let system = read_table(&crate::root::path(CONFIG_PATH))?;
+  let profile = selected_profile(&system)?;
+  let mut layers = vec![Layer {
+    source: Source::System,
+    location: Some(String::from(CONFIG_PATH)),
+    table: system,
+  }];
+  if let Some(profile) = profile {
+    layers.push(Layer {
+      source: Source::Profile,
+      location: Some(profile.location()),
+      table: profile.table,
+    });
+  }
+  if let Some(path) = user_config_path() {
+    layers.push(Layer {
+      source: Source::User,
+      table: read_table(&path)?,
+      location: Some(path.display().to_string()),
+    });
+  }
+  if let Some(path) = CONFIG_FILE.get() {
+    if !path.is_file() {
+      return Err(format!("Configuration file {} not found", path.display()));
+    }
+    layers.push(Layer {
+      source: Source::File,
+      table: read_table(path)?,
+      location: Some(path.display().to_string()),
+    });
+  }
+  layers.push(Layer {
+    source: Source::Env,
+    location: None,
+    table: env_table()?,
+  });
+
+  for layer in &layers {
+    if matches!(layer.source, Source::User | Source::File | Source::Env) {
+      check_unrestricted(layer)?;
+    }
+    Config::from_table(layer.table.clone()).map_err(|e| match &layer.location {
+      Some(location) => format!("Invalid configuration in {}: {}", location, e),
+      None => format!("Invalid configuration in the environment: {}", e),
+    })?;
+  }
+  Ok(layers)
+}
+
+/// The dotted names of everything set in `table`.
+fn setting_names(table: &toml::Table) -> Vec<String> {
+  table
+    .iter()
+    .flat_map(|(section, value)| match value.as_table() {
+      Some(entries) => entries
+        .keys()
+        .map(|name| format!("{}.{}", section, name))
+        .collect(),
+      None => vec![section.clone()],
+    })
+    .collect()
+}
+
+/// Refuses restricted keys, and anything that is not a configuration
+/// option, outside the system file and profiles.
+fn check_unrestricted(layer: &Layer) -> Result<(), String> {
+  for name in setting_names(&layer.table) {
+    if find_key(&name).is_ok_and(|key| !key.restricted) {
+      continue;
+    }
+    return Err(format!(
+      "{} in {} can only be set in {}",
+      name,
+      layer.origin(&name),
+      CONFIG_PATH
+    ));
+  }
+  Ok(())
+}
+
+/// Merges the layers key by key, later ones taking precedence.
+fn merge(layers: &[Layer]) -> toml::Table {
+  let mut merged = toml::Table::new();
+  for layer in layers {
+    for (section, value) in &layer.table {
+      match (
+        merged.get_mut(section).and_then(toml::Value::as_table_mut),
+        value.as_table(),
+      ) {
+        (Some(into), Some(entries)) => into.extend(entries.clone()),
+        _ => {
+          merged.insert(section.clone(), value.clone());
+        }
+      }
+    }
+  }
+  merged
+}
+
+/// Writes a configuration file, removing it once nothing is left in it.
+fn write_table(path: &Path, table: &toml::Table) -> Result<(), String> {
+  if table.is_empty() {
+    return match fs::remove_file(path) {
+      Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+        Err(format!("Failed to remove {}: {}", path.display(), e))
+      }
+      _ => Ok(()),
+    };
+  }
+  if let Some(dir) = path.parent() {
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+  }
+  let content = toml::to_string_pretty(table)
+    .map_err(|e| format!("Failed to serialize configuration: {}", e))?;
+  fs::write(path, content).map_err(|e| format!("Failed to write to {}: {}", path.display(), e))
+}
+
+/// The value of a dotted `section.name` key in `table`.
+fn lookup<'a>(table: &'a toml::Table, key: &str) -> Option<&'a toml::Value> {
+  let (section, name) = key.split_once('.')?;
+  table.get(section)?.as_table()?.get(name)
+}
+
+/// Sets a dotted key in `table`, adding its section if needed.
+fn insert(table: &mut toml::Table, key: &str, value: toml::Value) -> Result<(), String> {
+  let Some((section, name)) = key.split_once('.') else {
+    return Err(format!("Unknown configuration option {}", key));
+  };
+  let entries = table
+    .entry(section)
+    .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+  let Some(entries) = entries.as_table_mut() else {
+    return Err(format!("{} is not a table", section));
+  };
+  entries.insert(name.to_string(), value);
+  Ok(())
+}
+
+/// Removes a dotted key from `table`, dropping its section once empty.
+fn remove(table: &mut toml::Table, key: &str) {
+  let Some((section, name)) = key.split_once('.') else {
+    return;
+  };
+  if let Some(entries) = table.get_mut(section).and_then(toml::Value::as_table_mut) {
+    entries.remove(name);
+    if entries.is_empty() {
+      table.remove(section);
+    }
+  }
+}
+
+/// Every key of the schema with its effective value, unset optional keys
+/// being absent.
+fn effective(config: &Config) -> Result<toml::Table, String> {
+  toml::Table::try_from(config).map_err(|e| format!("Failed to serialize configuration: {}", e))
+}
+
+fn display(value: Option<&toml::Value>) -> String {
+  match value {
+    None => String::from("(unset)"),
+    Some(toml::Value::String(s)) => s.clone(),
+    Some(toml::Value::Array(items)) => items
+      .iter()
+      .map(|item| display(Some(item)))
+      .collect::<Vec<_>>()
+      .join(", "),
+    Some(value) => value.to_string(),
+  }
+}
+
+/// The `--format` given to a list command, or else `default.output_format`.
+/// A broken configuration is reported by the command itself, so it falls
+/// back to a table here.
+pub(crate) fn output_format(format: &Option<ListFormat>) -> ListFormat {
+  match format {
+    Some(format) => format.clone(),
+    None => Config::load().map_or(ListFormat::Table, |c| c.default.output_format),
+  }
+}
+
+/// Applies `f` to the system file, or with `user` to the caller's user file,
+/// which needs no privileges but only takes unrestricted keys.
+fn modify(user: bool, f: impl FnOnce(&mut toml::Table) -> Result<String, String>) -> bool {
+  let path = if user {
+    match user_config_path() {
+      Some(path) => path,
+      None => {
+        eprintln!(
+          "Error: Cannot locate the user configuration directory; set HOME or XDG_CONFIG_HOME"
+        );
+        return false;
+      }
+    }
+  } else if user::has_escalated_privileges() {
+    crate::root::path(CONFIG_PATH)
+  } else {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  };
+
+  let result = read_table(&path).and_then(|mut table| {
+    let message = f(&mut table)?;
+    write_table(&path, &table)?;
+    Ok(message)
+  });
+  match result {
+    Ok(message) => {
+      println!("{}", message);
+      true
+    }
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      false
+    }
+  }
+}
+
+pub(crate) fn set(key: &str, value: &str, user: bool) -> bool {
+  modify(user, |table| {
+    let option = find_key(key)?;
+    if user && option.restricted {
+      return Err(format!("{} can only be set in {}", key, CONFIG_PATH));
+    }
+    let parsed = option
+      .kind
+      .parse(value)
+      .map_err(|e| format!("Invalid value for {}: {}", key, e))?;
+    insert(table, key, parsed)?;
+    Config::from_table(table.clone()).map_err(|e| format!("Invalid value for {}: {}", key, e))?;
+    if key == "default.profile" && !profiles(table)?.iter().any(|p| p.name == value) {
+      return Err(format!("Profile {} not found", value));
+    }
+    Ok(format!("Configuration option {} set to {}", key, value))
+  })
+}
+
+pub(crate) fn get(key: &str) -> bool {
+  let value = find_key(key)
+    .and_then(|_| Config::load())
+    .and_then(|config| effective(&config))
+    .map(|values| display(lookup(&values, key)));
+  match value {
+    Ok(value) => {
+      println!("{} = {}", key, value);
+      true
+    }
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      false
+    }
+  }
+}
+
+#[derive(Tabled, Serialize)]
+struct ConfigRow {
+  #[tabled(rename = "Key")]
+  key: &'static str,
+  #[tabled(rename = "Value")]
+  value: String,
+  #[tabled(rename = "Source")]
+  source: &'static str,
+  /// The file or environment variable the value was read from
+  #[tabled(rename = "Origin")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  #[tabled(display = "display_origin")]
+  origin: Option<String>,
+}
+
+fn display_origin(origin: &Option<String>) -> String {
+  origin.clone().unwrap_or_default()
+}
+
+pub(crate) fn list(format: &ListFormat, show_origin: bool) -> bool {
+  let rows = layers().and_then(|layers| {
+    let config =
+      Config::from_table(merge(&layers)).map_err(|e| format!("Invalid configuration: {}", e))?;
+    let values = effective(&config)?;
+    Ok(
+      KEYS
+        .iter()
+        .map(|key| {
+          let layer = layers
+            .iter()
+            .rev()
+            .find(|layer| lookup(&layer.table, key.name).is_some());
+          ConfigRow {
+            key: key.name,
+            value: display(lookup(&values, key.name)),
+            source: layer.map_or(Source::Default, |l| l.source).name(),
+            origin: show_origin
+              .then(|| layer.map_or_else(|| String::from("built-in"), |l| l.origin(key.name))),
+          }
+        })
+        .collect::<Vec<_>>(),
+    )
+  });
+  let rows = match rows {
+    Ok(rows) => rows,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  match format {
+    ListFormat::Table => {
+      let mut table = Table::new(rows);
+      if !show_origin {
+        table.with(Remove::column(ByColumnName::new("Origin")));
+      }
+      println!("Configuration options:");
+      println!("{}", table);
+    }
+    ListFormat::Json => match serde_json::to_string_pretty(&rows) {
+      Ok(json) => println!("{}", json),
+      Err(e) => {
+        eprintln!("Error: Failed to serialize configuration to JSON: {}", e);
+        return false;
+      }
+    },
+    ListFormat::Csv => {
+      let quote = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+      if show_origin {
+        println!("key,value,source,origin");
+      } else {
+        println!("key,value,source");
+      }
+      for row in rows {
+        print!("{},{},{}", row.key, quote(&row.value), row.source);
+        match row.origin {
+          Some(origin) => println!(",{}", quote(&origin)),
+          None => println!(),
+        }
+      }
+    }
+  }
+  true
+}
+
+/// Resets one key, or every key of the schema, to its default. Other
+/// settings in the file, such as sudo command aliases, are kept.
+pub(crate) fn reset(key: Option<&str>, user: bool) -> bool {
+  modify(user, |table| match key {
+    Some(key) => {
+      find_key(key)?;
+      remove(table, key);
+      Ok(format!("Configuration option {} reset to default", key))
+    }
+    None => {
+      for key in &KEYS {
+        remove(table, key.name);
+      }
+      Ok(String::from("Configuration reset to defaults"))
+    }
+  })
+}
+
+#[derive(Tabled, Serialize)]
+struct ProfileRow {
+  #[tabled(rename = "Name")]
+  name: String,
+  #[tabled(rename = "Description")]
+  description: String,
+  #[tabled(rename = "Settings")]
+  settings: String,
+}
+
+pub(crate) fn profile_list(format: &ListFormat) -> bool {
+  let profiles =
+    match read_table(&crate::root::path(CONFIG_PATH)).and_then(|system| profiles(&system)) {
+      Ok(profiles) => profiles,
+      Err(e) => {
+        eprintln!("Error: {}", e);
+        return false;
+      }
+    };
+  let rows: Vec<ProfileRow> = profiles
+    .into_iter()
+    .map(|profile| ProfileRow {
+      settings: setting_names(&profile.table).join(", "),
+      name: profile.name,
+      description: profile.description.unwrap_or_default(),
+    })
+    .collect();
+
+  match format {
+    ListFormat::Table => {
+      if rows.is_empty() {
+        println!("No profiles defined in {}", CONFIG_PATH);
+        return true;
+      }
+      println!("Configuration profiles:");
+      println!("{}", Table::new(rows));
+    }
+    ListFormat::Json => match serde_json::to_string_pretty(&rows) {
+      Ok(json) => println!("{}", json),
+      Err(e) => {
+        eprintln!("Error: Failed to serialize profiles to JSON: {}", e);
+        return false;
+      }
+    },
+    ListFormat::Csv => {
+      println!("name,description,settings");
+      let quote = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+      for row in rows {
+        println!(
+          "{},{},{}",
+          row.name,
+          quote(&row.description),
+          quote(&row.settings)
+        );
+      }
+    }
+  }
+  true
+}
+
+pub(crate) fn profile_show(name: &str) -> bool {
+  let profile = read_table(&crate::root::path(CONFIG_PATH))
+    .and_then(|system| profiles(&system))
+    .and_then(|profiles| {
+      profiles
+        .into_iter()
+        .find(|profile| profile.name == name)
+        .ok_or_else(|| format!("Profile {} not found", name))
+    });
+  let profile = match profile {
+    Ok(profile) => profile,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  println!("Profile: {}", profile.name);
+  if let Some(description) = &profile.description {
+    println!("Description: {}", description);
+  }
+  let settings = setting_names(&profile.table);
+  if settings.is_empty() {
+    println!("Settings: none");
+  } else {
+    println!("Settings:");
+    for key in settings {
+      println!("  {} = {}", key, display(lookup(&profile.table, &key)));
+    }
+  }
+  true
+}
@@ -0,0 +1,706 @@
+use std::fs;
+use std::io::Write;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+pub(crate) const PASSWD_PATH: &str = "/etc/passwd";
+pub(crate) const SHADOW_PATH: &str = "/etc/shadow";
+pub(crate) const GROUP_PATH: &str = "/etc/group";
+pub(crate) const GSHADOW_PATH: &str = "/etc/gshadow";
+
+const LOCK_PATH: &str = "/etc/.pwd.lock";
+const LOGIN_DEFS_PATH: &str = "/etc/login.defs";
+const LOCK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// One colon-separated account file, kept line by line so that entries we
+/// don't touch are written back exactly as they were read.
+pub(crate) struct AccountFile {
+  path: PathBuf,
+  lines: Vec<String>,
+  present: bool,
+  trailing_newline: bool,
+  dirty: bool,
+}
+
+impl AccountFile {
+  fn load(path: &str) -> Result<Self, String> {
+    let path = crate::root::path(path);
+    let (content, present) = match fs::read_to_string(&path) {
+      Ok(content) => (content, true),
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => (String::new(), false),
+      Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    Ok(AccountFile {
+      path,
+      lines: content.lines().map(String::from).collect(),
+      present,
+      trailing_newline: content.is_empty() || content.ends_with('\n'),
+      dirty: false,
+    })
+  }
+
+  pub(crate) fn path(&self) -> &Path {
+    &self.path
+  }
+
+  /// Whether the file existed when it was read.
+  pub(crate) fn is_present(&self) -> bool {
+    self.present
+  }
+
+  pub(crate) fn lines(&self) -> impl Iterator<Item = &str> {
+    self.lines.iter().map(String::as_str)
+  }
+
+  pub(crate) fn find(&self, name: &str) -> Option<&str> {
+    self.lines().find(|line| entry_name(line) == name)
+  }
+
+  pub(crate) fn contains(&self, name: &str) -> bool {
+    self.find(name).is_some()
+  }
+
+  pub(crate) fn push(&mut self, line: String) {
+    self.lines.push(line);
+    self.dirty = true;
+  }
+
+  pub(crate) fn remove(&mut self, name: &str) -> bool {
+    let before = self.lines.len();
+    self.lines.retain(|line| entry_name(line) != name);
+    let removed = self.lines.len() != before;
+    self.dirty |= removed;
+    removed
+  }
+
+  pub(crate) fn replace(&mut self, name: &str, line: String) -> bool {
+    match self.lines.iter_mut().find(|l| entry_name(l) == name) {
+      Some(existing) => {
+        if *existing != line {
+          *existing = line;
+          self.dirty = true;
+        }
+        true
+      }
+      None => false,
+    }
+  }
+
+  /// Reorders the lines by `key`, keeping lines with equal keys in order.
+  pub(crate) fn sort_by_key<K: Ord>(&mut self, mut key: impl FnMut(&str) -> K) {
+    let before = self.lines.clone();
+    self.lines.sort_by_key(|line| key(line));
+    self.dirty |= self.lines != before;
+  }
+
+  fn contents(&self) -> String {
+    let mut content = self.lines.join("\n");
+    if self.trailing_newline && !self.lines.is_empty() {
+      content.push('\n');
+    }
+    content
+  }
+
+  /// Writes the file through a temporary sibling and renames it into place,
+  /// keeping the original owner and mode.
+  fn save(&self) -> Result<(), String> {
+    if !self.dirty || !self.present {
+      return Ok(());
+    }
+    crate::backup::before_write()?;
+
+    #[cfg(unix)]
+    {
+      use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+
+      let path = self.path.display();
+      let meta = fs::metadata(&self.path).map_err(|e| format!("Failed to stat {}: {}", path, e))?;
+      let tmp_path = format!("{}+", path);
+      let _ = fs::remove_file(&tmp_path);
+
+      let write = || -> std::io::Result<()> {
+        let mut file = fs::OpenOptions::new()
+          .write(true)
+          .create_new(true)
+          .mode(0o600)
+          .open(&tmp_path)?;
+        file.write_all(self.contents().as_bytes())?;
+        std::os::unix::fs::fchown(&file, Some(meta.uid()), Some(meta.gid()))?;
+        file.set_permissions(fs::Permissions::from_mode(meta.mode() & 0o7777))?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)
+      };
+
+      write().map_err(|e| {
+        let _ = fs::remove_file(&tmp_path);
+        format!("Failed to write to {}: {}", path, e)
+      })
+    }
+
+    #[cfg(not(unix))]
+    {
+      fs::write(&self.path, self.contents())
+        .map_err(|e| format!("Failed to write to {}: {}", self.path.display(), e))
+    }
+  }
+}
+
+fn entry_name(line: &str) -> &str {
+  line.split(':').next().unwrap_or("")
+}
+
+fn parse_optional(field: &str) -> Option<i64> {
+  field.parse().ok()
+}
+
+fn format_optional(value: Option<i64>) -> String {
+  value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+fn split_list(field: &str) -> Vec<String> {
+  field
+    .split(',')
+    .filter(|s| !s.is_empty())
+    .map(String::from)
+    .collect()
+}
+
+#[derive(Clone)]
+pub(crate) struct PasswdEntry {
+  pub(crate) name: String,
+  pub(crate) password: String,
+  pub(crate) uid: u32,
+  pub(crate) gid: u32,
+  pub(crate) gecos: String,
+  pub(crate) home: String,
+  pub(crate) shell: String,
+}
+
+impl PasswdEntry {
+  pub(crate) fn parse(line: &str) -> Option<Self> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() != 7 {
+      return None;
+    }
+    Some(PasswdEntry {
+      name: fields[0].to_string(),
+      password: fields[1].to_string(),
+      uid: fields[2].parse().ok()?,
+      gid: fields[3].parse().ok()?,
+      gecos: fields[4].to_string(),
+      home: fields[5].to_string(),
+      shell: fields[6].to_string(),
+    })
+  }
+
+  pub(crate) fn to_line(&self) -> String {
+    format!(
+      "{}:{}:{}:{}:{}:{}:{}",
+      self.name, self.password, self.uid, self.gid, self.gecos, self.home, self.shell
+    )
+  }
+}
+
+#[derive(Clone)]
+pub(crate) struct ShadowEntry {
+  pub(crate) name: String,
+  pub(crate) password: String,
+  pub(crate) last_change: Option<i64>,
+  pub(crate) min_age: Option<i64>,
+  pub(crate) max_age: Option<i64>,
+  pub(crate) warn: Option<i64>,
+  pub(crate) inactive: Option<i64>,
+  pub(crate) expire: Option<i64>,
+  pub(crate) reserved: String,
+}
+
+impl ShadowEntry {
+  /// A locked entry with the usual aging defaults, changed today.
+  pub(crate) fn locked(name: &str) -> Self {
+    ShadowEntry {
+      name: name.to_string(),
+      password: String::from("!"),
+      last_change: Some(days_since_epoch()),
+      min_age: Some(0),
+      max_age: Some(99999),
+      warn: Some(7),
+      inactive: None,
+      expire: None,
+      reserved: String::new(),
+    }
+  }
+
+  pub(crate) fn parse(line: &str) -> Option<Self> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() != 9 {
+      return None;
+    }
+    Some(ShadowEntry {
+      name: fields[0].to_string(),
+      password: fields[1].to_string(),
+      last_change: parse_optional(fields[2]),
+      min_age: parse_optional(fields[3]),
+      max_age: parse_optional(fields[4]),
+      warn: parse_optional(fields[5]),
+      inactive: parse_optional(fields[6]),
+      expire: parse_optional(fields[7]),
+      reserved: fields[8].to_string(),
+    })
+  }
+
+  pub(crate) fn to_line(&self) -> String {
+    format!(
+      "{}:{}:{}:{}:{}:{}:{}:{}:{}",
+      self.name,
+      self.password,
+      format_optional(self.last_change),
+      format_optional(self.min_age),
+      format_optional(self.max_age),
+      format_optional(self.warn),
+      format_optional(self.inactive),
+      format_optional(self.expire),
+      self.reserved
+    )
+  }
+}
+
+#[derive(Clone)]
+pub(crate) struct GroupEntry {
+  pub(crate) name: String,
+  pub(crate) password: String,
+  pub(crate) gid: u32,
+  pub(crate) members: Vec<String>,
+}
+
+impl GroupEntry {
+  pub(crate) fn parse(line: &str) -> Option<Self> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() != 4 {
+      return None;
+    }
+    Some(GroupEntry {
+      name: fields[0].to_string(),
+      password: fields[1].to_string(),
+      gid: fields[2].parse().ok()?,
+      members: split_list(fields[3]),
+    })
+  }
+
+  pub(crate) fn to_line(&self) -> String {
+    format!(
+      "{}:{}:{}:{}",
+      self.name,
+      self.password,
+      self.gid,
+      self.members.join(",")
+    )
+  }
+}
+
+#[derive(Clone)]
+pub(crate) struct GshadowEntry {
+  pub(crate) name: String,
+  pub(crate) password: String,
+  pub(crate) admins: Vec<String>,
+  pub(crate) members: Vec<String>,
+}
+
+impl GshadowEntry {
+  pub(crate) fn locked(name: &str) -> Self {
+    GshadowEntry {
+      name: name.to_string(),
+      password: String::from("!"),
+      admins: Vec::new(),
+      members: Vec::new(),
+    }
+  }
+
+  pub(crate) fn parse(line: &str) -> Option<Self> {
+    let fields: Vec<&str> = line.split(':').collect();
+    if fields.len() != 4 {
+      return None;
+    }
+    Some(GshadowEntry {
+      name: fields[0].to_string(),
+      password: fields[1].to_string(),
+      admins: split_list(fields[2]),
+      members: split_list(fields[3]),
+    })
+  }
+
+  pub(crate) fn to_line(&self) -> String {
+    format!(
+      "{}:{}:{}:{}",
+      self.name,
+      self.password,
+      self.admins.join(","),
+      self.members.join(",")
+    )
+  }
+}
+
+/// Exclusive lock on the account databases, compatible with `lckpwdf(3)`.
+/// Released when dropped.
+struct PwdLock {
+  _file: fs::File,
+}
+
+impl PwdLock {
+  #[cfg(unix)]
+  fn acquire() -> Result<Self, String> {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    let path = crate::root::path(LOCK_PATH);
+    let file = fs::OpenOptions::new()
+      .write(true)
+      .create(true)
+      .truncate(false)
+      .mode(0o600)
+      .open(&path)
+      .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+
+    let started = Instant::now();
+    loop {
+      // SAFETY: flock is a plain C struct; all-zero is a valid initial value.
+      let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+      lock.l_type = libc::F_WRLCK as _;
+      lock.l_whence = libc::SEEK_SET as _;
+      // SAFETY: the descriptor stays open for the lifetime of `file`.
+      if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_SETLK, &lock) } == 0 {
+        return Ok(PwdLock { _file: file });
+      }
+      if started.elapsed() > LOCK_TIMEOUT {
+        return Err(String::from(
+          "Account databases are locked by another process",
+        ));
+      }
+      std::thread::sleep(Duration::from_millis(100));
+    }
+  }
+
+  #[cfg(not(unix))]
+  fn acquire() -> Result<Self, String> {
+    Err(String::from(
+      "Locking the account databases is only supported on Unix-like systems",
+    ))
+  }
+}
+
+/// One of the account databases, for callers that need the raw lines.
+#[derive(Clone, Copy)]
+pub(crate) enum DbFile {
+  Passwd,
+  Shadow,
+  Group,
+  Gshadow,
+}
+
+/// The passwd, shadow, group and gshadow databases loaded together.
+pub(crate) struct Database {
+  passwd: AccountFile,
+  shadow: AccountFile,
+  group: AccountFile,
+  gshadow: AccountFile,
+  _lock: Option<PwdLock>,
+}
+
+impl Database {
+  /// Reads the databases without locking them, for read-only use.
+  pub(crate) fn load() -> Result<Self, String> {
+    Ok(Database {
+      passwd: AccountFile::load(PASSWD_PATH)?,
+      shadow: AccountFile::load(SHADOW_PATH)?,
+      group: AccountFile::load(GROUP_PATH)?,
+      gshadow: AccountFile::load(GSHADOW_PATH)?,
+      _lock: None,
+    })
+  }
+
+  /// Locks the databases and reads them for modification. The lock is held
+  /// until the returned value is dropped.
+  pub(crate) fn open() -> Result<Self, String> {
+    let lock = PwdLock::acquire()?;
+    let mut db = Database::load()?;
+    db._lock = Some(lock);
+    Ok(db)
+  }
+
+  /// Writes back every file that was modified.
+  pub(crate) fn commit(&mut self) -> Result<(), String> {
+    for file in [
+      &mut self.passwd,
+      &mut self.shadow,
+      &mut self.group,
+      &mut self.gshadow,
+    ] {
+      file.save()?;
+      file.dirty = false;
+    }
+    Ok(())
+  }
+
+  /// The raw lines of one database, including any that fail to parse.
+  pub(crate) fn file(&self, which: DbFile) -> &AccountFile {
+    match which {
+      DbFile::Passwd => &self.passwd,
+      DbFile::Shadow => &self.shadow,
+      DbFile::Group => &self.group,
+      DbFile::Gshadow => &self.gshadow,
+    }
+  }
+
+  /// Mutable access to the raw lines of one database, for repairs that
+  /// cannot go through the parsed entries.
+  pub(crate) fn file_mut(&mut self, which: DbFile) -> &mut AccountFile {
+    match which {
+      DbFile::Passwd => &mut self.passwd,
+      DbFile::Shadow => &mut self.shadow,
+      DbFile::Group => &mut self.group,
+      DbFile::Gshadow => &mut self.gshadow,
+    }
+  }
+
+  pub(crate) fn users(&self) -> Vec<PasswdEntry> {
+    self.passwd.lines().filter_map(PasswdEntry::parse).collect()
+  }
+
+  pub(crate) fn user(&self, name: &str) -> Option<PasswdEntry> {
+    self.passwd.find(name).and_then(PasswdEntry::parse)
+  }
+
+  pub(crate) fn set_user(&mut self, entry: &PasswdEntry) -> bool {
+    self.passwd.replace(&entry.name, entry.to_line())
+  }
+
+  pub(crate) fn shadow_entry(&self, name: &str) -> Option<ShadowEntry> {
+    self.shadow.find(name).and_then(ShadowEntry::parse)
+  }
+
+  pub(crate) fn set_shadow_entry(&mut self, entry: &ShadowEntry) -> bool {
+    self.shadow.replace(&entry.name, entry.to_line())
+  }
+
+  pub(crate) fn group(&self, name: &str) -> Option<GroupEntry> {
+    self.group.find(name).and_then(GroupEntry::parse)
+  }
+
+  pub(crate) fn set_group(&mut self, entry: &GroupEntry) -> bool {
+    self.group.replace(&entry.name, entry.to_line())
+  }
+
+  pub(crate) fn groups(&self) -> Vec<GroupEntry> {
+    self.group.lines().filter_map(GroupEntry::parse).collect()
+  }
+
+  /// Groups listing `user` as a member, not counting its primary group.
+  pub(crate) fn supplementary_groups(&self, user: &str) -> Vec<GroupEntry> {
+    self
+      .groups()
+      .into_iter()
+      .filter(|group| group.members.iter().any(|member| member == user))
+      .collect()
+  }
+
+  /// Lowest ID in the UID range that is free both as a UID and as a GID, so
+  /// a user and its private group can share it. Bounds not given are taken
+  /// from login.defs.
+  pub(crate) fn next_free_id(&self, min: Option<u32>, max: Option<u32>) -> Option<u32> {
+    let users = self.users();
+    let groups = self.groups();
+    id_range(min, max)
+      .find(|id| !users.iter().any(|u| u.uid == *id) && !groups.iter().any(|g| g.gid == *id))
+  }
+
+  pub(crate) fn add_user(&mut self, passwd: &PasswdEntry, shadow: &ShadowEntry) {
+    self.passwd.push(passwd.to_line());
+    self.shadow.push(shadow.to_line());
+  }
+
+  pub(crate) fn add_group(&mut self, group: &GroupEntry, gshadow: &GshadowEntry) {
+    self.group.push(group.to_line());
+    self.gshadow.push(gshadow.to_line());
+  }
+
+  /// Removes a user from a group's member list in group and gshadow.
+  pub(crate) fn remove_group_member(&mut self, group: &str, user: &str) -> bool {
+    let Some(mut entry) = self.group(group) else {
+      return false;
+    };
+    let removed = entry.members.iter().any(|m| m == user);
+    if removed {
+      entry.members.retain(|m| m != user);
+      self.group.replace(group, entry.to_line());
+    }
+    if let Some(mut entry) = self.gshadow.find(group).and_then(GshadowEntry::parse)
+      && entry.members.iter().any(|m| m == user)
+    {
+      entry.members.retain(|m| m != user);
+      self.gshadow.replace(group, entry.to_line());
+    }
+    removed
+  }
+
+  /// Adds a user to a group's member list in group and gshadow.
+  pub(crate) fn add_group_member(&mut self, group: &str, user: &str) -> bool {
+    let Some(mut entry) = self.group(group) else {
+      return false;
+    };
+    if !entry.members.iter().any(|m| m == user) {
+      entry.members.push(user.to_string());
+      self.group.replace(group, entry.to_line());
+    }
+    if let Some(mut entry) = self.gshadow.find(group).and_then(GshadowEntry::parse)
+      && !entry.members.iter().any(|m| m == user)
+    {
+      entry.members.push(user.to_string());
+      self.gshadow.replace(group, entry.to_line());
+    }
+    true
+  }
+
+  /// Removes a user from passwd and shadow and from every group it is a
+  /// member or administrator of.
+  pub(crate) fn remove_user(&mut self, name: &str) -> bool {
+    let removed = self.passwd.remove(name);
+    self.shadow.remove(name);
+
+    for mut group in self.groups() {
+      if group.members.iter().any(|m| m == name) {
+        group.members.retain(|m| m != name);
+        self.group.replace(&group.name.clone(), group.to_line());
+      }
+    }
+    let gshadow: Vec<GshadowEntry> = self
+      .gshadow
+      .lines()
+      .filter_map(GshadowEntry::parse)
+      .collect();
+    for mut entry in gshadow {
+      if entry.members.iter().chain(&entry.admins).any(|m| m == name) {
+        entry.members.retain(|m| m != name);
+        entry.admins.retain(|m| m != name);
+        self.gshadow.replace(&entry.name.clone(), entry.to_line());
+      }
+    }
+
+    removed
+  }
+
+  pub(crate) fn remove_group(&mut self, name: &str) -> bool {
+    let removed = self.group.remove(name);
+    self.gshadow.remove(name);
+    removed
+  }
+}
+
+/// Whether `name` is acceptable as a user or group name: lowercase letters,
+/// digits, underscores and dashes, not starting with a digit or dash.
+pub(crate) fn is_valid_name(name: &str) -> bool {
+  let mut chars = name.chars();
+  match chars.next() {
+    Some(c) if c.is_ascii_lowercase() || c == '_' => {}
+    _ => return false,
+  }
+  name.len() <= 32
+    && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_' || c == '-')
+}
+
+/// Whether `value` can be written into a passwd, shadow or group field as is:
+/// without the `:` separator or control characters such as a newline, which
+/// would let it add fields or whole entries.
+pub(crate) fn is_valid_field(value: &str) -> bool {
+  !value.contains(|c: char| c == ':' || c.is_control())
+}
+
+/// The range of regular (non-system) IDs. Bounds not given are taken from
+/// login.defs.
+pub(crate) fn id_range(min: Option<u32>, max: Option<u32>) -> RangeInclusive<u32> {
+  let min = min.or_else(|| login_defs_value("UID_MIN")).unwrap_or(1000);
+  let max = max.or_else(|| login_defs_value("UID_MAX")).unwrap_or(60000);
+  min..=max
+}
+
+/// Reads the user entries from passwd alone, which unlike shadow needs no
+/// privileges.
+pub(crate) fn read_users() -> Result<Vec<PasswdEntry>, String> {
+  Ok(
+    AccountFile::load(PASSWD_PATH)?
+      .lines()
+      .filter_map(PasswdEntry::parse)
+      .collect(),
+  )
+}
+
+/// Reads the group entries from group alone, which unlike gshadow needs no
+/// privileges.
+pub(crate) fn read_groups() -> Result<Vec<GroupEntry>, String> {
+  Ok(
+    AccountFile::load(GROUP_PATH)?
+      .lines()
+      .filter_map(GroupEntry::parse)
+      .collect(),
+  )
+}
+
+/// The users and groups from passwd and group only, for read-only lookups
+/// by callers that may not be allowed to read shadow.
+pub(crate) struct Accounts {
+  users: Vec<PasswdEntry>,
+  groups: Vec<GroupEntry>,
+}
+
+impl Accounts {
+  pub(crate) fn load() -> Result<Self, String> {
+    Ok(Accounts {
+      users: read_users()?,
+      groups: read_groups()?,
+    })
+  }
+
+  pub(crate) fn users(&self) -> Vec<PasswdEntry> {
+    self.users.clone()
+  }
+
+  pub(crate) fn user(&self, name: &str) -> Option<PasswdEntry> {
+    self.users.iter().find(|user| user.name == name).cloned()
+  }
+
+  pub(crate) fn groups(&self) -> Vec<GroupEntry> {
+    self.groups.clone()
+  }
+
+  pub(crate) fn group(&self, name: &str) -> Option<GroupEntry> {
+    self.groups.iter().find(|group| group.name == name).cloned()
+  }
+
+  /// Groups listing `user` as a member, not counting its primary group.
+  pub(crate) fn supplementary_groups(&self, user: &str) -> Vec<GroupEntry> {
+    self
+      .groups
+      .iter()
+      .filter(|group| group.members.iter().any(|member| member == user))
+      .cloned()
+      .collect()
+  }
+}
+
+/// Reads a numeric setting from /etc/login.defs.
+pub(crate) fn login_defs_value(key: &str) -> Option<u32> {
+  let content = fs::read_to_string(crate::root::path(LOGIN_DEFS_PATH)).ok()?;
+  content.lines().find_map(|line| {
+    let mut parts = line.split_whitespace();
+    if parts.next() == Some(key) {
+      parts.next()?.parse().ok()
+    } else {
+      None
+    }
+  })
+}
+
+/// Current date as days since the epoch, the unit used by shadow.
+pub(crate) fn days_since_epoch() -> i64 {
+  (crate::time::now() / 86400) as i64
+}
@@ -0,0 +1,971 @@
+use crate::cli::GuestRestrictions;
+use crate::config::Config;
+use crate::db::{
+  Database, DbFile, GroupEntry, GshadowEntry, PasswdEntry, ShadowEntry, is_valid_name,
+};
+use crate::limits::{self, Limit};
+use crate::{quota, time, user};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tabled::{Table, Tabled};
+
+pub(crate) const GUEST_STORE_PATH: &str = "/var/lib/userkit/guests.toml";
+const GUEST_PREFIX: &str = "guest-";
+const RESTRICTED_BIN_BASE: &str = "/var/lib/userkit/guest-bin";
+
+/// Where commands on a restricted guest's allowlist are looked up.
+const COMMAND_SEARCH_PATH: [&str; 6] = [
+  "/usr/local/bin",
+  "/usr/bin",
+  "/bin",
+  "/usr/local/sbin",
+  "/usr/sbin",
+  "/sbin",
+];
+
+/// Directories swept for files a guest left behind outside its home.
+const SCRATCH_DIRS: [&str; 3] = ["/tmp", "/var/tmp", "/dev/shm"];
+
+/// Bookkeeping for an account created by userkit's guest commands.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct Guest {
+  pub(crate) name: String,
+  pub(crate) uid: u32,
+  pub(crate) home: String,
+  /// Creation time in seconds since the epoch
+  pub(crate) created: u64,
+  /// Expiration time in seconds since the epoch
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) expires: Option<u64>,
+  /// Removed when the session that created it ends
+  #[serde(default)]
+  pub(crate) ephemeral: bool,
+  /// Home directory is a tmpfs mount
+  #[serde(default)]
+  pub(crate) tmpfs: bool,
+  /// Process running an ephemeral guest's session
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) pid: Option<u32>,
+  /// Login shell is the restricted shell
+  #[serde(default)]
+  pub(crate) restricted: bool,
+  /// Disk quota on the home directory in bytes
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) quota: Option<u64>,
+  /// Supplementary groups granted at creation
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub(crate) groups: Vec<String>,
+  /// Resource limits written to limits.d, as ITEM=VALUE
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub(crate) limits: Vec<String>,
+}
+
+/// Validated form of the restriction options a guest is created with.
+#[derive(Default)]
+struct Restrictions {
+  shell: Option<String>,
+  allowed_commands: Vec<String>,
+  quota: Option<u64>,
+  groups: Vec<String>,
+  limits: Vec<Limit>,
+}
+
+impl Restrictions {
+  fn resolve(args: &GuestRestrictions) -> Result<Self, String> {
+    let config = Config::load()?;
+    let excluded = &config.guest.excluded_groups;
+    if let Some(group) = args.groups.iter().find(|g| excluded.contains(g)) {
+      return Err(format!(
+        "Group {} is privileged and cannot be given to guest accounts",
+        group
+      ));
+    }
+
+    let (shell, allowed_commands) = if args.restricted_shell {
+      let shell = config.guest.restricted_shell;
+      if !Path::new(&shell).exists() {
+        return Err(format!("Restricted shell {} not found", shell));
+      }
+      let mut commands = config.guest.allowed_commands;
+      commands.extend(args.allow_commands.iter().cloned());
+      (Some(shell), commands)
+    } else {
+      (None, Vec::new())
+    };
+
+    Ok(Restrictions {
+      shell,
+      allowed_commands,
+      quota: args.quota.as_deref().map(quota::parse_size).transpose()?,
+      groups: args.groups.clone(),
+      limits: config
+        .guest
+        .limits
+        .iter()
+        .chain(&args.limits)
+        .map(|l| Limit::parse(l))
+        .collect::<Result<_, _>>()?,
+    })
+  }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct GuestStore {
+  #[serde(default, rename = "guest")]
+  guests: Vec<Guest>,
+}
+
+impl GuestStore {
+  fn load() -> Result<Self, String> {
+    let path = crate::root::path(GUEST_STORE_PATH);
+    match fs::read_to_string(&path) {
+      Ok(content) => {
+        toml::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+      }
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(GuestStore::default()),
+      Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+  }
+
+  fn save(&self) -> Result<(), String> {
+    crate::backup::before_write()?;
+    let path = crate::root::path(GUEST_STORE_PATH);
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let content = toml::to_string_pretty(self)
+      .map_err(|e| format!("Failed to serialize guest accounts: {}", e))?;
+    fs::write(&path, content).map_err(|e| format!("Failed to write to {}: {}", path.display(), e))
+  }
+
+  fn update(f: impl FnOnce(&mut GuestStore)) -> Result<(), String> {
+    let mut store = GuestStore::load()?;
+    f(&mut store);
+    store.save()
+  }
+}
+
+/// Names of the guest accounts userkit manages.
+pub(crate) fn guest_names() -> Result<Vec<String>, String> {
+  Ok(
+    GuestStore::load()?
+      .guests
+      .into_iter()
+      .map(|g| g.name)
+      .collect(),
+  )
+}
+
+pub(crate) fn create(
+  name: Option<&str>,
+  expire: Option<&str>,
+  restrictions: &GuestRestrictions,
+) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  let expires = match expire.map(time::parse_expiry).transpose() {
+    Ok(expires) => expires,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  let restrictions = match Restrictions::resolve(restrictions) {
+    Ok(restrictions) => restrictions,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  let guest = match create_guest_account(name, false, false, expires, &restrictions) {
+    Ok(guest) => guest,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  match (name, expire.zip(expires)) {
+    (_, Some((value, expires))) => match time::parse_duration(value).map(time::duration_unit) {
+      Ok((amount, unit)) => println!(
+        "Guest account {} created with {} {} expiration (until {})",
+        guest.name,
+        amount,
+        unit,
+        time::format_timestamp(expires)
+      ),
+      Err(_) => println!(
+        "Guest account {} created, expiring {}",
+        guest.name,
+        time::format_timestamp(expires)
+      ),
+    },
+    (Some(_), None) => println!("Guest account {} created", guest.name),
+    (None, None) => println!("Guest account created: {}", guest.name),
+  }
+  true
+}
+
+pub(crate) fn remove(name: &str) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  let Some(guest) = find_guest(name) else {
+    return false;
+  };
+  match remove_guest_account(&guest) {
+    Ok(()) => {
+      println!("Guest account {} removed", name);
+      true
+    }
+    Err(e) => {
+      eprintln!("Error: Failed to remove guest account {}: {}", name, e);
+      false
+    }
+  }
+}
+
+#[derive(Tabled)]
+struct GuestTable {
+  #[tabled(rename = "Name")]
+  name: String,
+  #[tabled(rename = "UID")]
+  uid: u32,
+  #[tabled(rename = "Home Directory")]
+  home: String,
+  #[tabled(rename = "Created")]
+  created: String,
+  #[tabled(rename = "Expires")]
+  expires: String,
+}
+
+pub(crate) fn list() -> bool {
+  let store = match GuestStore::load() {
+    Ok(store) => store,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  let table_data: Vec<GuestTable> = store
+    .guests
+    .into_iter()
+    .map(|guest| GuestTable {
+      expires: describe_expiry(guest.expires),
+      created: time::format_timestamp(guest.created),
+      name: guest.name,
+      uid: guest.uid,
+      home: guest.home,
+    })
+    .collect();
+
+  println!("Guest accounts:");
+  println!("{}", Table::new(table_data));
+  true
+}
+
+pub(crate) fn info(name: &str) -> bool {
+  let Some(guest) = find_guest(name) else {
+    return false;
+  };
+
+  println!("Guest account: {}", guest.name);
+  println!("UID: {}", guest.uid);
+  println!("Home Directory: {}", guest.home);
+  println!("Created: {}", time::format_timestamp(guest.created));
+  println!("Expires: {}", describe_expiry(guest.expires));
+  if let Some(pid) = guest.pid {
+    println!("Session: process {}", pid);
+  }
+  if guest.restricted {
+    println!("Shell: restricted");
+  }
+  if let Some(quota) = guest.quota {
+    println!("Quota: {}", quota::format_size(quota));
+  }
+  if !guest.groups.is_empty() {
+    println!("Groups: {}", guest.groups.join(", "));
+  }
+  if !guest.limits.is_empty() {
+    println!("Limits: {}", guest.limits.join(", "));
+  }
+  true
+}
+
+pub(crate) fn expire(name: &str, value: &str) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  let expires = match time::parse_expiry(value) {
+    Ok(expires) => expires,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  if let Err(e) = set_expiry(name, expires) {
+    eprintln!("Error: {}", e);
+    return false;
+  }
+
+  match time::parse_duration(value).map(time::duration_unit) {
+    Ok((amount, unit)) => println!(
+      "Expiration for guest account {} set to {} {}{} (until {})",
+      name,
+      amount,
+      unit,
+      if amount == 1 { "" } else { "s" },
+      time::format_timestamp(expires)
+    ),
+    Err(_) => println!(
+      "Expiration for guest account {} set to {}",
+      name,
+      time::format_timestamp(expires)
+    ),
+  }
+  true
+}
+
+fn set_expiry(name: &str, expires: u64) -> Result<(), String> {
+  // The database lock also covers the guest store
+  let mut db = Database::open()?;
+  let mut store = GuestStore::load()?;
+  let guest = store
+    .guests
+    .iter_mut()
+    .find(|g| g.name == name)
+    .ok_or_else(|| format!("Guest account {} not found", name))?;
+  guest.expires = Some(expires);
+
+  let mut shadow = db
+    .shadow_entry(name)
+    .ok_or_else(|| format!("User {} not found in shadow database", name))?;
+  shadow.expire = Some(shadow_expire_day(expires));
+  db.set_shadow_entry(&shadow);
+  db.commit()?;
+
+  store.save()
+}
+
+/// Removes guests whose expiry lies more than `grace` in the past, and
+/// ephemeral guests whose session is no longer running.
+pub(crate) fn reap(grace: Option<&str>, dry_run: bool) -> bool {
+  if !dry_run && !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  let grace = match grace.map(time::parse_duration).transpose() {
+    Ok(grace) => grace.unwrap_or(0),
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  let store = match GuestStore::load() {
+    Ok(store) => store,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  let now = time::now();
+  let mut success = true;
+  let mut reaped = 0;
+  for guest in store.guests {
+    let reason = match (guest.expires, guest.pid) {
+      (Some(expires), _) if expires.saturating_add(grace) <= now => {
+        format!("expired {}", time::format_timestamp(expires))
+      }
+      (_, Some(pid)) if guest.ephemeral && !process_alive(pid) => {
+        format!("session {} no longer running", pid)
+      }
+      _ => continue,
+    };
+
+    reaped += 1;
+    if dry_run {
+      println!("Would remove guest account {} ({})", guest.name, reason);
+      continue;
+    }
+    match remove_guest_account(&guest) {
+      Ok(()) => println!("Removed guest account {} ({})", guest.name, reason),
+      Err(e) => {
+        eprintln!(
+          "Error: Failed to remove guest account {}: {}",
+          guest.name, e
+        );
+        success = false;
+      }
+    }
+  }
+
+  if reaped == 0 {
+    println!("No guest accounts to remove");
+  }
+  success
+}
+
+/// Prints a systemd service and timer that run `guest reap` periodically.
+pub(crate) fn print_reap_units(grace: Option<&str>) -> bool {
+  if let Some(Err(e)) = grace.map(time::parse_duration) {
+    eprintln!("Error: {}", e);
+    return false;
+  }
+
+  let exe = std::env::current_exe()
+    .map(|p| p.to_string_lossy().into_owned())
+    .unwrap_or_else(|_| String::from("/usr/local/bin/userkit"));
+  let grace_arg = grace.map(|g| format!(" --grace {}", g)).unwrap_or_default();
+
+  println!("# /etc/systemd/system/userkit-guest-reap.service");
+  println!("[Unit]");
+  println!("Description=Remove expired userkit guest accounts");
+  println!();
+  println!("[Service]");
+  println!("Type=oneshot");
+  println!("ExecStart={} guest reap{}", exe, grace_arg);
+  println!();
+  println!("# /etc/systemd/system/userkit-guest-reap.timer");
+  println!("[Unit]");
+  println!("Description=Periodically remove expired userkit guest accounts");
+  println!();
+  println!("[Timer]");
+  println!("OnCalendar=*:0/15");
+  println!("Persistent=true");
+  println!();
+  println!("[Install]");
+  println!("WantedBy=timers.target");
+  true
+}
+
+fn find_guest(name: &str) -> Option<Guest> {
+  match GuestStore::load() {
+    Ok(store) => {
+      let guest = store.guests.into_iter().find(|g| g.name == name);
+      if guest.is_none() {
+        eprintln!("Error: Guest account {} not found", name);
+      }
+      guest
+    }
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      None
+    }
+  }
+}
+
+fn describe_expiry(expires: Option<u64>) -> String {
+  match expires {
+    Some(expires) if expires <= time::now() => {
+      format!("{} (expired)", time::format_timestamp(expires))
+    }
+    Some(expires) => time::format_timestamp(expires),
+    None => String::from("never"),
+  }
+}
+
+fn process_alive(pid: u32) -> bool {
+  // SAFETY: signal 0 only checks that the process exists.
+  let rc = unsafe { libc::kill(pid as libc::pid_t, 0) };
+  rc == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Creates a throwaway guest, runs its shell (or `command`) and removes every
+/// trace of the account once the shell exits or userkit is told to stop.
+pub(crate) fn shell(tmpfs: bool, restrictions: &GuestRestrictions, command: Option<&str>) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  let restrictions = match Restrictions::resolve(restrictions) {
+    Ok(restrictions) => restrictions,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  // A temporary home is limited by sizing its tmpfs rather than a project quota.
+  let tmpfs = tmpfs || restrictions.quota.is_some();
+  let guest = match create_guest_account(None, true, tmpfs, None, &restrictions) {
+    Ok(guest) => guest,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  println!("Guest account {} created", guest.name);
+
+  signals::install();
+  let success = run_session(&guest, &restrictions, command);
+
+  match remove_guest_account(&guest) {
+    Ok(()) => println!("Guest account {} removed", guest.name),
+    Err(e) => {
+      eprintln!(
+        "Error: Failed to remove guest account {}: {}",
+        guest.name, e
+      );
+      return false;
+    }
+  }
+  success
+}
+
+fn run_session(guest: &Guest, restrictions: &Restrictions, command: Option<&str>) -> bool {
+  use std::os::unix::process::CommandExt;
+
+  let ids = Database::load().and_then(|db| {
+    let entry = db
+      .user(&guest.name)
+      .ok_or_else(|| format!("User {} not found", guest.name))?;
+    let groups: Vec<libc::gid_t> = db
+      .groups()
+      .into_iter()
+      .filter(|g| g.members.contains(&guest.name))
+      .map(|g| g.gid)
+      .collect();
+    Ok((entry.gid, groups))
+  });
+  let (gid, groups) = match ids {
+    Ok(ids) => ids,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  let Some(mut cmd) = user::shell_command_with(Some(&guest.name), command, false) else {
+    return false;
+  };
+  if !restrictions.allowed_commands.is_empty() {
+    cmd.env("PATH", restricted_bin_dir(&guest.name));
+  }
+  // The session bypasses PAM, so apply the limits pam_limits would have set.
+  // That has to happen while still root, as raising a hard limit (nice,
+  // rtprio) needs privileges the guest lacks; the switch to the guest with
+  // its supplementary groups follows.
+  let apply_limits = limits::applier(&restrictions.limits);
+  let uid = guest.uid;
+  let switch = move || -> std::io::Result<()> {
+    apply_limits()?;
+    // SAFETY: groups outlives the call; these only make system calls, which
+    // is safe between fork and exec.
+    unsafe {
+      if libc::setgroups(groups.len(), groups.as_ptr()) != 0
+        || libc::setgid(gid) != 0
+        || libc::setuid(uid) != 0
+      {
+        return Err(std::io::Error::last_os_error());
+      }
+    }
+    Ok(())
+  };
+  // SAFETY: the closure only calls setrlimit, setgroups, setgid and setuid.
+  unsafe { cmd.pre_exec(switch) };
+
+  let mut child = match cmd.spawn() {
+    Ok(child) => child,
+    Err(e) => {
+      eprintln!("Failed to execute shell: {}", e);
+      return false;
+    }
+  };
+
+  loop {
+    match child.try_wait() {
+      Ok(Some(status)) => return status.success(),
+      Ok(None) => {}
+      Err(e) => {
+        eprintln!("Failed to wait for shell: {}", e);
+        kill_user_processes(guest.uid);
+        let _ = child.wait();
+        return false;
+      }
+    }
+    // Interrupts are left to the shell; hangups and termination end the session.
+    if signals::termination_requested() {
+      kill_user_processes(guest.uid);
+    }
+    std::thread::sleep(Duration::from_millis(50));
+  }
+}
+
+/// Adds a guest account and its private group and records it in the guest
+/// store. Ephemeral guests get a home under the temporary directory and
+/// remember the session that owns them.
+fn create_guest_account(
+  name: Option<&str>,
+  ephemeral: bool,
+  tmpfs: bool,
+  expires: Option<u64>,
+  restrictions: &Restrictions,
+) -> Result<Guest, String> {
+  let defaults = Config::load()?.default;
+  let mut db = Database::open()?;
+  if let Some(group) = restrictions.groups.iter().find(|g| db.group(g).is_none()) {
+    return Err(format!("Group {} not found", group));
+  }
+  let name = match name {
+    Some(name) => {
+      if !is_valid_name(name) {
+        return Err(format!("Invalid guest account name {}", name));
+      }
+      if db.file(DbFile::Passwd).contains(name) || db.file(DbFile::Group).contains(name) {
+        return Err(format!("User {} already exists", name));
+      }
+      name.to_string()
+    }
+    None => random_guest_name(&db)?,
+  };
+  let id = db
+    .next_free_id(defaults.uid_min, defaults.uid_max)
+    .ok_or_else(|| String::from("No free UID available for the guest account"))?;
+  let home = if ephemeral {
+    std::env::temp_dir().join(&name)
+  } else {
+    Path::new(&defaults.home_base).join(&name)
+  };
+
+  let guest = Guest {
+    name: name.clone(),
+    uid: id,
+    home: home.to_string_lossy().into_owned(),
+    created: time::now(),
+    expires,
+    ephemeral,
+    tmpfs,
+    pid: ephemeral.then(std::process::id),
+    restricted: restrictions.shell.is_some(),
+    quota: restrictions.quota,
+    groups: restrictions.groups.clone(),
+    limits: restrictions.limits.iter().map(Limit::to_string).collect(),
+  };
+
+  create_home(&guest, id, &defaults.skel)?;
+  if let Err(e) = apply_restrictions(&guest, restrictions) {
+    let _ = remove_guest_files(&guest);
+    return Err(e);
+  }
+
+  let passwd = PasswdEntry {
+    name: name.clone(),
+    password: String::from("x"),
+    uid: id,
+    gid: id,
+    gecos: String::from("Guest account"),
+    home: guest.home.clone(),
+    shell: restrictions.shell.clone().unwrap_or(defaults.shell),
+  };
+  let mut shadow = ShadowEntry::locked(&name);
+  shadow.expire = expires.map(shadow_expire_day);
+  let group = GroupEntry {
+    name: name.clone(),
+    password: String::from("x"),
+    gid: id,
+    members: Vec::new(),
+  };
+  db.add_user(&passwd, &shadow);
+  db.add_group(&group, &GshadowEntry::locked(&name));
+  for group in &restrictions.groups {
+    db.add_group_member(group, &name);
+  }
+
+  if let Err(e) = db
+    .commit()
+    .and_then(|()| GuestStore::update(|s| s.guests.push(guest.clone())))
+  {
+    let _ = remove_guest_files(&guest);
+    return Err(e);
+  }
+
+  Ok(guest)
+}
+
+/// The shadow expiration day for a precise expiry time. Rounds up so that
+/// shadow never locks a guest out before its expiry; `reap` enforces the
+/// exact time.
+fn shadow_expire_day(expires: u64) -> i64 {
+  expires.div_ceil(86400) as i64
+}
+
+fn create_home(guest: &Guest, id: u32, skel: &str) -> Result<(), String> {
+  use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+  let home = Path::new(&guest.home);
+  fs::DirBuilder::new()
+    .mode(0o700)
+    .create(home)
+    .map_err(|e| format!("Failed to create {}: {}", guest.home, e))?;
+
+  let populate = || -> Result<(), String> {
+    if guest.tmpfs {
+      mount_tmpfs(home, id, guest.quota)?;
+    }
+    fs::set_permissions(home, fs::Permissions::from_mode(0o700))
+      .and_then(|()| std::os::unix::fs::chown(home, Some(id), Some(id)))
+      .map_err(|e| format!("Failed to set ownership of {}: {}", guest.home, e))?;
+    user::copy_skel(Path::new(skel), home, id, id)
+      .map_err(|e| format!("Failed to populate {}: {}", guest.home, e))
+  };
+
+  populate().inspect_err(|_| {
+    let _ = remove_home(guest);
+  })
+}
+
+fn mount_tmpfs(target: &Path, id: u32, size: Option<u64>) -> Result<(), String> {
+  use std::ffi::CString;
+  use std::os::unix::ffi::OsStrExt;
+
+  let source = CString::new("tmpfs").unwrap();
+  let target_c = CString::new(target.as_os_str().as_bytes())
+    .map_err(|_| format!("Invalid path {}", target.display()))?;
+  let mut options = format!("mode=0700,uid={0},gid={0}", id);
+  if let Some(size) = size {
+    options.push_str(&format!(",size={}", size));
+  }
+  let data = CString::new(options).unwrap();
+
+  // SAFETY: all pointers are valid NUL-terminated strings for the duration of the call.
+  let rc = unsafe {
+    libc::mount(
+      source.as_ptr(),
+      target_c.as_ptr(),
+      source.as_ptr(),
+      libc::MS_NOSUID | libc::MS_NODEV,
+      data.as_ptr().cast(),
+    )
+  };
+  if rc != 0 {
+    return Err(format!(
+      "Failed to mount tmpfs on {}: {}",
+      target.display(),
+      std::io::Error::last_os_error()
+    ));
+  }
+  Ok(())
+}
+
+/// Sets up the parts of a guest's restrictions that live outside the
+/// account databases: the project quota, the restricted shell environment
+/// and the limits.d file.
+fn apply_restrictions(guest: &Guest, restrictions: &Restrictions) -> Result<(), String> {
+  if let Some(bytes) = guest.quota.filter(|_| !guest.tmpfs) {
+    quota::set_project_quota(Path::new(&guest.home), guest.uid, bytes)?;
+  }
+  if !restrictions.allowed_commands.is_empty() {
+    restrict_commands(guest, &restrictions.allowed_commands)?;
+  }
+  if !restrictions.limits.is_empty() {
+    limits::write_limits(&guest.name, &restrictions.limits)?;
+  }
+  Ok(())
+}
+
+fn restricted_bin_dir(name: &str) -> std::path::PathBuf {
+  Path::new(RESTRICTED_BIN_BASE).join(name)
+}
+
+/// Populates a root-owned directory with links to the allowed commands and
+/// pins the guest's PATH to it from root-owned startup files.
+fn restrict_commands(guest: &Guest, commands: &[String]) -> Result<(), String> {
+  let bin_dir = restricted_bin_dir(&guest.name);
+  fs::create_dir_all(&bin_dir)
+    .map_err(|e| format!("Failed to create {}: {}", bin_dir.display(), e))?;
+
+  for command in commands {
+    let target = if command.contains('/') {
+      Some(std::path::PathBuf::from(command)).filter(|p| p.is_file())
+    } else {
+      COMMAND_SEARCH_PATH
+        .iter()
+        .map(|dir| Path::new(dir).join(command))
+        .find(|p| p.is_file())
+    }
+    .ok_or_else(|| format!("Command {} not found", command))?;
+
+    let link = bin_dir.join(target.file_name().unwrap_or_default());
+    let _ = fs::remove_file(&link);
+    std::os::unix::fs::symlink(&target, &link)
+      .map_err(|e| format!("Failed to link {}: {}", link.display(), e))?;
+  }
+
+  let profile = format!(
+    "# Managed by userkit: restricted guest environment\nreadonly PATH={}\nexport PATH\n",
+    bin_dir.display()
+  );
+  for file in [".bash_profile", ".bashrc"] {
+    let path = Path::new(&guest.home).join(file);
+    let _ = fs::remove_file(&path);
+    fs::write(&path, &profile)
+      .map_err(|e| format!("Failed to write to {}: {}", path.display(), e))?;
+  }
+  Ok(())
+}
+
+/// Kills the guest's processes, deletes its files and removes it from the
+/// account databases and the guest store.
+pub(crate) fn remove_guest_account(guest: &Guest) -> Result<(), String> {
+  // The database lock also covers the guest store, so it is held until the
+  // entry is dropped. If the account was already deleted its UID may belong
+  // to someone else by now, so only the store entry is dropped.
+  let mut db = Database::open()?;
+  if db
+    .user(&guest.name)
+    .is_some_and(|user| user.uid == guest.uid)
+  {
+    kill_user_processes(guest.uid);
+    remove_guest_files(guest)?;
+    sweep_scratch_dirs(guest.uid);
+
+    db.remove_user(&guest.name);
+    db.remove_group(&guest.name);
+    db.commit()?;
+  }
+
+  GuestStore::update(|s| s.guests.retain(|g| g.name != guest.name))
+}
+
+/// Removes the home directory and everything `apply_restrictions` set up.
+fn remove_guest_files(guest: &Guest) -> Result<(), String> {
+  remove_home(guest)?;
+  if guest.quota.is_some() && !guest.tmpfs {
+    let parent = Path::new(&guest.home).parent().unwrap_or(Path::new("/"));
+    quota::clear_project_quota(parent, guest.uid)?;
+  }
+  limits::remove_limits(&guest.name)?;
+  match fs::remove_dir_all(restricted_bin_dir(&guest.name)) {
+    Ok(()) => Ok(()),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+    Err(e) => Err(format!("Failed to remove restricted commands: {}", e)),
+  }
+}
+
+fn remove_home(guest: &Guest) -> Result<(), String> {
+  let home = Path::new(&guest.home);
+  if guest.tmpfs
+    && let Ok(target) = std::ffi::CString::new(guest.home.as_bytes())
+  {
+    // SAFETY: target is a valid NUL-terminated path.
+    unsafe { libc::umount2(target.as_ptr(), libc::MNT_DETACH) };
+  }
+  match fs::remove_dir_all(home) {
+    Ok(()) => Ok(()),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+    Err(e) => Err(format!("Failed to remove {}: {}", guest.home, e)),
+  }
+}
+
+/// Removes top-level entries owned by `uid` from the shared scratch
+/// directories, so a later account reusing the UID cannot inherit them.
+fn sweep_scratch_dirs(uid: u32) {
+  use std::os::unix::fs::MetadataExt;
+
+  for dir in SCRATCH_DIRS {
+    let Ok(entries) = fs::read_dir(dir) else {
+      continue;
+    };
+    for entry in entries.flatten() {
+      let Ok(meta) = entry.path().symlink_metadata() else {
+        continue;
+      };
+      if meta.uid() != uid {
+        continue;
+      }
+      let _ = if meta.is_dir() {
+        fs::remove_dir_all(entry.path())
+      } else {
+        fs::remove_file(entry.path())
+      };
+    }
+  }
+}
+
+/// Sends SIGKILL to every process running as `uid` until none are left.
+pub(crate) fn kill_user_processes(uid: u32) {
+  for _ in 0..50 {
+    let pids = user_processes(uid);
+    if pids.is_empty() {
+      return;
+    }
+    for pid in pids {
+      // SAFETY: kill has no memory-safety preconditions.
+      unsafe { libc::kill(pid, libc::SIGKILL) };
+    }
+    std::thread::sleep(Duration::from_millis(20));
+  }
+}
+
+/// PIDs whose real, effective, saved or filesystem UID is `uid`.
+fn user_processes(uid: u32) -> Vec<libc::pid_t> {
+  let Ok(entries) = fs::read_dir("/proc") else {
+    return Vec::new();
+  };
+
+  entries
+    .flatten()
+    .filter_map(|entry| {
+      let pid: libc::pid_t = entry.file_name().to_str()?.parse().ok()?;
+      let status = fs::read_to_string(entry.path().join("status")).ok()?;
+      let uids = status.lines().find_map(|l| l.strip_prefix("Uid:"))?;
+      uids
+        .split_whitespace()
+        .any(|u| u.parse() == Ok(uid))
+        .then_some(pid)
+    })
+    .collect()
+}
+
+fn random_guest_name(db: &Database) -> Result<String, String> {
+  use std::io::Read;
+
+  let mut urandom =
+    fs::File::open("/dev/urandom").map_err(|e| format!("Failed to open /dev/urandom: {}", e))?;
+  loop {
+    let mut bytes = [0u8; 3];
+    urandom
+      .read_exact(&mut bytes)
+      .map_err(|e| format!("Failed to read /dev/urandom: {}", e))?;
+    let suffix: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    let name = format!("{}{}", GUEST_PREFIX, suffix);
+    if !db.file(DbFile::Passwd).contains(&name) && !db.file(DbFile::Group).contains(&name) {
+      return Ok(name);
+    }
+  }
+}
+
+/// Records termination signals so a guest session can be torn down instead
+/// of leaving the account behind. Handlers revert to the default disposition
+/// in the spawned shell on exec.
+mod signals {
+  use std::sync::atomic::{AtomicI32, Ordering};
+
+  static CAUGHT: AtomicI32 = AtomicI32::new(0);
+
+  extern "C" fn record(signal: libc::c_int) {
+    CAUGHT.store(signal, Ordering::SeqCst);
+  }
+
+  pub(super) fn install() {
+    for signal in [libc::SIGINT, libc::SIGQUIT, libc::SIGHUP, libc::SIGTERM] {
+      // SAFETY: `record` only performs an atomic store, which is async-signal-safe.
+      unsafe { libc::signal(signal, record as *const () as libc::sighandler_t) };
+    }
+  }
+
+  pub(super) fn termination_requested() -> bool {
+    matches!(CAUGHT.load(Ordering::SeqCst), libc::SIGHUP | libc::SIGTERM)
+  }
+}
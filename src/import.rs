@@ -0,0 +1,507 @@
+use crate::cli::{ImportFormat, ListFormat};
+use crate::config::Config;
+use crate::db::{self, Database, GroupEntry, GshadowEntry, PasswdEntry, ShadowEntry};
+use crate::{password, user};
+use serde::Serialize;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use tabled::{Table, Tabled};
+
+const CSV_COLUMNS: [&str; 8] = [
+  "username", "home_dir", "shell", "uid", "gid", "gecos", "password", "groups",
+];
+
+/// One user to create, as read from an input line or a batch step.
+#[derive(Default)]
+pub(crate) struct Row {
+  pub(crate) username: String,
+  pub(crate) home: Option<String>,
+  pub(crate) shell: Option<String>,
+  pub(crate) uid: Option<u32>,
+  /// A GID or group name
+  pub(crate) gid: Option<String>,
+  pub(crate) gecos: Option<String>,
+  /// In plain text; hashed with `password.hash_method`
+  pub(crate) password: Option<String>,
+  pub(crate) groups: Vec<String>,
+}
+
+/// A line of input and the user read from it.
+struct Input {
+  line: usize,
+  username: String,
+  row: Result<Row, String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Status {
+  Created,
+  Failed,
+  /// Not attempted after an earlier row failed
+  Skipped,
+  /// Added, then discarded because a later row failed
+  RolledBack,
+}
+
+impl Status {
+  fn name(self) -> &'static str {
+    match self {
+      Status::Created => "created",
+      Status::Failed => "failed",
+      Status::Skipped => "skipped",
+      Status::RolledBack => "rolled back",
+    }
+  }
+}
+
+#[derive(Tabled, Serialize)]
+struct ImportRow {
+  #[tabled(rename = "Line")]
+  line: usize,
+  #[tabled(rename = "Username")]
+  username: String,
+  #[tabled(rename = "Status")]
+  #[tabled(display = "display_status")]
+  status: Status,
+  #[tabled(rename = "UID")]
+  #[tabled(display = "display_uid")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  uid: Option<u32>,
+  #[tabled(rename = "Message")]
+  #[tabled(display = "display_message")]
+  #[serde(skip_serializing_if = "Option::is_none")]
+  message: Option<String>,
+}
+
+fn display_status(status: &Status) -> String {
+  status.name().to_string()
+}
+
+fn display_uid(uid: &Option<u32>) -> String {
+  uid.map(|uid| uid.to_string()).unwrap_or_default()
+}
+
+fn display_message(message: &Option<String>) -> String {
+  message.clone().unwrap_or_default()
+}
+
+/// Splits a CSV line into fields, handling quoted fields and doubled quotes.
+fn split_csv(line: &str) -> Result<Vec<String>, String> {
+  let mut fields = Vec::new();
+  let mut field = String::new();
+  let mut chars = line.chars().peekable();
+  let mut quoted = false;
+  while let Some(c) = chars.next() {
+    match c {
+      '"' if quoted && chars.peek() == Some(&'"') => {
+        chars.next();
+        field.push('"');
+      }
+      '"' if quoted => quoted = false,
+      '"' if field.is_empty() => quoted = true,
+      ',' if !quoted => fields.push(std::mem::take(&mut field)),
+      _ => field.push(c),
+    }
+  }
+  if quoted {
+    return Err(String::from("Unterminated quoted field"));
+  }
+  fields.push(field);
+  Ok(fields)
+}
+
+/// Lines worth reading: not blank and not comments, numbered from 1.
+fn content_lines(content: &str) -> impl Iterator<Item = (usize, &str)> {
+  content
+    .lines()
+    .enumerate()
+    .map(|(i, line)| (i + 1, line.trim_end_matches('\r')))
+    .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+}
+
+fn non_empty(value: &str) -> Option<String> {
+  let value = value.trim();
+  (!value.is_empty()).then(|| value.to_string())
+}
+
+fn parse_uid(value: &str) -> Result<Option<u32>, String> {
+  non_empty(value)
+    .map(|uid| uid.parse().map_err(|_| format!("Invalid UID '{}'", uid)))
+    .transpose()
+}
+
+/// Reads CSV with a header naming the columns, which may come in any order.
+/// Only `username` is required; `groups` is a list separated by semicolons
+/// or commas.
+fn parse_csv(content: &str) -> Result<Vec<Input>, String> {
+  let mut lines = content_lines(content);
+  let Some((_, header)) = lines.next() else {
+    return Ok(Vec::new());
+  };
+  let columns: Vec<String> = split_csv(header)
+    .map_err(|e| format!("Invalid CSV header: {}", e))?
+    .iter()
+    .map(|c| c.trim().to_lowercase().replace('-', "_"))
+    .collect();
+  if let Some(column) = columns.iter().find(|c| !CSV_COLUMNS.contains(&c.as_str())) {
+    return Err(format!(
+      "Unknown CSV column '{}': expected {}",
+      column,
+      CSV_COLUMNS.join(", ")
+    ));
+  }
+  if !columns.iter().any(|c| c == "username") {
+    return Err(String::from("CSV header has no username column"));
+  }
+
+  Ok(
+    lines
+      .map(|(line, text)| {
+        let fields = split_csv(text);
+        let username = fields
+          .as_ref()
+          .ok()
+          .and_then(|fields| {
+            let at = columns.iter().position(|c| c == "username")?;
+            fields.get(at).map(|f| f.trim().to_string())
+          })
+          .unwrap_or_default();
+        let row = fields.and_then(|fields| {
+          if fields.len() != columns.len() {
+            return Err(format!(
+              "Expected {} fields, found {}",
+              columns.len(),
+              fields.len()
+            ));
+          }
+          let mut row = Row::default();
+          for (column, value) in columns.iter().zip(&fields) {
+            match column.as_str() {
+              "username" => row.username = value.trim().to_string(),
+              "home_dir" => row.home = non_empty(value),
+              "shell" => row.shell = non_empty(value),
+              "uid" => row.uid = parse_uid(value)?,
+              "gid" => row.gid = non_empty(value),
+              "gecos" => row.gecos = non_empty(value),
+              "password" => row.password = (!value.is_empty()).then(|| value.clone()),
+              _ => row.groups = value.split([';', ',']).filter_map(non_empty).collect(),
+            }
+          }
+          Ok(row)
+        });
+        Input {
+          line,
+          username,
+          row,
+        }
+      })
+      .collect(),
+  )
+}
+
+/// Reads the `newusers` format: `name:password:uid:gid:gecos:dir:shell`,
+/// with the password in plain text and empty fields left to the defaults.
+fn parse_newusers(content: &str) -> Vec<Input> {
+  content_lines(content)
+    .map(|(line, text)| {
+      let fields: Vec<&str> = text.split(':').collect();
+      let username = fields[0].trim().to_string();
+      let row = if fields.len() != 7 {
+        Err(format!(
+          "Expected 7 colon-separated fields, found {}",
+          fields.len()
+        ))
+      } else {
+        parse_uid(fields[2]).map(|uid| Row {
+          username: username.clone(),
+          password: (!fields[1].is_empty()).then(|| fields[1].to_string()),
+          uid,
+          gid: non_empty(fields[3]),
+          gecos: non_empty(fields[4]),
+          home: non_empty(fields[5]),
+          shell: non_empty(fields[6]),
+          groups: Vec::new(),
+        })
+      };
+      Input {
+        line,
+        username,
+        row,
+      }
+    })
+    .collect()
+}
+
+/// Finds or creates the primary group for a new user: an existing group
+/// given by GID or name, a new group with the given GID or name, or else a
+/// private group named after the user.
+fn primary_group(db: &mut Database, row: &Row, uid: u32, config: &Config) -> Result<u32, String> {
+  let groups = db.groups();
+  let (name, gid) = match &row.gid {
+    Some(value) => match value.parse::<u32>() {
+      Ok(gid) => match groups.iter().find(|g| g.gid == gid) {
+        Some(_) => return Ok(gid),
+        None => (row.username.clone(), Some(gid)),
+      },
+      Err(_) => (value.clone(), None),
+    },
+    None => (row.username.clone(), None),
+  };
+  if let Some(group) = groups.iter().find(|g| g.name == name) {
+    if gid.is_some() {
+      return Err(format!(
+        "Group {} already exists with GID {}",
+        name, group.gid
+      ));
+    }
+    return Ok(group.gid);
+  }
+  if !db::is_valid_name(&name) {
+    return Err(format!("Invalid group name '{}'", name));
+  }
+
+  let gid = match gid {
+    Some(gid) => gid,
+    None if !groups.iter().any(|g| g.gid == uid) => uid,
+    None => db
+      .next_free_id(config.default.uid_min, config.default.uid_max)
+      .ok_or_else(|| format!("No free GID available for group {}", name))?,
+  };
+  db.add_group(
+    &GroupEntry {
+      name: name.clone(),
+      password: String::from("x"),
+      gid,
+      members: Vec::new(),
+    },
+    &GshadowEntry::locked(&name),
+  );
+  Ok(gid)
+}
+
+/// Adds one user to the databases in memory, returning its entry.
+pub(crate) fn add_row(
+  db: &mut Database,
+  row: &Row,
+  config: &Config,
+) -> Result<PasswdEntry, String> {
+  let defaults = &config.default;
+  if !db::is_valid_name(&row.username) {
+    return Err(format!("Invalid username '{}'", row.username));
+  }
+  if db.user(&row.username).is_some() {
+    return Err(format!("User {} already exists", row.username));
+  }
+  let mut groups = defaults.groups.clone();
+  for group in &row.groups {
+    if !groups.contains(group) {
+      groups.push(group.clone());
+    }
+  }
+  if let Some(group) = groups.iter().find(|g| db.group(g).is_none()) {
+    return Err(format!("Group {} not found", group));
+  }
+  let home = row.home.clone().unwrap_or_else(|| {
+    Path::new(&defaults.home_base)
+      .join(&row.username)
+      .to_string_lossy()
+      .into_owned()
+  });
+  let shell = row.shell.clone().unwrap_or_else(|| defaults.shell.clone());
+  for path in [&home, &shell] {
+    if !path.starts_with('/') || !db::is_valid_field(path) {
+      return Err(format!("Invalid path '{}': use an absolute path", path));
+    }
+  }
+  let gecos = row.gecos.clone().unwrap_or_default();
+  if !db::is_valid_field(&gecos) {
+    return Err(String::from(
+      "GECOS field cannot contain ':' or control characters",
+    ));
+  }
+
+  let uid = match row.uid {
+    Some(uid) => match db.users().into_iter().find(|u| u.uid == uid) {
+      Some(owner) => return Err(format!("UID {} is taken by user {}", uid, owner.name)),
+      None => uid,
+    },
+    None => db
+      .next_free_id(defaults.uid_min, defaults.uid_max)
+      .ok_or_else(|| String::from("No free UID available"))?,
+  };
+  let password = match &row.password {
+    Some(plain) => password::hash(plain, config.password.hash_method)?,
+    None => String::from("!"),
+  };
+  let gid = primary_group(db, row, uid, config)?;
+
+  let entry = PasswdEntry {
+    name: row.username.clone(),
+    password: String::from("x"),
+    uid,
+    gid,
+    gecos,
+    home,
+    shell,
+  };
+  let policy = &config.password;
+  db.add_user(
+    &entry,
+    &ShadowEntry {
+      password,
+      min_age: Some(policy.min_days.into()),
+      max_age: Some(policy.max_days.into()),
+      warn: Some(policy.warn_days.into()),
+      ..ShadowEntry::locked(&row.username)
+    },
+  );
+  for group in &groups {
+    db.add_group_member(group, &row.username);
+  }
+  Ok(entry)
+}
+
+fn read_input(path: &Path) -> Result<String, String> {
+  if path == Path::new("-") {
+    let mut content = String::new();
+    std::io::stdin()
+      .read_to_string(&mut content)
+      .map_err(|e| format!("Failed to read standard input: {}", e))?;
+    return Ok(content);
+  }
+  fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+}
+
+/// Creates users from a CSV or `newusers` file in one locked transaction.
+/// A failing row aborts the whole import unless `continue_on_error` is set,
+/// in which case it is skipped and the rest are created.
+pub(crate) fn import(
+  path: &Path,
+  input_format: Option<&ImportFormat>,
+  continue_on_error: bool,
+  format: &ListFormat,
+) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  let result = read_input(path).and_then(|content| {
+    let csv = match input_format {
+      Some(ImportFormat::Csv) => true,
+      Some(ImportFormat::Newusers) => false,
+      None => path.extension().is_some_and(|ext| ext == "csv"),
+    };
+    let inputs = if csv {
+      parse_csv(&content)?
+    } else {
+      parse_newusers(&content)
+    };
+    Ok((inputs, Config::load()?, Database::open()?))
+  });
+  let (inputs, config, mut db) = match result {
+    Ok(result) => result,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  let mut rows = Vec::new();
+  let mut created = Vec::new();
+  let mut aborted = false;
+  for input in inputs {
+    let mut row = ImportRow {
+      line: input.line,
+      username: input.username,
+      status: Status::Skipped,
+      uid: None,
+      message: None,
+    };
+    if !aborted {
+      match input.row.and_then(|r| add_row(&mut db, &r, &config)) {
+        Ok(entry) => {
+          row.status = Status::Created;
+          row.uid = Some(entry.uid);
+          created.push((rows.len(), entry));
+        }
+        Err(e) => {
+          row.status = Status::Failed;
+          row.message = Some(e);
+          aborted = !continue_on_error;
+        }
+      }
+    }
+    rows.push(row);
+  }
+
+  let mut success = !rows.iter().any(|r| r.status == Status::Failed);
+  if aborted {
+    for (index, _) in &created {
+      rows[*index].status = Status::RolledBack;
+    }
+  } else if let Err(e) = db.commit() {
+    eprintln!("Error: {}", e);
+    return false;
+  } else {
+    drop(db);
+    let skel = crate::root::path(&config.default.skel);
+    for (index, entry) in &created {
+      let home = crate::root::path(&entry.home);
+      if !home.exists()
+        && let Err(e) = user::create_home(&home, entry.uid, entry.gid, &skel)
+      {
+        rows[*index].message = Some(e);
+        success = false;
+      }
+    }
+  }
+
+  match format {
+    ListFormat::Table => {
+      if rows.is_empty() {
+        println!("No users to import");
+      } else {
+        println!("Import results:");
+        println!("{}", Table::new(&rows));
+      }
+    }
+    ListFormat::Json => match serde_json::to_string_pretty(&rows) {
+      Ok(json) => println!("{}", json),
+      Err(e) => {
+        eprintln!("Error: Failed to serialize import results to JSON: {}", e);
+        return false;
+      }
+    },
+    ListFormat::Csv => {
+      println!("line,username,status,uid,message");
+      let quote = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+      for row in &rows {
+        println!(
+          "{},{},{},{},{}",
+          row.line,
+          quote(&row.username),
+          row.status.name(),
+          display_uid(&row.uid),
+          quote(&display_message(&row.message))
+        );
+      }
+    }
+  }
+
+  let count = rows.iter().filter(|r| r.status == Status::Created).count();
+  if aborted {
+    eprintln!("Error: Import aborted; no users were created");
+  } else if !success {
+    eprintln!(
+      "Error: {} of {} users created; see the failed rows above",
+      count,
+      rows.len()
+    );
+  } else if matches!(format, ListFormat::Table) {
+    println!("{} users created", count);
+  }
+  success
+}
@@ -0,0 +1,129 @@
+use std::fs;
+use std::path::PathBuf;
+
+const LIMITS_DIR: &str = "/etc/security/limits.d";
+
+/// pam_limits items, with the rlimit they map to and the factor converting
+/// the limits.conf unit into the rlimit unit. Items without an rlimit are
+/// only enforced by pam_limits at login.
+const ITEMS: [(&str, Option<libc::c_int>, u64); 18] = [
+  ("core", Some(libc::RLIMIT_CORE as libc::c_int), 1024),
+  ("data", Some(libc::RLIMIT_DATA as libc::c_int), 1024),
+  ("fsize", Some(libc::RLIMIT_FSIZE as libc::c_int), 1024),
+  ("memlock", Some(libc::RLIMIT_MEMLOCK as libc::c_int), 1024),
+  ("nofile", Some(libc::RLIMIT_NOFILE as libc::c_int), 1),
+  ("rss", Some(libc::RLIMIT_RSS as libc::c_int), 1024),
+  ("stack", Some(libc::RLIMIT_STACK as libc::c_int), 1024),
+  ("cpu", Some(libc::RLIMIT_CPU as libc::c_int), 60),
+  ("nproc", Some(libc::RLIMIT_NPROC as libc::c_int), 1),
+  ("as", Some(libc::RLIMIT_AS as libc::c_int), 1024),
+  ("locks", Some(libc::RLIMIT_LOCKS as libc::c_int), 1),
+  (
+    "sigpending",
+    Some(libc::RLIMIT_SIGPENDING as libc::c_int),
+    1,
+  ),
+  ("msgqueue", Some(libc::RLIMIT_MSGQUEUE as libc::c_int), 1),
+  ("nice", Some(libc::RLIMIT_NICE as libc::c_int), 1),
+  ("rtprio", Some(libc::RLIMIT_RTPRIO as libc::c_int), 1),
+  ("maxlogins", None, 1),
+  ("maxsyslogins", None, 1),
+  ("priority", None, 1),
+];
+
+/// A resource limit in limits.conf terms, e.g. `nproc=100`.
+#[derive(Clone)]
+pub(crate) struct Limit {
+  pub(crate) item: String,
+  pub(crate) value: String,
+}
+
+impl Limit {
+  pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+    let (item, value) = spec
+      .split_once('=')
+      .ok_or_else(|| format!("Invalid limit '{}': expected ITEM=VALUE", spec))?;
+    if !ITEMS.iter().any(|(name, _, _)| *name == item) {
+      return Err(format!("Unknown limit item '{}'", item));
+    }
+    let numeric = match item {
+      "nice" | "priority" => value.parse::<i64>().is_ok_and(|v| (-20..=19).contains(&v)),
+      _ => value.parse::<u64>().is_ok() || matches!(value, "unlimited" | "infinity" | "-1"),
+    };
+    if !numeric {
+      return Err(format!("Invalid value '{}' for limit {}", value, item));
+    }
+
+    Ok(Limit {
+      item: item.to_string(),
+      value: value.to_string(),
+    })
+  }
+
+  /// The rlimit resource and value this limit sets, if it has one.
+  fn rlimit(&self) -> Option<(libc::c_int, libc::rlim_t)> {
+    let (_, resource, factor) = ITEMS.iter().find(|(name, _, _)| *name == self.item)?;
+    let value = match self.value.as_str() {
+      // RLIMIT_NICE counts up from 1 (nice 19) to 40 (nice -20). Checked
+      // first, since -1 is a nice value here rather than "unlimited".
+      value if self.item == "nice" => (20 - value.parse::<i64>().ok()?) as libc::rlim_t,
+      "unlimited" | "infinity" | "-1" => libc::RLIM_INFINITY,
+      value => value.parse::<u64>().ok()?.saturating_mul(*factor) as libc::rlim_t,
+    };
+    Some(((*resource)?, value))
+  }
+}
+
+impl std::fmt::Display for Limit {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}={}", self.item, self.value)
+  }
+}
+
+fn limits_file(username: &str) -> PathBuf {
+  crate::root::path(LIMITS_DIR).join(format!("userkit-{}.conf", username))
+}
+
+/// Writes the limits as hard and soft limits for `username` to its own file
+/// in limits.d.
+pub(crate) fn write_limits(username: &str, limits: &[Limit]) -> Result<(), String> {
+  let path = limits_file(username);
+  let mut content = format!("# Managed by userkit for {}\n", username);
+  for limit in limits {
+    content.push_str(&format!("{} - {} {}\n", username, limit.item, limit.value));
+  }
+
+  let dir = crate::root::path(LIMITS_DIR);
+  fs::create_dir_all(&dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+  fs::write(&path, content).map_err(|e| format!("Failed to write to {}: {}", path.display(), e))
+}
+
+pub(crate) fn remove_limits(username: &str) -> Result<(), String> {
+  let path = limits_file(username);
+  match fs::remove_file(&path) {
+    Ok(()) => Ok(()),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+    Err(e) => Err(format!("Failed to remove {}: {}", path.display(), e)),
+  }
+}
+
+/// Returns a function applying the limits to the calling process, for use
+/// in `pre_exec` where pam_limits does not run.
+pub(crate) fn applier(
+  limits: &[Limit],
+) -> impl Fn() -> std::io::Result<()> + Send + Sync + 'static {
+  let rlimits: Vec<(libc::c_int, libc::rlim_t)> = limits.iter().filter_map(Limit::rlimit).collect();
+  move || {
+    for (resource, value) in &rlimits {
+      let limit = libc::rlimit {
+        rlim_cur: *value,
+        rlim_max: *value,
+      };
+      // SAFETY: setrlimit is async-signal-safe and `limit` outlives the call.
+      if unsafe { libc::setrlimit(*resource as _, &limit) } != 0 {
+        return Err(std::io::Error::last_os_error());
+      }
+    }
+    Ok(())
+  }
+}
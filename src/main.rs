@@ -1,21 +1,99 @@
+mod acl;
+mod backup;
+mod batch;
+mod check;
 mod cli;
+mod config;
+mod db;
+mod guest;
+mod import;
+mod limits;
+mod manifest;
+mod mode;
+mod password;
+mod perm;
+mod quota;
+mod role;
+mod root;
+mod sudo;
+mod sudoers;
+mod time;
 mod user;
+mod xattr;
 
 use clap::Parser;
-use cli::{Cli, Domains, ListFormat, UserCommands};
+use cli::{
+  AclCommands, BackupCommands, Cli, ConfigCommands, Domains, GuestCommands, GuestRestrictions,
+  ListFormat, PermCommands, ProfileCommands, RoleCommands, SudoAction, SudoCommands, UserCommands,
+};
 
 // CLI structure is now defined in cli.rs
 
 fn main() {
   let cli = Cli::parse();
+  if let Some(dir) = &cli.root {
+    root::use_root(dir);
+  }
+  if let Some(path) = &cli.config {
+    config::use_file(path);
+  }
+  if let Some(name) = &cli.profile {
+    config::use_profile(name);
+  }
+  // Guests and shells run processes on this system, which cannot be done
+  // for accounts under another root
+  if cli.root.is_some()
+    && matches!(
+      cli.domain,
+      Domains::Guest(_) | Domains::User(UserCommands::Shell { .. })
+    )
+  {
+    eprintln!("Error: --root cannot be used with guest accounts or user shells");
+    std::process::exit(1);
+  }
 
   match &cli.domain {
     Domains::User(cmd) => handle_user_commands(cmd),
     Domains::Group(_) => println!("Group management not implemented yet"),
-    Domains::Perm(_) => println!("Permission management not implemented yet"),
-    Domains::Role(_) => println!("Role management not implemented yet"),
-    Domains::Guest(_) => println!("Guest account management not implemented yet"),
-    Domains::Config(_) => println!("Configuration management not implemented yet"),
+    Domains::Perm(cmd) => handle_perm_commands(cmd),
+    Domains::Role(cmd) => handle_role_commands(cmd),
+    Domains::Guest(cmd) => handle_guest_commands(cmd),
+    Domains::Config(cmd) => handle_config_commands(cmd),
+    Domains::Backup(cmd) => handle_backup_commands(cmd),
+    Domains::Restore { id } => {
+      if !backup::restore(id) {
+        std::process::exit(1);
+      }
+    }
+    Domains::Apply { manifest, prune } => {
+      if !manifest::apply(manifest, *prune) {
+        std::process::exit(1);
+      }
+    }
+    Domains::Plan { manifest, prune } => {
+      if !manifest::show_plan(manifest, *prune) {
+        std::process::exit(1);
+      }
+    }
+    Domains::Batch { file, dry_run } => {
+      if !batch::run(file.as_ref(), *dry_run) {
+        std::process::exit(1);
+      }
+    }
+    Domains::Check { fix, yes } => {
+      if !check::check(*fix, *yes) {
+        std::process::exit(1);
+      }
+    }
+    Domains::Export {
+      output,
+      system,
+      passwords,
+    } => {
+      if !manifest::export(output.as_deref(), *system, *passwords) {
+        std::process::exit(1);
+      }
+    }
   }
 }
 
@@ -31,7 +109,7 @@ fn handle_user_commands(cmd: &UserCommands) {
         println!("Filtering not implemented yet");
       }
 
-      match format {
+      match config::output_format(format) {
         ListFormat::Table => user::list_users_as_table(),
         ListFormat::Json => user::list_users_as_json(),
         ListFormat::Csv => println!("CSV format not implemented yet"),
@@ -42,6 +120,11 @@ fn handle_user_commands(cmd: &UserCommands) {
         std::process::exit(1);
       }
     }
+    UserCommands::Roles { username, format } => {
+      if !role::user_roles(username, &config::output_format(format)) {
+        std::process::exit(1);
+      }
+    }
     UserCommands::Add {
       username,
       home_dir,
@@ -50,16 +133,33 @@ fn handle_user_commands(cmd: &UserCommands) {
       gid,
       gecos,
     } => {
-      let home = home_dir.as_deref().unwrap_or("/home/");
-      if user::add_user(username, home) {
+      if user::add_user(
+        username,
+        home_dir.as_deref(),
+        shell.as_deref(),
+        *uid,
+        *gid,
+        gecos.as_deref(),
+      ) {
         println!("User {} created successfully", username);
       } else {
         eprintln!("Error: Failed to create user {}", username);
         std::process::exit(1);
       }
-      // Additional parameters not implemented yet
-      if shell.is_some() || uid.is_some() || gid.is_some() || gecos.is_some() {
-        println!("Additional parameters not implemented yet");
+    }
+    UserCommands::Import {
+      file,
+      input_format,
+      continue_on_error,
+      format,
+    } => {
+      if !import::import(
+        file,
+        input_format.as_ref(),
+        *continue_on_error,
+        &config::output_format(format),
+      ) {
+        std::process::exit(1);
       }
     }
     UserCommands::Remove {
@@ -88,10 +188,245 @@ fn handle_user_commands(cmd: &UserCommands) {
     UserCommands::Passwd { username } => {
       println!("Password for {} changed successfully", username);
     }
-    UserCommands::Shell { username, command } => {
-      if !user::shell(username.as_deref(), command.as_deref()) {
+    UserCommands::Shell {
+      username,
+      temp,
+      command,
+    } => {
+      let success = if *temp {
+        guest::shell(false, &GuestRestrictions::default(), command.as_deref())
+      } else {
+        user::shell(username.as_deref(), command.as_deref())
+      };
+      if !success {
+        std::process::exit(1);
+      }
+    }
+  }
+}
+
+fn handle_role_commands(cmd: &RoleCommands) {
+  let success = match cmd {
+    RoleCommands::Create {
+      rolename,
+      description,
+      inherits,
+    } => role::create(rolename, description.as_deref(), inherits),
+    RoleCommands::Delete { rolename, force } => role::delete(rolename, *force),
+    RoleCommands::Assign {
+      rolename,
+      username,
+      duration,
+      until,
+    } => role::assign(rolename, username, duration.as_deref(), until.as_deref()),
+    RoleCommands::Revoke { rolename, username } => role::revoke(rolename, username),
+    RoleCommands::List { format } => role::list(&config::output_format(format)),
+    RoleCommands::Info {
+      rolename,
+      effective,
+    } => role::info(rolename, *effective),
+    RoleCommands::Tree => role::tree(),
+    RoleCommands::WhoHas { permission, format } => {
+      role::who_has(permission, &config::output_format(format))
+    }
+    RoleCommands::Diff {
+      first,
+      second,
+      format,
+    } => role::diff(first, second, &config::output_format(format)),
+    RoleCommands::AddPerm {
+      rolename,
+      permission,
+    } => role::add_perm(rolename, permission),
+    RoleCommands::RemovePerm {
+      rolename,
+      permission,
+    } => role::remove_perm(rolename, permission),
+    RoleCommands::Sync => role::sync(),
+    RoleCommands::Reap { dry_run } => role::reap(*dry_run),
+  };
+  if !success {
+    std::process::exit(1);
+  }
+}
+
+fn handle_guest_commands(cmd: &GuestCommands) {
+  match cmd {
+    GuestCommands::Shell {
+      tmpfs,
+      restrictions,
+      command,
+    } => {
+      if !guest::shell(*tmpfs, restrictions, command.as_deref()) {
+        std::process::exit(1);
+      }
+    }
+    GuestCommands::Create {
+      name,
+      expire,
+      restrictions,
+    } => {
+      if !guest::create(name.as_deref(), expire.as_deref(), restrictions) {
+        std::process::exit(1);
+      }
+    }
+    GuestCommands::Remove { name } => {
+      if !guest::remove(name) {
+        std::process::exit(1);
+      }
+    }
+    GuestCommands::List => {
+      if !guest::list() {
+        std::process::exit(1);
+      }
+    }
+    GuestCommands::Info { name } => {
+      if !guest::info(name) {
+        std::process::exit(1);
+      }
+    }
+    GuestCommands::Expire { name, time } => {
+      if !guest::expire(name, time) {
+        std::process::exit(1);
+      }
+    }
+    GuestCommands::Reap {
+      grace,
+      dry_run,
+      print_units,
+    } => {
+      let success = if *print_units {
+        guest::print_reap_units(grace.as_deref())
+      } else {
+        guest::reap(grace.as_deref(), *dry_run)
+      };
+      if !success {
+        std::process::exit(1);
+      }
+    }
+  }
+}
+
+fn handle_perm_commands(cmd: &PermCommands) {
+  match cmd {
+    PermCommands::Set {
+      target,
+      permissions,
+      recursive,
+      follow_symlinks,
+      owner,
+      files_only,
+      dirs_only,
+    } => {
+      let success = perm::set(
+        target,
+        permissions.as_deref(),
+        *recursive,
+        *follow_symlinks,
+        owner.as_deref(),
+        files_only.as_deref(),
+        dirs_only.as_deref(),
+      );
+      if !success {
+        std::process::exit(1);
+      }
+    }
+    PermCommands::Get { target, format } => {
+      if !perm::get(target, &config::output_format(format)) {
         std::process::exit(1);
       }
     }
+    PermCommands::Check {
+      username,
+      target,
+      as_groups,
+    } => {
+      if !perm::check(username, target, as_groups) {
+        std::process::exit(1);
+      }
+    }
+    PermCommands::Acl(cmd) => handle_acl_commands(cmd),
+    PermCommands::Sudo {
+      command,
+      username,
+      action,
+    } => {
+      let success = match (command, username, action) {
+        (Some(SudoCommands::Status { username }), _, _) => sudo::status(username),
+        (
+          Some(SudoCommands::Grant {
+            subject,
+            commands,
+            runas,
+            nopasswd,
+            hosts,
+          }),
+          _,
+          _,
+        ) => sudo::grant(subject, commands, runas.as_deref(), *nopasswd, hosts),
+        (Some(SudoCommands::List { format }), _, _) => sudo::list(&config::output_format(format)),
+        (Some(SudoCommands::Audit { username, format }), _, _) => {
+          sudo::audit(username.as_deref(), &config::output_format(format))
+        }
+        (Some(SudoCommands::Revoke { id }), _, _) => sudo::revoke(*id),
+        (None, Some(username), Some(SudoAction::Enable)) => sudo::enable(username),
+        (None, Some(username), Some(SudoAction::Disable)) => sudo::disable(username),
+        // clap requires the username and action without a subcommand.
+        _ => unreachable!(),
+      };
+      if !success {
+        std::process::exit(1);
+      }
+    }
+  }
+}
+
+fn handle_acl_commands(cmd: &AclCommands) {
+  let success = match cmd {
+    AclCommands::Add {
+      target,
+      entries,
+      default,
+      recursive,
+    } => perm::acl_add(target, entries, *default, *recursive),
+    AclCommands::Remove {
+      target,
+      entries,
+      default,
+      recursive,
+    } => perm::acl_remove(target, entries, *default, *recursive),
+    AclCommands::List { target, format } => perm::acl_list(target, &config::output_format(format)),
+  };
+  if !success {
+    std::process::exit(1);
+  }
+}
+
+fn handle_config_commands(cmd: &ConfigCommands) {
+  let success = match cmd {
+    ConfigCommands::Set { key, value, user } => config::set(key, value, *user),
+    ConfigCommands::Get { key } => config::get(key),
+    ConfigCommands::List {
+      format,
+      show_origin,
+    } => config::list(&config::output_format(format), *show_origin),
+    ConfigCommands::Reset { key, user } => config::reset(key.as_deref(), *user),
+    ConfigCommands::Profile(ProfileCommands::List { format }) => {
+      config::profile_list(&config::output_format(format))
+    }
+    ConfigCommands::Profile(ProfileCommands::Show { name }) => config::profile_show(name),
+  };
+  if !success {
+    std::process::exit(1);
+  }
+}
+
+fn handle_backup_commands(cmd: &BackupCommands) {
+  let success = match cmd {
+    BackupCommands::List { format } => backup::list(&config::output_format(format)),
+    BackupCommands::Diff { id } => backup::diff(id),
+  };
+  if !success {
+    std::process::exit(1);
   }
 }
@@ -0,0 +1,1532 @@
+use crate::config::Config;
+use crate::db::{self, Database, GroupEntry, GshadowEntry, PasswdEntry, ShadowEntry};
+use crate::sudo::SudoRule;
+use crate::{guest, role, sudo, time, user};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::ffi::CString;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+pub(crate) const MANAGED_STORE_PATH: &str = "/var/lib/userkit/managed.toml";
+
+/// Accounts, roles and sudo rules a host should have, as read from a
+/// manifest file.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Manifest {
+  #[serde(rename = "group", skip_serializing_if = "Vec::is_empty")]
+  groups: Vec<GroupSpec>,
+  #[serde(rename = "user", skip_serializing_if = "Vec::is_empty")]
+  users: Vec<UserSpec>,
+  #[serde(rename = "role", skip_serializing_if = "Vec::is_empty")]
+  roles: Vec<RoleSpec>,
+  #[serde(rename = "sudo", skip_serializing_if = "Vec::is_empty")]
+  sudo: Vec<SudoSpec>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GroupSpec {
+  name: String,
+  /// Allocated from the UID range when unset
+  #[serde(skip_serializing_if = "Option::is_none")]
+  gid: Option<u32>,
+}
+
+/// A user account. Attributes left out are not managed: an existing account
+/// keeps its own, and a new one gets the configured defaults.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UserSpec {
+  name: String,
+  /// Allocated from the UID range when unset
+  #[serde(skip_serializing_if = "Option::is_none")]
+  uid: Option<u32>,
+  /// Primary group; a private group named after the user when unset
+  #[serde(skip_serializing_if = "Option::is_none")]
+  group: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  gecos: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  home: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  shell: Option<String>,
+  /// Password hash as stored in shadow
+  #[serde(skip_serializing_if = "Option::is_none")]
+  password: Option<String>,
+  /// Exactly the supplementary groups the user is in, apart from those
+  /// roles and `perm sudo enable` put it in
+  #[serde(skip_serializing_if = "Option::is_none")]
+  groups: Option<Vec<String>>,
+  /// Exactly the roles assigned to the user, apart from time-bound ones
+  #[serde(skip_serializing_if = "Option::is_none")]
+  roles: Option<Vec<String>>,
+  /// Exactly the keys in the user's authorized_keys
+  #[serde(skip_serializing_if = "Option::is_none")]
+  ssh_keys: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RoleSpec {
+  name: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  description: Option<String>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  inherits: Vec<String>,
+  #[serde(default)]
+  permissions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+struct SudoSpec {
+  /// A username or `%group`
+  subject: String,
+  commands: Vec<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  runas: Option<String>,
+  #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+  nopasswd: bool,
+  /// All hosts when empty
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  hosts: Vec<String>,
+}
+
+impl SudoSpec {
+  fn hosts(&self) -> Vec<String> {
+    if self.hosts.is_empty() {
+      vec![String::from("ALL")]
+    } else {
+      self.hosts.clone()
+    }
+  }
+
+  fn matches(&self, rule: &SudoRule) -> bool {
+    rule.subject == self.subject
+      && rule.commands == self.commands
+      && rule.runas == self.runas
+      && rule.nopasswd == self.nopasswd
+      && rule.hosts == self.hosts()
+  }
+
+  fn to_rule(&self) -> SudoRule {
+    SudoRule {
+      id: 0,
+      subject: self.subject.clone(),
+      hosts: self.hosts(),
+      runas: self.runas.clone(),
+      nopasswd: self.nopasswd,
+      commands: self.commands.clone(),
+      role: None,
+    }
+  }
+}
+
+/// What `apply` has created or adopted, so that `--prune` only ever removes
+/// those.
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ManagedStore {
+  users: Vec<String>,
+  groups: Vec<String>,
+  roles: Vec<String>,
+  sudo_rules: Vec<u32>,
+}
+
+impl ManagedStore {
+  fn load() -> Result<Self, String> {
+    let path = crate::root::path(MANAGED_STORE_PATH);
+    match fs::read_to_string(&path) {
+      Ok(content) => {
+        toml::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+      }
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ManagedStore::default()),
+      Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+  }
+
+  fn save(&self) -> Result<(), String> {
+    crate::backup::before_write()?;
+    let path = crate::root::path(MANAGED_STORE_PATH);
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let content = toml::to_string_pretty(self)
+      .map_err(|e| format!("Failed to serialize managed accounts: {}", e))?;
+    fs::write(&path, content).map_err(|e| format!("Failed to write to {}: {}", path.display(), e))
+  }
+}
+
+/// A user account to create, with defaults filled in.
+struct NewUser {
+  name: String,
+  uid: Option<u32>,
+  /// Existing or planned primary group; a private group when unset
+  group: Option<String>,
+  gecos: String,
+  home: String,
+  shell: String,
+  password: Option<String>,
+}
+
+#[derive(Clone, Copy)]
+enum UserField {
+  Gecos,
+  Home,
+  Shell,
+  Group,
+}
+
+impl UserField {
+  fn name(self) -> &'static str {
+    match self {
+      UserField::Gecos => "gecos",
+      UserField::Home => "home",
+      UserField::Shell => "shell",
+      UserField::Group => "group",
+    }
+  }
+}
+
+/// One step of bringing the system in line with a manifest.
+enum Change {
+  AddGroup {
+    name: String,
+    gid: Option<u32>,
+  },
+  AddUser(NewUser),
+  ModifyUser {
+    name: String,
+    field: UserField,
+    from: String,
+    to: String,
+  },
+  SetPassword {
+    name: String,
+    hash: String,
+  },
+  AddMember {
+    user: String,
+    group: String,
+  },
+  RemoveMember {
+    user: String,
+    group: String,
+  },
+  AddKey {
+    user: String,
+    key: String,
+  },
+  RemoveKey {
+    user: String,
+    key: String,
+  },
+  AddRole {
+    name: String,
+    description: Option<String>,
+    inherits: Vec<String>,
+  },
+  UpdateRole {
+    name: String,
+    description: Option<String>,
+    inherits: Vec<String>,
+  },
+  AddPermission {
+    role: String,
+    permission: String,
+  },
+  RemovePermission {
+    role: String,
+    permission: String,
+  },
+  Assign {
+    role: String,
+    user: String,
+  },
+  Revoke {
+    role: String,
+    user: String,
+  },
+  AddSudo(SudoSpec),
+  RemoveSudo {
+    id: u32,
+    rule: String,
+  },
+  RemoveRole {
+    name: String,
+  },
+  RemoveUser {
+    name: String,
+  },
+  RemoveGroup {
+    name: String,
+  },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Action {
+  Add,
+  Change,
+  Remove,
+}
+
+impl Change {
+  fn action(&self) -> Action {
+    match self {
+      Change::AddGroup { .. }
+      | Change::AddUser(_)
+      | Change::AddMember { .. }
+      | Change::AddKey { .. }
+      | Change::AddRole { .. }
+      | Change::AddPermission { .. }
+      | Change::Assign { .. }
+      | Change::AddSudo(_) => Action::Add,
+      Change::ModifyUser { .. } | Change::SetPassword { .. } | Change::UpdateRole { .. } => {
+        Action::Change
+      }
+      Change::RemoveMember { .. }
+      | Change::RemoveKey { .. }
+      | Change::RemovePermission { .. }
+      | Change::Revoke { .. }
+      | Change::RemoveSudo { .. }
+      | Change::RemoveRole { .. }
+      | Change::RemoveUser { .. }
+      | Change::RemoveGroup { .. } => Action::Remove,
+    }
+  }
+}
+
+impl fmt::Display for Change {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    let symbol = match self.action() {
+      Action::Add => '+',
+      Action::Change => '~',
+      Action::Remove => '-',
+    };
+    write!(f, "{} ", symbol)?;
+    match self {
+      Change::AddGroup { name, gid } => match gid {
+        Some(gid) => write!(f, "group {} (GID {})", name, gid),
+        None => write!(f, "group {}", name),
+      },
+      Change::AddUser(user) => {
+        write!(f, "user {}", user.name)?;
+        if let Some(uid) = user.uid {
+          write!(f, " (UID {})", uid)?;
+        }
+        write!(f, ", home {}, shell {}", user.home, user.shell)
+      }
+      Change::ModifyUser {
+        name,
+        field,
+        from,
+        to,
+      } => write!(f, "user {}: {} {} -> {}", name, field.name(), from, to),
+      Change::SetPassword { name, .. } => write!(f, "user {}: password", name),
+      Change::AddMember { user, group } | Change::RemoveMember { user, group } => {
+        write!(f, "user {} in group {}", user, group)
+      }
+      Change::AddKey { user, key } | Change::RemoveKey { user, key } => {
+        write!(f, "ssh key {} for user {}", key_summary(key), user)
+      }
+      Change::AddRole {
+        name,
+        description: _,
+        inherits,
+      } => {
+        write!(f, "role {}", name)?;
+        if !inherits.is_empty() {
+          write!(f, " (inherits {})", inherits.join(", "))?;
+        }
+        Ok(())
+      }
+      Change::UpdateRole { name, inherits, .. } => {
+        write!(f, "role {}: description and parents", name)?;
+        if !inherits.is_empty() {
+          write!(f, " (inherits {})", inherits.join(", "))?;
+        }
+        Ok(())
+      }
+      Change::AddPermission { role, permission }
+      | Change::RemovePermission { role, permission } => {
+        write!(f, "permission {} on role {}", permission, role)
+      }
+      Change::Assign { role, user } | Change::Revoke { role, user } => {
+        write!(f, "role {} for user {}", role, user)
+      }
+      Change::AddSudo(spec) => write!(f, "sudo rule {}", sudo::render_rule(&spec.to_rule())),
+      Change::RemoveSudo { id, rule } => write!(f, "sudo rule {}: {}", id, rule),
+      Change::RemoveRole { name } => write!(f, "role {}", name),
+      Change::RemoveUser { name } => write!(f, "user {}", name),
+      Change::RemoveGroup { name } => write!(f, "group {}", name),
+    }
+  }
+}
+
+/// Whether a manifest file is YAML rather than TOML, going by its name.
+fn is_yaml(path: &Path) -> bool {
+  path
+    .extension()
+    .is_some_and(|ext| ext == "yaml" || ext == "yml")
+}
+
+impl Manifest {
+  fn load(path: &Path) -> Result<Self, String> {
+    let content =
+      fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if is_yaml(path) {
+      serde_yaml_ng::from_str(&content)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+    } else {
+      toml::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+    }
+  }
+
+  /// Checks the manifest on its own and against what exists, before any
+  /// change is planned.
+  fn validate(&self, db: &Database, roles: &[role::Role]) -> Result<(), String> {
+    let names = |kind: &str, names: Vec<&str>| -> Result<(), String> {
+      let mut seen = BTreeSet::new();
+      for name in names {
+        if !db::is_valid_name(name) {
+          return Err(format!("Invalid {} name '{}'", kind, name));
+        }
+        if !seen.insert(name) {
+          return Err(format!(
+            "{} {} is listed more than once",
+            capitalize(kind),
+            name
+          ));
+        }
+      }
+      Ok(())
+    };
+    names(
+      "group",
+      self.groups.iter().map(|g| g.name.as_str()).collect(),
+    )?;
+    names("user", self.users.iter().map(|u| u.name.as_str()).collect())?;
+    names("role", self.roles.iter().map(|r| r.name.as_str()).collect())?;
+
+    let group_exists =
+      |name: &str| db.group(name).is_some() || self.groups.iter().any(|g| g.name == name);
+    let role_exists = |name: &str| {
+      roles.iter().any(|r| r.name == name) || self.roles.iter().any(|r| r.name == name)
+    };
+
+    for user in &self.users {
+      for group in user.group.iter().chain(user.groups.iter().flatten()) {
+        if !group_exists(group) {
+          return Err(format!(
+            "Group {} of user {} does not exist",
+            group, user.name
+          ));
+        }
+      }
+      if let Some(role) = user.roles.iter().flatten().find(|r| !role_exists(r)) {
+        return Err(format!(
+          "Role {} of user {} does not exist",
+          role, user.name
+        ));
+      }
+      for path in user.home.iter().chain(&user.shell) {
+        if !path.starts_with('/') || !db::is_valid_field(path) {
+          return Err(format!(
+            "Invalid path {} for user {}: use an absolute path",
+            path, user.name
+          ));
+        }
+      }
+      if let Some(gecos) = &user.gecos
+        && !db::is_valid_field(gecos)
+      {
+        return Err(format!("Invalid GECOS field for user {}", user.name));
+      }
+      if let Some(password) = &user.password
+        && (password.is_empty() || !db::is_valid_field(password))
+      {
+        return Err(format!("Invalid password hash for user {}", user.name));
+      }
+      // A newline would smuggle a second key, with options of its own, into
+      // authorized_keys
+      if let Some(key) = user
+        .ssh_keys
+        .iter()
+        .flatten()
+        .find(|k| key_id(k).is_none() || k.contains(['\n', '\r']))
+      {
+        return Err(format!(
+          "Invalid SSH key for user {}: {}",
+          user.name,
+          key_summary(key)
+        ));
+      }
+    }
+
+    for spec in &self.roles {
+      if let Some(parent) = spec.inherits.iter().find(|r| !role_exists(r)) {
+        return Err(format!(
+          "Role {} inherits from {}, which does not exist",
+          spec.name, parent
+        ));
+      }
+    }
+    self.role_order()?;
+
+    for spec in &self.sudo {
+      if spec.commands.is_empty() {
+        return Err(format!("Sudo rule for {} has no commands", spec.subject));
+      }
+      sudo::validate_rule(&spec.to_rule())
+        .map_err(|e| format!("Sudo rule for {}: {}", spec.subject, e))?;
+    }
+    Ok(())
+  }
+
+  /// The manifest's roles with parents before the roles inheriting from them.
+  fn role_order(&self) -> Result<Vec<&RoleSpec>, String> {
+    fn visit<'a>(
+      manifest: &'a Manifest,
+      spec: &'a RoleSpec,
+      path: &mut Vec<String>,
+      order: &mut Vec<&'a RoleSpec>,
+    ) -> Result<(), String> {
+      if let Some(start) = path.iter().position(|name| *name == spec.name) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(spec.name.clone());
+        return Err(format!("Role inheritance cycle: {}", cycle.join(" -> ")));
+      }
+      if order.iter().any(|r| r.name == spec.name) {
+        return Ok(());
+      }
+      path.push(spec.name.clone());
+      for parent in &spec.inherits {
+        if let Some(parent) = manifest.roles.iter().find(|r| r.name == *parent) {
+          visit(manifest, parent, path, order)?;
+        }
+      }
+      path.pop();
+      order.push(spec);
+      Ok(())
+    }
+
+    let mut order = Vec::new();
+    for spec in &self.roles {
+      visit(self, spec, &mut Vec::new(), &mut order)?;
+    }
+    Ok(order)
+  }
+}
+
+fn capitalize(text: &str) -> String {
+  let mut chars = text.chars();
+  chars
+    .next()
+    .map_or_else(String::new, |c| c.to_uppercase().chain(chars).collect())
+}
+
+/// The type and base64 data of an authorized_keys line, which identify the
+/// key whatever options and comment it has.
+fn key_id(line: &str) -> Option<String> {
+  let fields: Vec<&str> = line.split_whitespace().collect();
+  let at = fields.iter().position(|field| {
+    field.starts_with("ssh-") || field.starts_with("ecdsa-") || field.starts_with("sk-")
+  })?;
+  let data = fields.get(at + 1)?;
+  Some(format!("{} {}", fields[at], data))
+}
+
+/// A key shortened for display: its type, the end of its data and comment.
+fn key_summary(line: &str) -> String {
+  let fields: Vec<&str> = line.split_whitespace().collect();
+  match key_id(line) {
+    Some(id) => {
+      let (kind, data) = id.split_once(' ').unwrap_or((&id, ""));
+      let tail = &data[data.len().saturating_sub(8)..];
+      let comment = fields
+        .iter()
+        .skip_while(|field| **field != data)
+        .skip(1)
+        .copied()
+        .collect::<Vec<_>>()
+        .join(" ");
+      format!("{} ...{} {}", kind, tail, comment)
+        .trim_end()
+        .to_string()
+    }
+    None => line.chars().take(40).collect(),
+  }
+}
+
+const SSH_DIR: &str = ".ssh";
+const AUTHORIZED_KEYS: &str = "authorized_keys";
+
+/// Opens `path` relative to `dir`, or as given without one, never following
+/// a symlink in its last component.
+fn open_nofollow(
+  dir: Option<&OwnedFd>,
+  path: &Path,
+  flags: libc::c_int,
+  mode: libc::mode_t,
+) -> std::io::Result<OwnedFd> {
+  let path_c = CString::new(path.as_os_str().as_bytes())
+    .map_err(|_| std::io::Error::from(std::io::ErrorKind::InvalidInput))?;
+  let dirfd = dir.map_or(libc::AT_FDCWD, |dir| dir.as_raw_fd());
+  // SAFETY: the path is NUL-terminated and dirfd is open or AT_FDCWD.
+  let fd = unsafe {
+    libc::openat(
+      dirfd,
+      path_c.as_ptr(),
+      flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+      libc::c_uint::from(mode),
+    )
+  };
+  if fd < 0 {
+    return Err(std::io::Error::last_os_error());
+  }
+  // SAFETY: fd was just opened and nothing else owns it.
+  Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Opens the user's `~/.ssh` without following symlinks. The user controls
+/// that directory, so one that is a symlink or belongs to someone else is
+/// refused rather than letting root write through it. With `create`, a
+/// missing directory is made and given to the user.
+fn open_ssh_dir(user: &PasswdEntry, create: bool) -> Result<Option<OwnedFd>, String> {
+  let path = crate::root::path(&user.home).join(SSH_DIR);
+  let home = match open_home(user) {
+    Ok(home) => home,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound && !create => return Ok(None),
+    Err(e) => return Err(format!("Failed to open {}: {}", user.home, e)),
+  };
+  let mut created = false;
+  if create {
+    let name = CString::new(SSH_DIR).unwrap_or_default();
+    // SAFETY: home is an open directory and the name is NUL-terminated.
+    if unsafe { libc::mkdirat(home.as_raw_fd(), name.as_ptr(), 0o700) } == 0 {
+      created = true;
+    } else {
+      let e = std::io::Error::last_os_error();
+      if e.kind() != std::io::ErrorKind::AlreadyExists {
+        return Err(format!("Failed to create {}: {}", path.display(), e));
+      }
+    }
+  }
+  let dir = match open_nofollow(
+    Some(&home),
+    Path::new(SSH_DIR),
+    libc::O_RDONLY | libc::O_DIRECTORY,
+    0,
+  ) {
+    Ok(dir) => File::from(dir),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+    Err(e) if matches!(e.raw_os_error(), Some(libc::ELOOP | libc::ENOTDIR)) => {
+      return Err(format!(
+        "Refusing to use {}: it is a symlink or not a directory",
+        path.display()
+      ));
+    }
+    Err(e) => return Err(format!("Failed to open {}: {}", path.display(), e)),
+  };
+  let meta = dir
+    .metadata()
+    .map_err(|e| format!("Failed to stat {}: {}", path.display(), e))?;
+  if created {
+    std::os::unix::fs::fchown(&dir, Some(user.uid), Some(user.gid))
+      .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+  } else if meta.uid() != user.uid {
+    return Err(format!(
+      "Refusing to use {}: it is not owned by {}",
+      path.display(),
+      user.name
+    ));
+  }
+  Ok(Some(OwnedFd::from(dir)))
+}
+
+fn open_home(user: &PasswdEntry) -> std::io::Result<OwnedFd> {
+  // The home path comes from passwd, so only what is inside it is the
+  // user's to change.
+  let home = fs::File::open(crate::root::path(&user.home))?;
+  if !home.metadata()?.is_dir() {
+    return Err(std::io::Error::from(std::io::ErrorKind::NotADirectory));
+  }
+  Ok(OwnedFd::from(home))
+}
+
+fn read_keys(user: &PasswdEntry) -> Result<Vec<String>, String> {
+  let path = crate::root::path(&user.home)
+    .join(SSH_DIR)
+    .join(AUTHORIZED_KEYS);
+  let Some(dir) = open_ssh_dir(user, false)? else {
+    return Ok(Vec::new());
+  };
+  let file = match open_nofollow(
+    Some(&dir),
+    Path::new(AUTHORIZED_KEYS),
+    libc::O_RDONLY | libc::O_NONBLOCK,
+    0,
+  ) {
+    Ok(file) => File::from(file),
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+    Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+  };
+  let mut content = String::new();
+  file
+    .metadata()
+    .and_then(|meta| {
+      if meta.is_file() {
+        Ok(())
+      } else {
+        Err(std::io::Error::other("not a regular file"))
+      }
+    })
+    .and_then(|()| (&file).read_to_string(&mut content))
+    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+  Ok(content.lines().map(str::to_string).collect())
+}
+
+/// Works out the changes that bring the system in line with the manifest,
+/// in the order they have to be made.
+fn plan(manifest: &Manifest, prune: bool) -> Result<Vec<Change>, String> {
+  let db = Database::load()?;
+  let defaults = Config::load()?.default;
+  let roles = role::roles()?;
+  let rules = sudo::granted_rules()?;
+  let managed = ManagedStore::load()?;
+  manifest.validate(&db, &roles)?;
+
+  let mut changes = Vec::new();
+
+  for spec in &manifest.groups {
+    match db.group(&spec.name) {
+      None => {
+        if let Some(gid) = spec.gid
+          && let Some(owner) = db.groups().into_iter().find(|g| g.gid == gid)
+        {
+          return Err(format!(
+            "GID {} of group {} is taken by group {}",
+            gid, spec.name, owner.name
+          ));
+        }
+        changes.push(Change::AddGroup {
+          name: spec.name.clone(),
+          gid: spec.gid,
+        });
+      }
+      Some(group) => {
+        if let Some(gid) = spec.gid.filter(|gid| *gid != group.gid) {
+          return Err(format!(
+            "Group {} has GID {}, not {}; change it by hand first",
+            spec.name, group.gid, gid
+          ));
+        }
+      }
+    }
+  }
+
+  let mut memberships = Vec::new();
+  let mut keys = Vec::new();
+  for spec in &manifest.users {
+    let existing = db.user(&spec.name);
+    match &existing {
+      None => {
+        if let Some(uid) = spec.uid
+          && let Some(owner) = db.users().into_iter().find(|u| u.uid == uid)
+        {
+          return Err(format!(
+            "UID {} of user {} is taken by user {}",
+            uid, spec.name, owner.name
+          ));
+        }
+        changes.push(Change::AddUser(NewUser {
+          name: spec.name.clone(),
+          uid: spec.uid,
+          group: spec.group.clone(),
+          gecos: spec.gecos.clone().unwrap_or_default(),
+          home: spec.home.clone().unwrap_or_else(|| {
+            Path::new(&defaults.home_base)
+              .join(&spec.name)
+              .to_string_lossy()
+              .into_owned()
+          }),
+          shell: spec.shell.clone().unwrap_or_else(|| defaults.shell.clone()),
+          password: spec.password.clone(),
+        }));
+      }
+      Some(current) => {
+        if let Some(uid) = spec.uid.filter(|uid| *uid != current.uid) {
+          return Err(format!(
+            "User {} has UID {}, not {}; change it by hand first",
+            spec.name, current.uid, uid
+          ));
+        }
+        let current_group = db
+          .groups()
+          .into_iter()
+          .find(|g| g.gid == current.gid)
+          .map_or_else(|| current.gid.to_string(), |g| g.name);
+        for (field, from, to) in [
+          (UserField::Gecos, &current.gecos, &spec.gecos),
+          (UserField::Home, &current.home, &spec.home),
+          (UserField::Shell, &current.shell, &spec.shell),
+          (UserField::Group, &current_group, &spec.group),
+        ] {
+          if let Some(to) = to.as_ref().filter(|to| *to != from) {
+            changes.push(Change::ModifyUser {
+              name: spec.name.clone(),
+              field,
+              from: from.clone(),
+              to: to.clone(),
+            });
+          }
+        }
+        if let Some(hash) = &spec.password
+          && db
+            .shadow_entry(&spec.name)
+            .is_none_or(|entry| entry.password != *hash)
+        {
+          changes.push(Change::SetPassword {
+            name: spec.name.clone(),
+            hash: hash.clone(),
+          });
+        }
+      }
+    }
+
+    if let Some(groups) = &spec.groups {
+      let current: Vec<String> = db
+        .supplementary_groups(&spec.name)
+        .into_iter()
+        .map(|g| g.name)
+        .collect();
+      for group in groups.iter().filter(|g| !current.contains(g)) {
+        memberships.push(Change::AddMember {
+          user: spec.name.clone(),
+          group: group.clone(),
+        });
+      }
+      let mut held = role::held_groups(&spec.name)?;
+      held.extend(sudo::held_groups(&spec.name)?);
+      for group in current
+        .iter()
+        .filter(|g| !groups.contains(g) && !held.contains(g))
+      {
+        memberships.push(Change::RemoveMember {
+          user: spec.name.clone(),
+          group: group.clone(),
+        });
+      }
+    }
+
+    if let Some(wanted) = &spec.ssh_keys {
+      let current = match &existing {
+        Some(current) => read_keys(current)?,
+        None => Vec::new(),
+      };
+      let current_ids: Vec<Option<String>> = current.iter().map(|k| key_id(k)).collect();
+      let wanted_ids: Vec<Option<String>> = wanted.iter().map(|k| key_id(k)).collect();
+      for (key, id) in wanted.iter().zip(&wanted_ids) {
+        if !current_ids.contains(id) {
+          keys.push(Change::AddKey {
+            user: spec.name.clone(),
+            key: key.clone(),
+          });
+        }
+      }
+      for (key, id) in current.iter().zip(&current_ids) {
+        if id.is_some() && !wanted_ids.contains(id) {
+          keys.push(Change::RemoveKey {
+            user: spec.name.clone(),
+            key: key.clone(),
+          });
+        }
+      }
+    }
+  }
+  changes.extend(memberships);
+  changes.extend(keys);
+
+  let mut permissions = Vec::new();
+  for spec in manifest.role_order()? {
+    let current = roles.iter().find(|r| r.name == spec.name);
+    let (have, add) = match current {
+      None => {
+        changes.push(Change::AddRole {
+          name: spec.name.clone(),
+          description: spec.description.clone(),
+          inherits: spec.inherits.clone(),
+        });
+        (&[][..], true)
+      }
+      Some(current) => {
+        if current.description != spec.description || current.inherits != spec.inherits {
+          changes.push(Change::UpdateRole {
+            name: spec.name.clone(),
+            description: spec.description.clone(),
+            inherits: spec.inherits.clone(),
+          });
+        }
+        (&current.permissions[..], false)
+      }
+    };
+    for permission in spec.permissions.iter().filter(|p| add || !have.contains(p)) {
+      permissions.push(Change::AddPermission {
+        role: spec.name.clone(),
+        permission: permission.clone(),
+      });
+    }
+    for permission in have.iter().filter(|p| !spec.permissions.contains(p)) {
+      permissions.push(Change::RemovePermission {
+        role: spec.name.clone(),
+        permission: permission.clone(),
+      });
+    }
+  }
+  changes.extend(permissions);
+
+  for spec in &manifest.users {
+    let Some(wanted) = &spec.roles else {
+      continue;
+    };
+    let assigned = |role: &role::Role| role.users.contains(&spec.name);
+    for name in wanted {
+      if !roles.iter().any(|r| r.name == *name && assigned(r)) {
+        changes.push(Change::Assign {
+          role: name.clone(),
+          user: spec.name.clone(),
+        });
+      }
+    }
+    for role in roles.iter().filter(|r| assigned(r)) {
+      if !wanted.contains(&role.name) && !role.expires.contains_key(&spec.name) {
+        changes.push(Change::Revoke {
+          role: role.name.clone(),
+          user: spec.name.clone(),
+        });
+      }
+    }
+  }
+
+  for spec in &manifest.sudo {
+    if !rules.iter().any(|rule| spec.matches(rule)) {
+      changes.push(Change::AddSudo(spec.clone()));
+    }
+  }
+
+  if prune {
+    for rule in &rules {
+      if managed.sudo_rules.contains(&rule.id) && !manifest.sudo.iter().any(|s| s.matches(rule)) {
+        changes.push(Change::RemoveSudo {
+          id: rule.id,
+          rule: sudo::render_rule(rule),
+        });
+      }
+    }
+    let pruned_users: Vec<&String> = managed
+      .users
+      .iter()
+      .filter(|name| !manifest.users.iter().any(|u| u.name == **name))
+      .filter(|name| db.user(name).is_some())
+      .collect();
+    for name in &pruned_users {
+      for role in roles.iter().filter(|r| r.users.contains(name)) {
+        changes.push(Change::Revoke {
+          role: role.name.clone(),
+          user: name.to_string(),
+        });
+      }
+    }
+    for name in &managed.roles {
+      if !manifest.roles.iter().any(|r| r.name == *name) && roles.iter().any(|r| r.name == *name) {
+        changes.push(Change::RemoveRole { name: name.clone() });
+      }
+    }
+    for name in pruned_users {
+      changes.push(Change::RemoveUser { name: name.clone() });
+    }
+    for name in &managed.groups {
+      if manifest.groups.iter().any(|g| g.name == *name) {
+        continue;
+      }
+      let Some(group) = db.group(name) else {
+        continue;
+      };
+      let primary_of = db.users().into_iter().find(|u| {
+        u.gid == group.gid
+          && !changes
+            .iter()
+            .any(|c| matches!(c, Change::RemoveUser { name } if *name == u.name))
+      });
+      match primary_of {
+        Some(user) => eprintln!(
+          "Warning: Group {} is no longer in the manifest but is the primary group of {}; keeping it",
+          name, user.name
+        ),
+        None => changes.push(Change::RemoveGroup { name: name.clone() }),
+      }
+    }
+  }
+
+  Ok(changes)
+}
+
+fn print_plan(changes: &[Change]) {
+  if changes.is_empty() {
+    println!("No changes. The system matches the manifest.");
+    return;
+  }
+  println!("Planned changes:");
+  for change in changes {
+    println!("  {}", change);
+  }
+  let count = |action| changes.iter().filter(|c| c.action() == action).count();
+  println!(
+    "Plan: {} to add, {} to change, {} to remove.",
+    count(Action::Add),
+    count(Action::Change),
+    count(Action::Remove)
+  );
+}
+
+/// Shows what `apply` would change without changing anything.
+pub(crate) fn show_plan(path: &Path, prune: bool) -> bool {
+  match Manifest::load(path).and_then(|manifest| plan(&manifest, prune)) {
+    Ok(changes) => {
+      print_plan(&changes);
+      true
+    }
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      false
+    }
+  }
+}
+
+/// Brings the system in line with the manifest, stopping at the first
+/// change that fails. Everything in the manifest is then recorded as
+/// managed.
+pub(crate) fn apply(path: &Path, prune: bool) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+  let changes = match Manifest::load(path).and_then(|manifest| plan(&manifest, prune)) {
+    Ok(changes) => changes,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  print_plan(&changes);
+  if changes.is_empty() {
+    return true;
+  }
+
+  let mut done = 0;
+  for change in &changes {
+    if !execute(change) {
+      break;
+    }
+    done += 1;
+  }
+  if let Err(e) = record_managed(&changes[..done]) {
+    eprintln!("Error: {}", e);
+    return false;
+  }
+  if done < changes.len() {
+    eprintln!(
+      "Error: Apply stopped after {} of {} changes",
+      done,
+      changes.len()
+    );
+    return false;
+  }
+  println!("Apply complete: {} changes made.", done);
+  true
+}
+
+/// Makes one change, reporting the result. Role and sudo changes go through
+/// the commands that make them by hand.
+fn execute(change: &Change) -> bool {
+  let result = match change {
+    Change::AddRole {
+      name,
+      description,
+      inherits,
+    } => return role::create(name, description.as_deref(), inherits),
+    Change::UpdateRole {
+      name,
+      description,
+      inherits,
+    } => return role::redefine(name, description.as_deref(), inherits),
+    Change::AddPermission { role, permission } => return role::add_perm(role, permission),
+    Change::RemovePermission { role, permission } => return role::remove_perm(role, permission),
+    Change::Assign { role, user } => return role::assign(role, user, None, None),
+    Change::Revoke { role, user } => return role::revoke(role, user),
+    Change::RemoveRole { name } => return role::delete(name, true),
+    Change::AddSudo(spec) => {
+      return sudo::grant(
+        &spec.subject,
+        &spec.commands,
+        spec.runas.as_deref(),
+        spec.nopasswd,
+        &spec.hosts,
+      );
+    }
+    Change::RemoveSudo { id, .. } => return sudo::revoke(*id),
+    Change::AddKey { user, key } => update_keys(user, |keys| keys.push(key.clone()))
+      .map(|()| format!("SSH key {} added for user {}", key_summary(key), user)),
+    Change::RemoveKey { user, key } => update_keys(user, |keys| {
+      let id = key_id(key);
+      keys.retain(|k| key_id(k) != id);
+    })
+    .map(|()| format!("SSH key {} removed for user {}", key_summary(key), user)),
+    _ => execute_account(change),
+  };
+  match result {
+    Ok(message) => {
+      println!("{}", message);
+      true
+    }
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      false
+    }
+  }
+}
+
+/// Makes a change to the account databases in one locked transaction.
+fn execute_account(change: &Change) -> Result<String, String> {
+  let mut db = Database::open()?;
+  let message = match change {
+    Change::AddGroup { name, gid } => {
+      let defaults = Config::load()?.default;
+      let gid = match gid {
+        Some(gid) => *gid,
+        None => db
+          .next_free_id(defaults.uid_min, defaults.uid_max)
+          .ok_or_else(|| format!("No free GID available for group {}", name))?,
+      };
+      db.add_group(
+        &GroupEntry {
+          name: name.clone(),
+          password: String::from("x"),
+          gid,
+          members: Vec::new(),
+        },
+        &GshadowEntry::locked(name),
+      );
+      format!("Group {} created with GID {}", name, gid)
+    }
+    Change::AddUser(new) => return add_user(db, new),
+    Change::ModifyUser {
+      name, field, to, ..
+    } => {
+      let mut entry = db
+        .user(name)
+        .ok_or_else(|| format!("User {} does not exist", name))?;
+      match field {
+        UserField::Gecos => entry.gecos = to.clone(),
+        UserField::Home => entry.home = to.clone(),
+        UserField::Shell => entry.shell = to.clone(),
+        UserField::Group => {
+          entry.gid = db
+            .group(to)
+            .ok_or_else(|| format!("Group {} does not exist", to))?
+            .gid
+        }
+      }
+      db.set_user(&entry);
+      format!("User {} {} set to {}", name, field.name(), to)
+    }
+    Change::SetPassword { name, hash } => {
+      let mut entry = db
+        .shadow_entry(name)
+        .ok_or_else(|| format!("User {} has no shadow entry", name))?;
+      entry.password = hash.clone();
+      entry.last_change = Some(db::days_since_epoch());
+      db.set_shadow_entry(&entry);
+      format!("Password of user {} set", name)
+    }
+    Change::AddMember { user, group } => {
+      db.add_group_member(group, user);
+      format!("User {} added to group {}", user, group)
+    }
+    Change::RemoveMember { user, group } => {
+      db.remove_group_member(group, user);
+      format!("User {} removed from group {}", user, group)
+    }
+    Change::RemoveUser { name } => {
+      user::remove_account(&mut db, name);
+      format!("User {} removed; its home directory was kept", name)
+    }
+    Change::RemoveGroup { name } => {
+      db.remove_group(name);
+      format!("Group {} removed", name)
+    }
+    _ => unreachable!("not an account change"),
+  };
+  db.commit()?;
+  Ok(message)
+}
+
+fn add_user(mut db: Database, new: &NewUser) -> Result<String, String> {
+  let config = Config::load()?;
+  let uid = match new.uid {
+    Some(uid) => uid,
+    None => db
+      .next_free_id(config.default.uid_min, config.default.uid_max)
+      .ok_or_else(|| format!("No free UID available for user {}", new.name))?,
+  };
+  let gid = match &new.group {
+    Some(group) => {
+      db.group(group)
+        .ok_or_else(|| format!("Group {} does not exist", group))?
+        .gid
+    }
+    None => {
+      if db.group(&new.name).is_some() {
+        return Err(format!(
+          "Group {} already exists; set it as the primary group of user {}",
+          new.name, new.name
+        ));
+      }
+      if db.groups().iter().any(|g| g.gid == uid) {
+        return Err(format!(
+          "GID {} is taken, so user {} cannot get a private group with it",
+          uid, new.name
+        ));
+      }
+      db.add_group(
+        &GroupEntry {
+          name: new.name.clone(),
+          password: String::from("x"),
+          gid: uid,
+          members: Vec::new(),
+        },
+        &GshadowEntry::locked(&new.name),
+      );
+      uid
+    }
+  };
+
+  let policy = &config.password;
+  let shadow = ShadowEntry {
+    password: new.password.clone().unwrap_or_else(|| String::from("!")),
+    min_age: Some(policy.min_days.into()),
+    max_age: Some(policy.max_days.into()),
+    warn: Some(policy.warn_days.into()),
+    ..ShadowEntry::locked(&new.name)
+  };
+  db.add_user(
+    &PasswdEntry {
+      name: new.name.clone(),
+      password: String::from("x"),
+      uid,
+      gid,
+      gecos: new.gecos.clone(),
+      home: new.home.clone(),
+      shell: new.shell.clone(),
+    },
+    &shadow,
+  );
+  db.commit()?;
+  drop(db);
+
+  let home = crate::root::path(&new.home);
+  if !home.exists() {
+    user::create_home(&home, uid, gid, &crate::root::path(&config.default.skel))?;
+  }
+  Ok(format!("User {} created with UID {}", new.name, uid))
+}
+
+/// Rewrites a user's authorized_keys, creating it and `~/.ssh` owned by the
+/// user if needed. The new file is written next to the old one and renamed
+/// over it, all relative to the opened `~/.ssh`, so no symlink the user
+/// places there is followed.
+fn update_keys(username: &str, f: impl FnOnce(&mut Vec<String>)) -> Result<(), String> {
+  let user = Database::load()?
+    .user(username)
+    .ok_or_else(|| format!("User {} does not exist", username))?;
+  let mut keys = read_keys(&user)?;
+  f(&mut keys);
+
+  let path = crate::root::path(&user.home)
+    .join(SSH_DIR)
+    .join(AUTHORIZED_KEYS);
+  let dir =
+    open_ssh_dir(&user, true)?.ok_or_else(|| format!("Failed to create {}", path.display()))?;
+  let mut content = keys.join("\n");
+  if !content.is_empty() {
+    content.push('\n');
+  }
+
+  let temp = format!(".{}.userkit", AUTHORIZED_KEYS);
+  let temp_c = CString::new(temp.as_str()).unwrap_or_default();
+  let name_c = CString::new(AUTHORIZED_KEYS).unwrap_or_default();
+  let unlink_temp = || {
+    // SAFETY: dir is an open directory and the name is NUL-terminated.
+    unsafe { libc::unlinkat(dir.as_raw_fd(), temp_c.as_ptr(), 0) };
+  };
+  unlink_temp();
+  let write = || -> std::io::Result<()> {
+    let mut file = File::from(open_nofollow(
+      Some(&dir),
+      Path::new(&temp),
+      libc::O_WRONLY | libc::O_CREAT | libc::O_EXCL,
+      0o600,
+    )?);
+    file.write_all(content.as_bytes())?;
+    std::os::unix::fs::fchown(&file, Some(user.uid), Some(user.gid))?;
+    file.sync_all()?;
+    // SAFETY: dir is an open directory and both names are NUL-terminated.
+    let renamed = unsafe {
+      libc::renameat(
+        dir.as_raw_fd(),
+        temp_c.as_ptr(),
+        dir.as_raw_fd(),
+        name_c.as_ptr(),
+      )
+    };
+    if renamed != 0 {
+      return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+  };
+  write().map_err(|e| {
+    unlink_temp();
+    format!("Failed to write to {}: {}", path.display(), e)
+  })
+}
+
+/// Records what the changes made created as managed, and drops records of
+/// what no longer exists. Accounts, roles and rules that existed before an
+/// apply are never recorded, so pruning cannot remove them.
+fn record_managed(made: &[Change]) -> Result<(), String> {
+  let db = Database::load()?;
+  let roles = role::roles()?;
+  let rules = sudo::granted_rules()?;
+  let mut managed = ManagedStore::load()?;
+
+  let mut users: BTreeSet<String> = managed.users.drain(..).collect();
+  let mut groups: BTreeSet<String> = managed.groups.drain(..).collect();
+  let mut role_names: BTreeSet<String> = managed.roles.drain(..).collect();
+  let mut rule_ids: BTreeSet<u32> = managed.sudo_rules.drain(..).collect();
+  for change in made {
+    match change {
+      Change::AddUser(new) => {
+        users.insert(new.name.clone());
+      }
+      Change::AddGroup { name, .. } => {
+        groups.insert(name.clone());
+      }
+      Change::AddRole { name, .. } => {
+        role_names.insert(name.clone());
+      }
+      // No rule matched the spec before it was granted, so any that does
+      // now is the one just added.
+      Change::AddSudo(spec) => {
+        rule_ids.extend(
+          rules
+            .iter()
+            .filter(|rule| spec.matches(rule))
+            .map(|rule| rule.id),
+        );
+      }
+      _ => {}
+    }
+  }
+  users.retain(|name| db.user(name).is_some());
+  groups.retain(|name| db.group(name).is_some());
+  role_names.retain(|name| roles.iter().any(|r| r.name == *name));
+  rule_ids.retain(|id| rules.iter().any(|rule| rule.id == *id));
+
+  ManagedStore {
+    users: users.into_iter().collect(),
+    groups: groups.into_iter().collect(),
+    roles: role_names.into_iter().collect(),
+    sudo_rules: rule_ids.into_iter().collect(),
+  }
+  .save()
+}
+
+/// Describes the current accounts as a manifest. Guest accounts are left
+/// out, as are time-bound role assignments, which a manifest cannot express.
+fn snapshot(system: bool, passwords: bool) -> Result<Manifest, String> {
+  let db = Database::load()?;
+  let defaults = Config::load()?.default;
+  let range = db::id_range(defaults.uid_min, defaults.uid_max);
+  let guests = guest::guest_names()?;
+  let roles = role::roles()?;
+  let all_groups = db.groups();
+
+  let mut manifest = Manifest::default();
+  let mut private_groups = Vec::new();
+  for entry in db.users() {
+    if !(system || range.contains(&entry.uid)) || guests.contains(&entry.name) {
+      continue;
+    }
+    let group = match all_groups.iter().find(|g| g.gid == entry.gid) {
+      Some(g) if g.name == entry.name && g.gid == entry.uid && g.members.is_empty() => {
+        private_groups.push(g.name.clone());
+        None
+      }
+      Some(g) => Some(g.name.clone()),
+      None => {
+        eprintln!(
+          "Warning: Primary GID {} of user {} has no group; leaving the group out",
+          entry.gid, entry.name
+        );
+        None
+      }
+    };
+    let held = role::held_groups(&entry.name)?;
+    let groups: Vec<String> = db
+      .supplementary_groups(&entry.name)
+      .into_iter()
+      .map(|g| g.name)
+      .filter(|name| !held.contains(name))
+      .collect();
+    let user_roles: Vec<String> = roles
+      .iter()
+      .filter(|r| r.users.contains(&entry.name) && !r.expires.contains_key(&entry.name))
+      .map(|r| r.name.clone())
+      .collect();
+    let keys: Vec<String> = read_keys(&entry)?
+      .into_iter()
+      .filter(|k| key_id(k).is_some())
+      .collect();
+    let password = if passwords {
+      db.shadow_entry(&entry.name).map(|s| s.password)
+    } else {
+      None
+    };
+
+    manifest.users.push(UserSpec {
+      uid: Some(entry.uid),
+      group,
+      gecos: Some(entry.gecos).filter(|g| !g.is_empty()),
+      home: Some(entry.home),
+      shell: Some(entry.shell),
+      password,
+      groups: Some(groups).filter(|g| !g.is_empty()),
+      roles: Some(user_roles).filter(|r| !r.is_empty()),
+      ssh_keys: Some(keys).filter(|k| !k.is_empty()),
+      name: entry.name,
+    });
+  }
+
+  manifest.groups = all_groups
+    .into_iter()
+    .filter(|g| system || range.contains(&g.gid))
+    .filter(|g| !private_groups.contains(&g.name) && !guests.contains(&g.name))
+    .map(|g| GroupSpec {
+      name: g.name,
+      gid: Some(g.gid),
+    })
+    .collect();
+
+  manifest.roles = roles
+    .into_iter()
+    .map(|r| RoleSpec {
+      name: r.name,
+      description: r.description,
+      inherits: r.inherits,
+      permissions: r.permissions,
+    })
+    .collect();
+
+  manifest.sudo = sudo::granted_rules()?
+    .into_iter()
+    .map(|rule| SudoSpec {
+      subject: rule.subject,
+      commands: rule.commands,
+      runas: rule.runas,
+      nopasswd: rule.nopasswd,
+      hosts: if rule.hosts == ["ALL"] {
+        Vec::new()
+      } else {
+        rule.hosts
+      },
+    })
+    .collect();
+  Ok(manifest)
+}
+
+/// Writes the current accounts as a manifest `apply` can recreate them from,
+/// to `output` or standard output. Non-system accounts only unless `system`
+/// is set; password hashes only with `passwords`.
+pub(crate) fn export(output: Option<&Path>, system: bool, passwords: bool) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+  let result = snapshot(system, passwords).and_then(|manifest| {
+    let content = if output.is_some_and(is_yaml) {
+      serde_yaml_ng::to_string(&manifest).map_err(|e| e.to_string())
+    } else {
+      toml::to_string_pretty(&manifest).map_err(|e| e.to_string())
+    }
+    .map_err(|e| format!("Failed to serialize manifest: {}", e))?;
+    let content = format!(
+      "# Exported by userkit on {}\n\n{}",
+      time::format_timestamp(time::now()),
+      content
+    );
+    match output {
+      Some(path) => write_private(path, &content).map(|()| {
+        let count =
+          |n: usize, what: &str| format!("{} {}{}", n, what, if n == 1 { "" } else { "s" });
+        format!(
+          "Exported {}, {}, {} and {} to {}",
+          count(manifest.users.len(), "user"),
+          count(manifest.groups.len(), "group"),
+          count(manifest.roles.len(), "role"),
+          count(manifest.sudo.len(), "sudo rule"),
+          path.display()
+        )
+      }),
+      None => {
+        print!("{}", content);
+        Ok(String::new())
+      }
+    }
+  });
+  match result {
+    Ok(message) => {
+      if !message.is_empty() {
+        println!("{}", message);
+      }
+      true
+    }
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      false
+    }
+  }
+}
+
+/// Writes a file only root can read, as an export may hold password hashes.
+fn write_private(path: &Path, content: &str) -> Result<(), String> {
+  use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+  fs::OpenOptions::new()
+    .write(true)
+    .create(true)
+    .truncate(true)
+    .mode(0o600)
+    .open(path)
+    .and_then(|mut file| {
+      file.set_permissions(fs::Permissions::from_mode(0o600))?;
+      std::io::Write::write_all(&mut file, content.as_bytes())
+    })
+    .map_err(|e| format!("Failed to write to {}: {}", path.display(), e))
+}
@@ -0,0 +1,210 @@
+const S_ISUID: u32 = 0o4000;
+const S_ISGID: u32 = 0o2000;
+const S_ISVTX: u32 = 0o1000;
+
+const USER_BITS: u32 = 0o4700;
+const GROUP_BITS: u32 = 0o2070;
+const OTHER_BITS: u32 = 0o1007;
+const ALL_BITS: u32 = 0o7777;
+
+/// A file mode change in chmod syntax: octal (`2755`) or symbolic
+/// (`u+rwx,g-w,o=`).
+pub(crate) enum Mode {
+  Octal {
+    bits: u32,
+    /// Five digits given, so directory setuid/setgid bits are cleared too
+    explicit: bool,
+  },
+  Symbolic(Vec<Clause>),
+}
+
+pub(crate) struct Clause {
+  /// Mask of the permission classes the clause applies to; 0 when none was
+  /// given, which means all classes filtered by the umask
+  who: u32,
+  actions: Vec<Action>,
+}
+
+struct Action {
+  op: char,
+  perms: Perms,
+}
+
+enum Perms {
+  /// Any of r, w, x, X, s and t
+  Bits { bits: u32, conditional_x: bool },
+  /// Copy the bits of another class: u, g or o
+  Copy(char),
+}
+
+impl Mode {
+  pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+    if !spec.is_empty() && spec.chars().all(|c| c.is_ascii_digit()) {
+      let bits = u32::from_str_radix(spec, 8)
+        .ok()
+        .filter(|bits| *bits <= ALL_BITS && spec.len() <= 5)
+        .ok_or_else(|| format!("Invalid mode '{}'", spec))?;
+      return Ok(Mode::Octal {
+        bits,
+        explicit: spec.len() == 5,
+      });
+    }
+
+    spec
+      .split(',')
+      .map(|clause| parse_clause(clause).ok_or_else(|| format!("Invalid mode '{}'", spec)))
+      .collect::<Result<_, _>>()
+      .map(Mode::Symbolic)
+  }
+
+  /// Computes the new mode for a file whose current permission bits are
+  /// `current`. Like GNU chmod, directories keep their setuid and setgid
+  /// bits unless a symbolic mode or a five-digit octal mode says otherwise.
+  pub(crate) fn apply(&self, current: u32, is_dir: bool, umask: u32) -> u32 {
+    let current = current & ALL_BITS;
+    match self {
+      Mode::Octal { bits, explicit } => {
+        if is_dir && !explicit {
+          bits | (current & (S_ISUID | S_ISGID))
+        } else {
+          *bits
+        }
+      }
+      Mode::Symbolic(clauses) => clauses
+        .iter()
+        .fold(current, |mode, clause| clause.apply(mode, is_dir, umask)),
+    }
+  }
+}
+
+impl Clause {
+  fn apply(&self, mut mode: u32, is_dir: bool, umask: u32) -> u32 {
+    let (who, filter) = if self.who == 0 {
+      (USER_BITS | GROUP_BITS | OTHER_BITS, !umask & ALL_BITS)
+    } else {
+      (self.who, ALL_BITS)
+    };
+
+    for action in &self.actions {
+      let bits = match action.perms {
+        Perms::Bits {
+          bits,
+          conditional_x,
+        } => {
+          let x = conditional_x && (is_dir || mode & 0o111 != 0);
+          bits | if x { 0o111 } else { 0 }
+        }
+        Perms::Copy(class) => {
+          let rwx = match class {
+            'u' => (mode >> 6) & 0o7,
+            'g' => (mode >> 3) & 0o7,
+            _ => mode & 0o7,
+          };
+          rwx * 0o111
+        }
+      };
+      let bits = bits & who;
+
+      match action.op {
+        '+' => mode |= bits & filter,
+        '-' => mode &= !(bits & filter),
+        _ => {
+          // Directories keep setuid/setgid under '=' unless they are named.
+          let keep = if is_dir { S_ISUID | S_ISGID } else { 0 };
+          let cleared = who & 0o777 | (who & (S_ISUID | S_ISGID | S_ISVTX) & !keep);
+          mode = (mode & !cleared) | (bits & filter);
+        }
+      }
+    }
+    mode
+  }
+}
+
+fn parse_clause(clause: &str) -> Option<Clause> {
+  let split = clause
+    .find(|c: char| !"ugoa".contains(c))
+    .unwrap_or(clause.len());
+  let (who_part, mut rest) = clause.split_at(split);
+
+  let who = who_part.chars().fold(0, |who, c| {
+    who
+      | match c {
+        'u' => USER_BITS,
+        'g' => GROUP_BITS,
+        'o' => OTHER_BITS,
+        _ => ALL_BITS,
+      }
+  });
+
+  let mut actions = Vec::new();
+  while let Some(op) = rest.chars().next().filter(|c| "+-=".contains(*c)) {
+    rest = &rest[1..];
+    let end = rest.find(['+', '-', '=']).unwrap_or(rest.len());
+    let (perms, remaining) = rest.split_at(end);
+    rest = remaining;
+
+    let perms = match perms {
+      "u" | "g" | "o" => Perms::Copy(perms.chars().next()?),
+      _ => {
+        let mut bits = 0;
+        let mut conditional_x = false;
+        for c in perms.chars() {
+          bits |= match c {
+            'r' => 0o444,
+            'w' => 0o222,
+            'x' => 0o111,
+            'X' => {
+              conditional_x = true;
+              0
+            }
+            's' => S_ISUID | S_ISGID,
+            't' => S_ISVTX,
+            _ => return None,
+          };
+        }
+        Perms::Bits {
+          bits,
+          conditional_x,
+        }
+      }
+    };
+    actions.push(Action { op, perms });
+  }
+
+  (!actions.is_empty() && rest.is_empty()).then_some(Clause { who, actions })
+}
+
+/// Renders permission bits the way `ls -l` does, e.g. `rwxr-sr-t`.
+pub(crate) fn symbolic(mode: u32) -> String {
+  let flag = |bit: u32, c: char| if mode & bit != 0 { c } else { '-' };
+  let special = |bit: u32, x: u32, set: char| match (mode & bit != 0, mode & x != 0) {
+    (true, true) => set,
+    (true, false) => set.to_ascii_uppercase(),
+    (false, true) => 'x',
+    (false, false) => '-',
+  };
+
+  [
+    flag(0o400, 'r'),
+    flag(0o200, 'w'),
+    special(S_ISUID, 0o100, 's'),
+    flag(0o040, 'r'),
+    flag(0o020, 'w'),
+    special(S_ISGID, 0o010, 's'),
+    flag(0o004, 'r'),
+    flag(0o002, 'w'),
+    special(S_ISVTX, 0o001, 't'),
+  ]
+  .iter()
+  .collect()
+}
+
+/// The process umask, which unqualified symbolic modes respect.
+pub(crate) fn current_umask() -> u32 {
+  // SAFETY: umask cannot fail; the original value is restored immediately.
+  unsafe {
+    let mask = libc::umask(0o022);
+    libc::umask(mask);
+    mask as u32
+  }
+}
@@ -0,0 +1,52 @@
+use crate::config::HashMethod;
+use std::ffi::{CStr, CString};
+
+#[cfg_attr(target_os = "linux", link(name = "crypt"))]
+unsafe extern "C" {
+  fn crypt(phrase: *const libc::c_char, setting: *const libc::c_char) -> *mut libc::c_char;
+  fn crypt_gensalt(
+    prefix: *const libc::c_char,
+    count: libc::c_ulong,
+    rbytes: *const libc::c_char,
+    nrbytes: libc::c_int,
+  ) -> *mut libc::c_char;
+}
+
+impl HashMethod {
+  fn prefix(self) -> &'static CStr {
+    match self {
+      HashMethod::Yescrypt => c"$y$",
+      HashMethod::Sha512 => c"$6$",
+      HashMethod::Sha256 => c"$5$",
+    }
+  }
+}
+
+/// Hashes a password for shadow with a fresh random salt.
+pub(crate) fn hash(password: &str, method: HashMethod) -> Result<String, String> {
+  let phrase =
+    CString::new(password).map_err(|_| String::from("Password contains a NUL character"))?;
+  // SAFETY: the prefix is NUL-terminated; a null rbytes asks libcrypt to
+  // read random bytes itself. The result points into a static buffer and is
+  // copied before the next call.
+  let setting = unsafe { crypt_gensalt(method.prefix().as_ptr(), 0, std::ptr::null(), 0) };
+  if setting.is_null() {
+    return Err(String::from("Failed to generate a password salt"));
+  }
+  // SAFETY: setting was checked above and is NUL-terminated.
+  let setting = unsafe { CStr::from_ptr(setting) }.to_owned();
+  // SAFETY: both pointers are valid NUL-terminated strings.
+  let hashed = unsafe { crypt(phrase.as_ptr(), setting.as_ptr()) };
+  if hashed.is_null() {
+    return Err(String::from("Failed to hash the password"));
+  }
+  // SAFETY: hashed was checked above and is NUL-terminated.
+  let hashed = unsafe { CStr::from_ptr(hashed) }
+    .to_string_lossy()
+    .into_owned();
+  // libcrypt signals failure with a string starting with '*'
+  if hashed.starts_with('*') {
+    return Err(String::from("Failed to hash the password"));
+  }
+  Ok(hashed)
+}
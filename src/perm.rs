@@ -0,0 +1,1467 @@
+use crate::acl::{self, AclEntry, Tag};
+use crate::cli::ListFormat;
+use crate::db::Accounts;
+use crate::mode::{self, Mode};
+use crate::xattr;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::ffi::{CStr, CString, OsStr};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::path::{Component, Path, PathBuf};
+use tabled::{Table, Tabled};
+
+const CAPABILITY_XATTR: &str = "security.capability";
+const VFS_CAP_REVISION_MASK: u32 = 0xff00_0000;
+const VFS_CAP_REVISION_1: u32 = 0x0100_0000;
+const VFS_CAP_FLAGS_EFFECTIVE: u32 = 0x0000_0001;
+
+const FS_IOC_GETFLAGS: libc::c_ulong = 0x8008_6601;
+const FS_IMMUTABLE_FL: libc::c_long = 0x0000_0010;
+const FS_APPEND_FL: libc::c_long = 0x0000_0020;
+
+/// Capability names indexed by capability number, as in linux/capability.h.
+const CAPABILITIES: [&str; 41] = [
+  "chown",
+  "dac_override",
+  "dac_read_search",
+  "fowner",
+  "fsetid",
+  "kill",
+  "setgid",
+  "setuid",
+  "setpcap",
+  "linux_immutable",
+  "net_bind_service",
+  "net_broadcast",
+  "net_admin",
+  "net_raw",
+  "ipc_lock",
+  "ipc_owner",
+  "sys_module",
+  "sys_rawio",
+  "sys_chroot",
+  "sys_ptrace",
+  "sys_pacct",
+  "sys_admin",
+  "sys_boot",
+  "sys_nice",
+  "sys_resource",
+  "sys_time",
+  "sys_tty_config",
+  "mknod",
+  "lease",
+  "audit_write",
+  "audit_control",
+  "setfcap",
+  "mac_override",
+  "mac_admin",
+  "syslog",
+  "wake_alarm",
+  "block_suspend",
+  "audit_read",
+  "perfmon",
+  "bpf",
+  "checkpoint_restore",
+];
+
+/// What `perm set` changes on each file it visits.
+struct Changes {
+  file_mode: Option<Mode>,
+  dir_mode: Option<Mode>,
+  owner: Option<(Option<u32>, Option<u32>)>,
+  umask: u32,
+}
+
+/// Walks a tree applying `Changes`, counting failures instead of stopping.
+struct Walker<'a> {
+  changes: &'a Changes,
+  follow_symlinks: bool,
+  /// Directories already visited, to survive symlink loops
+  visited: HashSet<(u64, u64)>,
+  failures: usize,
+}
+
+/// A file the walker changes, named relative to an open directory (or to
+/// the working directory, for the target itself). Changes go through the
+/// `*at` calls, which only follow a symlink when `flags` allows it.
+struct At {
+  dir: RawFd,
+  name: CString,
+  flags: libc::c_int,
+  /// The full path, for messages
+  path: PathBuf,
+}
+
+pub(crate) fn set(
+  target: &str,
+  permissions: Option<&str>,
+  recursive: bool,
+  follow_symlinks: bool,
+  owner: Option<&str>,
+  files_only: Option<&str>,
+  dirs_only: Option<&str>,
+) -> bool {
+  let changes = match parse_changes(permissions, owner, files_only, dirs_only) {
+    Ok(changes) => changes,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  let path = Path::new(target);
+  let metadata = match fs::metadata(path) {
+    Ok(metadata) => metadata,
+    Err(e) => {
+      eprintln!("Error: Cannot access {}: {}", target, e);
+      return false;
+    }
+  };
+
+  let mode_spec = match (files_only, dirs_only) {
+    (None, None) => permissions.map(str::to_string),
+    _ if !recursive => {
+      let spec = if metadata.is_dir() {
+        dirs_only
+      } else {
+        files_only
+      };
+      spec.or(permissions).map(str::to_string)
+    }
+    _ => Some(
+      [
+        dirs_only
+          .or(permissions)
+          .map(|m| format!("{} for directories", m)),
+        files_only
+          .or(permissions)
+          .map(|m| format!("{} for files", m)),
+      ]
+      .into_iter()
+      .flatten()
+      .collect::<Vec<_>>()
+      .join(" and "),
+    ),
+  };
+  if mode_spec.is_none() && changes.owner.is_none() {
+    eprintln!(
+      "Error: No mode given for {}; use --recursive to change its contents",
+      target
+    );
+    return false;
+  }
+
+  let mut walker = Walker {
+    changes: &changes,
+    follow_symlinks,
+    visited: HashSet::new(),
+    failures: 0,
+  };
+  // The target itself is followed if it is a symlink, as chmod does.
+  let Ok(name) = CString::new(path.as_os_str().as_bytes()) else {
+    eprintln!("Error: Invalid path {}", target);
+    return false;
+  };
+  let top = At {
+    dir: libc::AT_FDCWD,
+    name,
+    flags: 0,
+    path: path.to_path_buf(),
+  };
+  walker.apply(&top, metadata.mode(), metadata.is_dir());
+  if recursive && metadata.is_dir() {
+    match fs::File::open(path) {
+      Ok(dir) => walker.descend(path, &dir),
+      Err(e) => walker.fail(path, "read", e),
+    }
+  }
+
+  if walker.failures > 0 {
+    if recursive {
+      eprintln!(
+        "Error: Failed to update {} {} under {}",
+        walker.failures,
+        if walker.failures == 1 {
+          "entry"
+        } else {
+          "entries"
+        },
+        target
+      );
+    }
+    return false;
+  }
+
+  let how = if recursive { "set recursively" } else { "set" };
+  if let Some(spec) = mode_spec {
+    let joiner = if recursive && (files_only.is_some() || dirs_only.is_some()) {
+      "in"
+    } else {
+      "for"
+    };
+    println!("Permissions {} to {} {} {}", how, spec, joiner, target);
+  }
+  if let Some(owner) = owner {
+    println!("Owner {} to {} for {}", how, owner, target);
+  }
+  true
+}
+
+fn parse_changes(
+  permissions: Option<&str>,
+  owner: Option<&str>,
+  files_only: Option<&str>,
+  dirs_only: Option<&str>,
+) -> Result<Changes, String> {
+  let parse = |spec: Option<&str>| spec.map(Mode::parse).transpose();
+
+  Ok(Changes {
+    file_mode: parse(files_only.or(permissions))?,
+    dir_mode: parse(dirs_only.or(permissions))?,
+    owner: owner.map(parse_owner).transpose()?,
+    umask: mode::current_umask(),
+  })
+}
+
+/// Parses `USER`, `USER:GROUP`, `:GROUP` or `USER:` (the user's login group)
+/// into a UID and GID. Names are looked up in passwd and group; numeric IDs
+/// are taken as they are.
+fn parse_owner(spec: &str) -> Result<(Option<u32>, Option<u32>), String> {
+  let db = Accounts::load()?;
+  let (user, group) = match spec.split_once(':') {
+    Some((user, group)) => (user, Some(group)),
+    None => (spec, None),
+  };
+
+  let user = match user {
+    "" => None,
+    name => Some(match db.user(name) {
+      Some(entry) => (entry.uid, entry.gid),
+      None => {
+        let uid = name
+          .parse()
+          .map_err(|_| format!("User {} does not exist", name))?;
+        let gid = db.users().iter().find(|u| u.uid == uid).map(|u| u.gid);
+        (uid, gid.unwrap_or(uid))
+      }
+    }),
+  };
+
+  let gid = match group {
+    None => None,
+    Some("") => match user {
+      Some((_, gid)) => Some(gid),
+      None => return Err(format!("Invalid owner '{}'", spec)),
+    },
+    Some(name) => Some(match db.group(name) {
+      Some(entry) => entry.gid,
+      None => name
+        .parse()
+        .map_err(|_| format!("Group {} does not exist", name))?,
+    }),
+  };
+
+  if user.is_none() && gid.is_none() {
+    return Err(format!("Invalid owner '{}'", spec));
+  }
+  Ok((user.map(|(uid, _)| uid), gid))
+}
+
+impl Walker<'_> {
+  /// Changes everything under the open directory `dir`. Entries are reached
+  /// through the directory's descriptor rather than by path, so an entry
+  /// swapped for a symlink during the walk is not followed unless asked to.
+  fn descend(&mut self, path: &Path, dir: &fs::File) {
+    let Ok(metadata) = dir.metadata() else {
+      return;
+    };
+    if !self.visited.insert((metadata.dev(), metadata.ino())) {
+      return;
+    }
+
+    let names = match entry_names(dir) {
+      Ok(names) => names,
+      Err(e) => {
+        self.fail(path, "read", e);
+        return;
+      }
+    };
+    for name in names {
+      let mut entry = At {
+        dir: dir.as_raw_fd(),
+        path: path.join(OsStr::from_bytes(name.as_bytes())),
+        name,
+        flags: libc::AT_SYMLINK_NOFOLLOW,
+      };
+      let Ok(mut stat) = entry.stat() else {
+        continue;
+      };
+
+      if stat.st_mode & libc::S_IFMT == libc::S_IFLNK {
+        if !self.follow_symlinks {
+          // The link itself has no mode of its own, but it does have an owner.
+          if let Some((uid, gid)) = self.changes.owner
+            && let Err(e) = entry.chown(uid, gid)
+          {
+            self.fail(&entry.path, "change the owner of", e);
+          }
+          continue;
+        }
+        entry.flags = 0;
+        match entry.stat() {
+          Ok(target) => stat = target,
+          // Dangling links are left alone.
+          Err(_) => continue,
+        }
+      }
+
+      let is_dir = stat.st_mode & libc::S_IFMT == libc::S_IFDIR;
+      self.apply(&entry, stat.st_mode, is_dir);
+      if is_dir {
+        match entry.open_dir() {
+          Ok(child) => self.descend(&entry.path, &child),
+          Err(e) => self.fail(&entry.path, "read", e),
+        }
+      }
+    }
+  }
+
+  fn apply(&mut self, entry: &At, mode: u32, is_dir: bool) {
+    // Changing the owner clears setuid/setgid, so it has to come first.
+    if let Some((uid, gid)) = self.changes.owner
+      && let Err(e) = entry.chown(uid, gid)
+    {
+      self.fail(&entry.path, "change the owner of", e);
+      return;
+    }
+
+    let new_mode = if is_dir {
+      &self.changes.dir_mode
+    } else {
+      &self.changes.file_mode
+    };
+    if let Some(new_mode) = new_mode {
+      let current = match self.changes.owner {
+        // Re-read the mode the chown left behind.
+        Some(_) => entry.stat().map_or(mode, |stat| stat.st_mode),
+        None => mode,
+      };
+      let new = new_mode.apply(current, is_dir, self.changes.umask);
+      if let Err(e) = entry.chmod(new) {
+        self.fail(&entry.path, "set permissions on", e);
+      }
+    }
+  }
+
+  fn fail(&mut self, path: &Path, action: &str, error: std::io::Error) {
+    eprintln!("Error: Failed to {} {}: {}", action, path.display(), error);
+    self.failures += 1;
+  }
+}
+
+impl At {
+  fn stat(&self) -> io::Result<libc::stat> {
+    let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+    match unsafe { libc::fstatat(self.dir, self.name.as_ptr(), &mut stat, self.flags) } {
+      0 => Ok(stat),
+      _ => Err(io::Error::last_os_error()),
+    }
+  }
+
+  fn chown(&self, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    // -1 leaves the ID as it is.
+    let uid = uid.unwrap_or(u32::MAX);
+    let gid = gid.unwrap_or(u32::MAX);
+    match unsafe { libc::fchownat(self.dir, self.name.as_ptr(), uid, gid, self.flags) } {
+      0 => Ok(()),
+      _ => Err(io::Error::last_os_error()),
+    }
+  }
+
+  fn chmod(&self, mode: u32) -> io::Result<()> {
+    let rc = unsafe {
+      libc::fchmodat(
+        self.dir,
+        self.name.as_ptr(),
+        mode as libc::mode_t,
+        self.flags,
+      )
+    };
+    match rc {
+      0 => Ok(()),
+      _ => Err(io::Error::last_os_error()),
+    }
+  }
+
+  /// Opens the entry as a directory to walk into, refusing a symlink unless
+  /// `flags` follows them.
+  fn open_dir(&self) -> io::Result<fs::File> {
+    let mut flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_CLOEXEC;
+    if self.flags & libc::AT_SYMLINK_NOFOLLOW != 0 {
+      flags |= libc::O_NOFOLLOW;
+    }
+    match unsafe { libc::openat(self.dir, self.name.as_ptr(), flags) } {
+      -1 => Err(io::Error::last_os_error()),
+      fd => Ok(fs::File::from(unsafe { OwnedFd::from_raw_fd(fd) })),
+    }
+  }
+}
+
+/// The names in an open directory, without `.` and `..`.
+fn entry_names(dir: &fs::File) -> io::Result<Vec<CString>> {
+  // closedir closes the descriptor, so hand it a copy.
+  let fd = unsafe { libc::dup(dir.as_raw_fd()) };
+  if fd == -1 {
+    return Err(io::Error::last_os_error());
+  }
+  let stream = unsafe { libc::fdopendir(fd) };
+  if stream.is_null() {
+    let error = io::Error::last_os_error();
+    unsafe { libc::close(fd) };
+    return Err(error);
+  }
+  // Start from the beginning even if the descriptor was read before.
+  unsafe { libc::rewinddir(stream) };
+
+  let mut names = Vec::new();
+  loop {
+    let entry = unsafe { libc::readdir(stream) };
+    if entry.is_null() {
+      break;
+    }
+    let name = unsafe { CStr::from_ptr((*entry).d_name.as_ptr()) };
+    if name != c"." && name != c".." {
+      names.push(name.to_owned());
+    }
+  }
+  unsafe { libc::closedir(stream) };
+  Ok(names)
+}
+
+/// The credentials `perm check` evaluates access with.
+struct Identity {
+  name: String,
+  uid: u32,
+  /// Primary GID first, then supplementary and hypothetical groups
+  gids: Vec<u32>,
+}
+
+/// The outcome of checking one permission bit against one file.
+struct Decision {
+  granted: bool,
+  reason: String,
+}
+
+/// A named user or group ACL entry given on the command line, e.g.
+/// `u:alice:rwx` or `g:devs:rX`.
+struct AclSpec {
+  text: String,
+  tag: Tag,
+  id: u32,
+  perms: u32,
+  /// `X`: execute only on directories and files someone can already execute
+  conditional_x: bool,
+}
+
+#[derive(Tabled, Serialize)]
+struct AclRow {
+  #[tabled(rename = "Scope")]
+  scope: &'static str,
+  #[tabled(rename = "Type")]
+  #[serde(rename = "type")]
+  kind: &'static str,
+  #[tabled(rename = "Name")]
+  name: String,
+  #[tabled(rename = "Permissions")]
+  permissions: String,
+  #[tabled(rename = "Effective")]
+  effective: String,
+}
+
+const ACCESS_BITS: [(u32, &str); 3] = [(0o4, "read"), (0o2, "write"), (0o1, "execute")];
+
+/// Everything `perm get` reports about a file.
+#[derive(Serialize)]
+struct FileInfo {
+  path: String,
+  #[serde(rename = "type")]
+  file_type: &'static str,
+  mode: String,
+  symbolic: String,
+  owner: String,
+  uid: u32,
+  group: String,
+  gid: u32,
+  special: Vec<&'static str>,
+  acl: Vec<String>,
+  default_acl: Vec<String>,
+  capabilities: Option<String>,
+  attributes: Vec<&'static str>,
+}
+
+#[derive(Tabled)]
+struct PropertyRow {
+  #[tabled(rename = "Property")]
+  property: &'static str,
+  #[tabled(rename = "Value")]
+  value: String,
+}
+
+pub(crate) fn get(target: &str, format: &ListFormat) -> bool {
+  let info = match file_info(Path::new(target)) {
+    Ok(info) => info,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  match format {
+    ListFormat::Table => print_info_table(&info),
+    ListFormat::Json => match serde_json::to_string_pretty(&info) {
+      Ok(json) => println!("{}", json),
+      Err(e) => {
+        eprintln!("Error: Failed to serialize permissions to JSON: {}", e);
+        return false;
+      }
+    },
+    ListFormat::Csv => print_info_csv(&info),
+  }
+  true
+}
+
+fn file_info(path: &Path) -> Result<FileInfo, String> {
+  let metadata =
+    fs::metadata(path).map_err(|e| format!("Cannot access {}: {}", path.display(), e))?;
+  let db = Accounts::load()?;
+  let mode = metadata.mode();
+
+  let special = [(0o4000, "setuid"), (0o2000, "setgid"), (0o1000, "sticky")]
+    .into_iter()
+    .filter(|(bit, _)| mode & bit != 0)
+    .map(|(_, name)| name)
+    .collect();
+  let acl_strings = |entries: Vec<AclEntry>| -> Vec<String> {
+    entries
+      .iter()
+      .map(|entry| acl_entry_string(&db, entry))
+      .collect()
+  };
+
+  Ok(FileInfo {
+    path: path.display().to_string(),
+    file_type: file_type_name(&metadata.file_type()),
+    mode: format!("{:04o}", mode & 0o7777),
+    symbolic: format!(
+      "{}{}",
+      type_char(&metadata.file_type()),
+      mode::symbolic(mode)
+    ),
+    owner: user_name(&db, metadata.uid()),
+    uid: metadata.uid(),
+    group: group_name(&db, metadata.gid()),
+    gid: metadata.gid(),
+    special,
+    acl: acl_strings(acl::read(path, false)?),
+    default_acl: if metadata.is_dir() {
+      acl_strings(acl::read(path, true)?)
+    } else {
+      Vec::new()
+    },
+    capabilities: xattr::get(path, CAPABILITY_XATTR)?
+      .as_deref()
+      .and_then(capabilities_string),
+    attributes: attribute_flags(path, &metadata),
+  })
+}
+
+fn print_info_table(info: &FileInfo) {
+  let none = |list: &[&str]| {
+    if list.is_empty() {
+      String::from("none")
+    } else {
+      list.join(", ")
+    }
+  };
+
+  let mut rows = vec![
+    PropertyRow {
+      property: "Type",
+      value: info.file_type.to_string(),
+    },
+    PropertyRow {
+      property: "Mode",
+      value: format!("{} ({})", info.mode, info.symbolic),
+    },
+    PropertyRow {
+      property: "Owner",
+      value: format!("{} ({})", info.owner, info.uid),
+    },
+    PropertyRow {
+      property: "Group",
+      value: format!("{} ({})", info.group, info.gid),
+    },
+    PropertyRow {
+      property: "Special Bits",
+      value: none(&info.special),
+    },
+  ];
+  if !info.acl.is_empty() {
+    rows.push(PropertyRow {
+      property: "ACL",
+      value: info.acl.join("\n"),
+    });
+  }
+  if !info.default_acl.is_empty() {
+    rows.push(PropertyRow {
+      property: "Default ACL",
+      value: info.default_acl.join("\n"),
+    });
+  }
+  if let Some(capabilities) = &info.capabilities {
+    rows.push(PropertyRow {
+      property: "Capabilities",
+      value: capabilities.clone(),
+    });
+  }
+  rows.push(PropertyRow {
+    property: "Attributes",
+    value: none(&info.attributes),
+  });
+
+  println!("Permissions for {}:", info.path);
+  println!("{}", Table::new(rows));
+}
+
+fn print_info_csv(info: &FileInfo) {
+  println!(
+    "path,type,mode,symbolic,owner,uid,group,gid,special,acl,default_acl,capabilities,attributes"
+  );
+  let fields = [
+    info.path.clone(),
+    info.file_type.to_string(),
+    info.mode.clone(),
+    info.symbolic.clone(),
+    info.owner.clone(),
+    info.uid.to_string(),
+    info.group.clone(),
+    info.gid.to_string(),
+    info.special.join(" "),
+    info.acl.join(" "),
+    info.default_acl.join(" "),
+    info.capabilities.clone().unwrap_or_default(),
+    info.attributes.join(" "),
+  ];
+  let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+  println!("{}", fields.join(","));
+}
+
+fn csv_field(value: &str) -> String {
+  if value.contains([',', '"', '\n']) {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  } else {
+    value.to_string()
+  }
+}
+
+fn file_type_name(file_type: &fs::FileType) -> &'static str {
+  if file_type.is_dir() {
+    "directory"
+  } else if file_type.is_symlink() {
+    "symbolic link"
+  } else if file_type.is_block_device() {
+    "block device"
+  } else if file_type.is_char_device() {
+    "character device"
+  } else if file_type.is_fifo() {
+    "FIFO"
+  } else if file_type.is_socket() {
+    "socket"
+  } else {
+    "regular file"
+  }
+}
+
+fn type_char(file_type: &fs::FileType) -> char {
+  match file_type_name(file_type) {
+    "directory" => 'd',
+    "symbolic link" => 'l',
+    "block device" => 'b',
+    "character device" => 'c',
+    "FIFO" => 'p',
+    "socket" => 's',
+    _ => '-',
+  }
+}
+
+fn user_name(db: &Accounts, uid: u32) -> String {
+  db.users()
+    .into_iter()
+    .find(|user| user.uid == uid)
+    .map_or(uid.to_string(), |user| user.name)
+}
+
+fn group_name(db: &Accounts, gid: u32) -> String {
+  db.groups()
+    .into_iter()
+    .find(|group| group.gid == gid)
+    .map_or(gid.to_string(), |group| group.name)
+}
+
+/// Formats an ACL entry the way getfacl does, e.g. `user:alice:r-x`.
+fn acl_entry_string(db: &Accounts, entry: &AclEntry) -> String {
+  let qualifier = match (entry.tag, entry.id) {
+    (Tag::User, Some(uid)) => user_name(db, uid),
+    (Tag::Group, Some(gid)) => group_name(db, gid),
+    _ => String::new(),
+  };
+  format!(
+    "{}:{}:{}",
+    entry.tag.name(),
+    qualifier,
+    acl::perms_string(entry.perms)
+  )
+}
+
+/// Formats a `security.capability` value the way getcap does, e.g.
+/// `cap_net_bind_service,cap_net_raw=ep`.
+fn capabilities_string(data: &[u8]) -> Option<String> {
+  let word = |index: usize| -> Option<u32> {
+    let bytes = data.get(index * 4..index * 4 + 4)?;
+    Some(u32::from_le_bytes(bytes.try_into().ok()?))
+  };
+  let magic = word(0)?;
+  let words = if magic & VFS_CAP_REVISION_MASK == VFS_CAP_REVISION_1 {
+    1
+  } else {
+    2
+  };
+  let effective = magic & VFS_CAP_FLAGS_EFFECTIVE != 0;
+
+  let mut permitted = 0u64;
+  let mut inheritable = 0u64;
+  for i in 0..words {
+    permitted |= (word(1 + i * 2)? as u64) << (32 * i);
+    inheritable |= (word(2 + i * 2)? as u64) << (32 * i);
+  }
+
+  // Capabilities sharing the same flags are listed together.
+  let mut clauses: Vec<(String, Vec<String>)> = Vec::new();
+  for bit in 0..64 {
+    let in_permitted = permitted & (1 << bit) != 0;
+    let in_inheritable = inheritable & (1 << bit) != 0;
+    if !in_permitted && !in_inheritable {
+      continue;
+    }
+    let flags: String = [
+      (effective && in_permitted, 'e'),
+      (in_inheritable, 'i'),
+      (in_permitted, 'p'),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, c)| *c)
+    .collect();
+    let name = CAPABILITIES
+      .get(bit)
+      .map_or(format!("cap_{}", bit), |name| format!("cap_{}", name));
+    match clauses.iter_mut().find(|(f, _)| *f == flags) {
+      Some((_, names)) => names.push(name),
+      None => clauses.push((flags, vec![name])),
+    }
+  }
+
+  let clauses: Vec<String> = clauses
+    .into_iter()
+    .map(|(flags, names)| format!("{}={}", names.join(","), flags))
+    .collect();
+  (!clauses.is_empty()).then(|| clauses.join(" "))
+}
+
+/// The immutable and append-only inode flags, as shown by lsattr.
+fn attribute_flags(path: &Path, metadata: &fs::Metadata) -> Vec<&'static str> {
+  use std::os::unix::io::AsRawFd;
+
+  // Opening devices or FIFOs could block or have side effects.
+  if !metadata.is_file() && !metadata.is_dir() {
+    return Vec::new();
+  }
+  let Ok(file) = fs::File::open(path) else {
+    return Vec::new();
+  };
+  let mut flags: libc::c_long = 0;
+  // SAFETY: FS_IOC_GETFLAGS writes at most a long into `flags`.
+  if unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_GETFLAGS as _, &mut flags) } != 0 {
+    return Vec::new();
+  }
+
+  [
+    (FS_IMMUTABLE_FL, "immutable"),
+    (FS_APPEND_FL, "append-only"),
+  ]
+  .into_iter()
+  .filter(|(flag, _)| flags & flag != 0)
+  .map(|(_, name)| name)
+  .collect()
+}
+
+pub(crate) fn check(username: &str, target: &str, as_groups: &[String]) -> bool {
+  let db = match Accounts::load() {
+    Ok(db) => db,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  let identity = match resolve_identity(&db, username, as_groups) {
+    Ok(identity) => identity,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  let (dirs, path) = match lookup(Path::new(target)) {
+    Ok(lookup) => lookup,
+    Err(e) => {
+      eprintln!("Error: Cannot access {}: {}", target, e);
+      return false;
+    }
+  };
+
+  // Every directory the lookup passes through needs search permission; the
+  // first one missing it denies everything.
+  for dir in &dirs {
+    let decision = match dir_decision(&db, &identity, dir) {
+      Ok(decision) => decision,
+      Err(e) => {
+        eprintln!("Error: {}", e);
+        return false;
+      }
+    };
+    if !decision.granted {
+      println!("User {} has no access to {}", identity.name, target);
+      for (_, name) in ACCESS_BITS {
+        println!(
+          "  {:<8} denied   cannot search {}: {}",
+          name,
+          dir.display(),
+          decision.reason
+        );
+      }
+      return true;
+    }
+  }
+
+  let decisions = match file_decisions(&db, &identity, &path) {
+    Ok(decisions) => decisions,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  let granted: Vec<&str> = ACCESS_BITS
+    .iter()
+    .zip(&decisions)
+    .filter(|(_, decision)| decision.granted)
+    .map(|((_, name), _)| *name)
+    .collect();
+
+  if granted.is_empty() {
+    println!("User {} has no access to {}", identity.name, target);
+  } else {
+    println!(
+      "User {} has {} access to {}",
+      identity.name,
+      granted.join(", "),
+      target
+    );
+  }
+  for ((_, name), decision) in ACCESS_BITS.iter().zip(&decisions) {
+    println!(
+      "  {:<8} {:<8} {}",
+      name,
+      if decision.granted {
+        "granted"
+      } else {
+        "denied"
+      },
+      decision.reason
+    );
+  }
+  true
+}
+
+/// Resolves `target` one component at a time as path lookup does,
+/// following symlinks where they occur. Returns the directories searched on
+/// the way, in order, and the path finally reached.
+fn lookup(target: &Path) -> std::io::Result<(Vec<PathBuf>, PathBuf)> {
+  // Linux gives up after 40 symlinks in one lookup
+  const MAX_SYMLINKS: usize = 40;
+
+  let target = std::env::current_dir()?.join(target);
+  let mut pending: Vec<PathBuf> = target
+    .components()
+    .rev()
+    .map(|c| PathBuf::from(c.as_os_str()))
+    .collect();
+  let mut current = PathBuf::from("/");
+  let mut searched: Vec<PathBuf> = Vec::new();
+  let mut symlinks = 0;
+  while let Some(next) = pending.pop() {
+    let component = next.components().next();
+    match component {
+      Some(Component::RootDir) => current = PathBuf::from("/"),
+      Some(Component::Normal(name)) => {
+        if !searched.contains(&current) {
+          searched.push(current.clone());
+        }
+        let candidate = current.join(name);
+        if !fs::symlink_metadata(&candidate)?.file_type().is_symlink() {
+          current = candidate;
+          continue;
+        }
+        symlinks += 1;
+        if symlinks > MAX_SYMLINKS {
+          return Err(std::io::Error::from_raw_os_error(libc::ELOOP));
+        }
+        let link = fs::read_link(&candidate)?;
+        pending.extend(
+          link
+            .components()
+            .rev()
+            .map(|c| PathBuf::from(c.as_os_str())),
+        );
+      }
+      Some(Component::ParentDir) => {
+        if !searched.contains(&current) {
+          searched.push(current.clone());
+        }
+        current.pop();
+      }
+      _ => {}
+    }
+  }
+  Ok((searched, current))
+}
+
+fn resolve_identity(
+  db: &Accounts,
+  username: &str,
+  as_groups: &[String],
+) -> Result<Identity, String> {
+  let user = db
+    .user(username)
+    .ok_or_else(|| format!("User {} does not exist", username))?;
+
+  let mut gids = vec![user.gid];
+  let extra = as_groups.iter().map(|name| {
+    db.group(name)
+      .map(|group| group.gid)
+      .ok_or_else(|| format!("Group {} does not exist", name))
+  });
+  let supplementary = db
+    .supplementary_groups(username)
+    .into_iter()
+    .map(|g| Ok(g.gid));
+  for gid in supplementary.chain(extra) {
+    let gid = gid?;
+    if !gids.contains(&gid) {
+      gids.push(gid);
+    }
+  }
+
+  Ok(Identity {
+    name: user.name,
+    uid: user.uid,
+    gids,
+  })
+}
+
+fn dir_decision(db: &Accounts, identity: &Identity, dir: &Path) -> Result<Decision, String> {
+  let metadata =
+    fs::metadata(dir).map_err(|e| format!("Cannot access {}: {}", dir.display(), e))?;
+  let acl = acl::read(dir, false)?;
+  Ok(decide(db, identity, &metadata, &acl, 0o1, "search"))
+}
+
+fn file_decisions(
+  db: &Accounts,
+  identity: &Identity,
+  path: &Path,
+) -> Result<Vec<Decision>, String> {
+  let metadata =
+    fs::metadata(path).map_err(|e| format!("Cannot access {}: {}", path.display(), e))?;
+  let acl = acl::read(path, false)?;
+  let attributes = attribute_flags(path, &metadata);
+
+  Ok(
+    ACCESS_BITS
+      .iter()
+      .map(|(bit, name)| {
+        if *bit == 0o2 && attributes.contains(&"immutable") {
+          return Decision {
+            granted: false,
+            reason: String::from("the file is immutable"),
+          };
+        }
+        let mut decision = decide(db, identity, &metadata, &acl, *bit, name);
+        if *bit == 0o2 && decision.granted && attributes.contains(&"append-only") {
+          decision.reason.push_str(" (append only)");
+        }
+        decision
+      })
+      .collect(),
+  )
+}
+
+/// Checks one permission bit the way the kernel's generic_permission does:
+/// root overrides, then the owner, named user, group and other classes in
+/// turn, with the ACL mask limiting named users and the group class. The
+/// first class that matches decides, even if it denies.
+fn decide(
+  db: &Accounts,
+  identity: &Identity,
+  metadata: &fs::Metadata,
+  acl: &[AclEntry],
+  bit: u32,
+  name: &str,
+) -> Decision {
+  let mode = metadata.mode();
+  let verdict = |granted: bool, reason: String| Decision { granted, reason };
+  let allows = |granted: bool| if granted { "allow" } else { "do not allow" };
+  let allows_one = |granted: bool| if granted { "allows" } else { "does not allow" };
+
+  if identity.uid == 0 {
+    // Execute needs at least one execute bit, even for root.
+    if bit == 0o1 && !metadata.is_dir() && mode & 0o111 == 0 {
+      return verdict(
+        false,
+        String::from("no execute bit is set, not even root may execute"),
+      );
+    }
+    return verdict(true, String::from("root overrides permission checks"));
+  }
+
+  if identity.uid == metadata.uid() {
+    let perms = (mode >> 6) & 0o7;
+    return verdict(
+      perms & bit != 0,
+      format!(
+        "owner permissions {} {} {}",
+        acl::perms_string(perms),
+        allows(perms & bit != 0),
+        name
+      ),
+    );
+  }
+
+  if acl.is_empty() {
+    if identity.gids.contains(&metadata.gid()) {
+      let perms = (mode >> 3) & 0o7;
+      return verdict(
+        perms & bit != 0,
+        format!(
+          "group {} permissions {} {} {}",
+          group_name(db, metadata.gid()),
+          acl::perms_string(perms),
+          allows(perms & bit != 0),
+          name
+        ),
+      );
+    }
+  } else {
+    let mask = acl
+      .iter()
+      .find(|entry| entry.tag == Tag::Mask)
+      .map_or(0o7, |entry| entry.perms);
+    let masked = |entry: &AclEntry| {
+      let mut text = format!("ACL entry {}", acl_entry_string(db, entry));
+      if mask != 0o7 {
+        text.push_str(&format!(" masked by {}", acl::perms_string(mask)));
+      }
+      text
+    };
+
+    if let Some(entry) = acl
+      .iter()
+      .find(|entry| entry.tag == Tag::User && entry.id == Some(identity.uid))
+    {
+      let granted = entry.perms & mask & bit != 0;
+      return verdict(
+        granted,
+        format!("{} {} {}", masked(entry), allows_one(granted), name),
+      );
+    }
+
+    let matching: Vec<&AclEntry> = acl
+      .iter()
+      .filter(|entry| match entry.tag {
+        Tag::GroupObj => identity.gids.contains(&metadata.gid()),
+        Tag::Group => entry.id.is_some_and(|gid| identity.gids.contains(&gid)),
+        _ => false,
+      })
+      .collect();
+    if !matching.is_empty() {
+      return match matching.iter().find(|entry| entry.perms & mask & bit != 0) {
+        Some(entry) => verdict(
+          true,
+          format!("{} {} {}", masked(entry), allows_one(true), name),
+        ),
+        None => {
+          let entries: Vec<String> = matching.iter().map(|entry| masked(entry)).collect();
+          let denies = if entries.len() == 1 {
+            allows_one(false)
+          } else {
+            allows(false)
+          };
+          verdict(false, format!("{} {} {}", entries.join(", "), denies, name))
+        }
+      };
+    }
+  }
+
+  let perms = mode & 0o7;
+  verdict(
+    perms & bit != 0,
+    format!(
+      "other permissions {} {} {}",
+      acl::perms_string(perms),
+      allows(perms & bit != 0),
+      name
+    ),
+  )
+}
+
+pub(crate) fn acl_add(target: &str, entries: &[String], default: bool, recursive: bool) -> bool {
+  update_acls(target, entries, default, recursive, true)
+}
+
+pub(crate) fn acl_remove(target: &str, entries: &[String], default: bool, recursive: bool) -> bool {
+  update_acls(target, entries, default, recursive, false)
+}
+
+fn update_acls(
+  target: &str,
+  entries: &[String],
+  default: bool,
+  recursive: bool,
+  add: bool,
+) -> bool {
+  let specs = match Accounts::load().and_then(|db| {
+    entries
+      .iter()
+      .map(|entry| parse_acl_spec(&db, entry, add))
+      .collect::<Result<Vec<_>, _>>()
+  }) {
+    Ok(specs) => specs,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  let path = Path::new(target);
+  let metadata = match fs::metadata(path) {
+    Ok(metadata) => metadata,
+    Err(e) => {
+      eprintln!("Error: Cannot access {}: {}", target, e);
+      return false;
+    }
+  };
+  if default && !metadata.is_dir() {
+    eprintln!("Error: Default ACLs can only be set on directories");
+    return false;
+  }
+
+  let mut failures = 0;
+  let mut apply = |path: &Path, metadata: &fs::Metadata| {
+    if let Err(e) = update_acl(path, metadata, &specs, default, add) {
+      eprintln!("Error: {}", e);
+      failures += 1;
+    }
+  };
+  apply(path, &metadata);
+  if recursive && metadata.is_dir() {
+    visit_tree(path, &mut apply);
+  }
+  if failures > 0 {
+    return false;
+  }
+
+  let texts: Vec<&str> = specs.iter().map(|spec| spec.text.as_str()).collect();
+  println!(
+    "{}ACL {} {} {}{} {}",
+    if default { "Default " } else { "" },
+    if specs.len() == 1 { "entry" } else { "entries" },
+    texts.join(", "),
+    if add { "added" } else { "removed" },
+    if recursive { " recursively" } else { "" },
+    if add {
+      format!("to {}", target)
+    } else {
+      format!("from {}", target)
+    }
+  );
+  true
+}
+
+/// Adds or removes the entries on one file, starting from its mode when it
+/// has no ACL yet, and recomputes the mask.
+fn update_acl(
+  path: &Path,
+  metadata: &fs::Metadata,
+  specs: &[AclSpec],
+  default: bool,
+  add: bool,
+) -> Result<(), String> {
+  if default && !metadata.is_dir() {
+    return Ok(());
+  }
+
+  let mut entries = acl::read(path, default)?;
+  if entries.is_empty() {
+    if !add {
+      return Ok(());
+    }
+    // A new default ACL starts from the directory's own access rights.
+    let access = if default {
+      acl::read(path, false)?
+    } else {
+      Vec::new()
+    };
+    entries = if access.is_empty() {
+      acl::from_mode(metadata.mode())
+    } else {
+      access
+        .into_iter()
+        .filter(|entry| !entry.tag.is_masked() || entry.tag == Tag::GroupObj)
+        .collect()
+    };
+  }
+
+  for spec in specs {
+    entries.retain(|entry| !(entry.tag == spec.tag && entry.id == Some(spec.id)));
+    if add {
+      let x = spec.conditional_x && (metadata.is_dir() || metadata.mode() & 0o111 != 0);
+      entries.push(AclEntry {
+        tag: spec.tag,
+        id: Some(spec.id),
+        perms: spec.perms | if x { 0o1 } else { 0 },
+      });
+    }
+  }
+  acl::recompute_mask(&mut entries);
+
+  // Without named entries a default ACL only gets in the way of the umask.
+  if default && acl::is_minimal(&entries) {
+    entries.clear();
+  }
+  acl::write(path, default, &entries)
+}
+
+/// Parses `u:USER:PERMS` or `g:GROUP:PERMS`, or just `u:USER` / `g:GROUP`
+/// when the entry is being removed.
+fn parse_acl_spec(db: &Accounts, spec: &str, with_perms: bool) -> Result<AclSpec, String> {
+  let invalid = || {
+    if with_perms {
+      format!(
+        "Invalid ACL entry '{}': expected u:USER:PERMS or g:GROUP:PERMS",
+        spec
+      )
+    } else {
+      format!("Invalid ACL entry '{}': expected u:USER or g:GROUP", spec)
+    }
+  };
+
+  let mut parts = spec.splitn(3, ':');
+  let tag = match parts.next() {
+    Some("u" | "user") => Tag::User,
+    Some("g" | "group") => Tag::Group,
+    _ => return Err(invalid()),
+  };
+  let name = parts
+    .next()
+    .filter(|name| !name.is_empty())
+    .ok_or_else(invalid)?;
+  let id = match tag {
+    Tag::User => db.user(name).map(|user| user.uid),
+    _ => db.group(name).map(|group| group.gid),
+  };
+  let id = match id {
+    Some(id) => id,
+    None => name.parse().map_err(|_| {
+      if tag == Tag::User {
+        format!("User {} does not exist", name)
+      } else {
+        format!("Group {} does not exist", name)
+      }
+    })?,
+  };
+
+  let (perms, conditional_x) = match (parts.next(), with_perms) {
+    (Some(text), true) => parse_acl_perms(text).ok_or_else(invalid)?,
+    (None, false) => (0, false),
+    _ => return Err(invalid()),
+  };
+
+  Ok(AclSpec {
+    text: spec.to_string(),
+    tag,
+    id,
+    perms,
+    conditional_x,
+  })
+}
+
+/// Parses ACL permissions such as `rwx`, `r-x` or `rX`, returning the bits
+/// and whether `X` asked for execute on directories and executables only.
+pub(crate) fn parse_acl_perms(text: &str) -> Option<(u32, bool)> {
+  let mut perms = 0;
+  let mut conditional_x = false;
+  for c in text.chars() {
+    match c {
+      'r' => perms |= 0o4,
+      'w' => perms |= 0o2,
+      'x' => perms |= 0o1,
+      'X' => conditional_x = true,
+      '-' => {}
+      _ => return None,
+    }
+  }
+  Some((perms, conditional_x))
+}
+
+/// Gives `uid` a named user entry with `perms` in the access ACL of `path`,
+/// or removes its entry when `perms` is `None`.
+pub(crate) fn set_user_acl(path: &Path, uid: u32, perms: Option<&str>) -> Result<(), String> {
+  let metadata =
+    fs::metadata(path).map_err(|e| format!("Cannot access {}: {}", path.display(), e))?;
+  let (bits, conditional_x) = match perms {
+    Some(text) => {
+      parse_acl_perms(text).ok_or_else(|| format!("Invalid ACL permissions '{}'", text))?
+    }
+    None => (0, false),
+  };
+  let spec = AclSpec {
+    text: format!("u:{}", uid),
+    tag: Tag::User,
+    id: uid,
+    perms: bits,
+    conditional_x,
+  };
+  update_acl(path, &metadata, &[spec], false, perms.is_some())
+}
+
+pub(crate) fn acl_list(target: &str, format: &ListFormat) -> bool {
+  let path = Path::new(target);
+  let rows = match acl_rows(path) {
+    Ok(rows) => rows,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  match format {
+    ListFormat::Table => {
+      println!("ACL for {}:", target);
+      println!("{}", Table::new(rows));
+    }
+    ListFormat::Json => match serde_json::to_string_pretty(&rows) {
+      Ok(json) => println!("{}", json),
+      Err(e) => {
+        eprintln!("Error: Failed to serialize ACL to JSON: {}", e);
+        return false;
+      }
+    },
+    ListFormat::Csv => {
+      println!("scope,type,name,permissions,effective");
+      for row in rows {
+        println!(
+          "{},{},{},{},{}",
+          row.scope,
+          row.kind,
+          csv_field(&row.name),
+          row.permissions,
+          row.effective
+        );
+      }
+    }
+  }
+  true
+}
+
+/// The access ACL (synthesized from the mode when there is none) followed
+/// by the default ACL, if any.
+fn acl_rows(path: &Path) -> Result<Vec<AclRow>, String> {
+  let metadata =
+    fs::metadata(path).map_err(|e| format!("Cannot access {}: {}", path.display(), e))?;
+  let db = Accounts::load()?;
+
+  let mut access = acl::read(path, false)?;
+  if access.is_empty() {
+    access = acl::from_mode(metadata.mode());
+  }
+  let default = if metadata.is_dir() {
+    acl::read(path, true)?
+  } else {
+    Vec::new()
+  };
+
+  let mut rows = Vec::new();
+  for (scope, mut entries) in [("access", access), ("default", default)] {
+    entries.sort_by_key(|entry| (entry.tag, entry.id));
+    let mask = entries
+      .iter()
+      .find(|entry| entry.tag == Tag::Mask)
+      .map_or(0o7, |entry| entry.perms);
+    for entry in entries {
+      let (kind, name) = match (entry.tag, entry.id) {
+        (Tag::UserObj, _) => ("owner", user_name(&db, metadata.uid())),
+        (Tag::User, Some(uid)) => ("user", user_name(&db, uid)),
+        (Tag::GroupObj, _) => ("owning group", group_name(&db, metadata.gid())),
+        (Tag::Group, Some(gid)) => ("group", group_name(&db, gid)),
+        (Tag::Mask, _) => ("mask", String::new()),
+        _ => ("other", String::new()),
+      };
+      let effective = if entry.tag.is_masked() {
+        entry.perms & mask
+      } else {
+        entry.perms
+      };
+      rows.push(AclRow {
+        scope,
+        kind,
+        name,
+        permissions: acl::perms_string(entry.perms),
+        effective: acl::perms_string(effective),
+      });
+    }
+  }
+  Ok(rows)
+}
+
+/// Calls `visit` for everything below `dir`, without following symbolic
+/// links, which have no ACLs of their own.
+fn visit_tree(dir: &Path, visit: &mut impl FnMut(&Path, &fs::Metadata)) {
+  let Ok(entries) = fs::read_dir(dir) else {
+    return;
+  };
+  for entry in entries.flatten() {
+    let path = entry.path();
+    let Ok(metadata) = fs::symlink_metadata(&path) else {
+      continue;
+    };
+    if metadata.file_type().is_symlink() {
+      continue;
+    }
+    visit(&path, &metadata);
+    if metadata.is_dir() {
+      visit_tree(&path, visit);
+    }
+  }
+}
@@ -0,0 +1,149 @@
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+
+const PRJQUOTA: libc::c_int = 2;
+const FS_XFLAG_PROJINHERIT: u32 = 0x0000_0200;
+const FS_IOC_FSGETXATTR: libc::c_ulong = 0x801c_581f;
+const FS_IOC_FSSETXATTR: libc::c_ulong = 0x401c_5820;
+
+/// `struct fsxattr` from linux/fs.h
+#[repr(C)]
+#[derive(Default)]
+struct FsXattr {
+  xflags: u32,
+  extsize: u32,
+  nextents: u32,
+  projid: u32,
+  cowextsize: u32,
+  pad: [u8; 8],
+}
+
+/// Parses a size such as `512K`, `500M` or `2G` into bytes, using binary
+/// units. Bare numbers are bytes.
+pub(crate) fn parse_size(value: &str) -> Result<u64, String> {
+  let value = value.trim();
+  let split = value
+    .find(|c: char| !c.is_ascii_digit())
+    .unwrap_or(value.len());
+  let (amount, unit) = value.split_at(split);
+
+  let amount: u64 = amount
+    .parse()
+    .map_err(|_| format!("Invalid size '{}'", value))?;
+  let shift = match unit.to_ascii_uppercase().as_str() {
+    "" | "B" => 0,
+    "K" => 10,
+    "M" => 20,
+    "G" => 30,
+    "T" => 40,
+    _ => {
+      return Err(format!(
+        "Invalid size '{}': expected a unit of K, M, G or T",
+        value
+      ));
+    }
+  };
+
+  amount
+    .checked_mul(1 << shift)
+    .filter(|bytes| *bytes > 0)
+    .ok_or_else(|| format!("Invalid size '{}'", value))
+}
+
+/// Formats a byte count with the largest binary unit that divides it.
+pub(crate) fn format_size(bytes: u64) -> String {
+  [(40, "T"), (30, "G"), (20, "M"), (10, "K")]
+    .into_iter()
+    .find(|(shift, _)| bytes >= 1 << shift && bytes.is_multiple_of(1 << shift))
+    .map_or(bytes.to_string(), |(shift, unit)| {
+      format!("{}{}", bytes >> shift, unit)
+    })
+}
+
+/// Tags `dir` with project `project`, inherited by everything created in it,
+/// and limits the project's disk usage to `bytes`. The filesystem must be
+/// mounted with project quotas enabled.
+pub(crate) fn set_project_quota(dir: &Path, project: u32, bytes: u64) -> Result<(), String> {
+  use std::os::unix::io::AsRawFd;
+
+  let file = fs::File::open(dir).map_err(|e| format!("Failed to open {}: {}", dir.display(), e))?;
+  let mut attr = FsXattr::default();
+  // SAFETY: attr matches the kernel's struct fsxattr layout.
+  let mut rc = unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FSGETXATTR as _, &mut attr) };
+  if rc == 0 {
+    attr.projid = project;
+    attr.xflags |= FS_XFLAG_PROJINHERIT;
+    // SAFETY: as above.
+    rc = unsafe { libc::ioctl(file.as_raw_fd(), FS_IOC_FSSETXATTR as _, &attr) };
+  }
+  if rc != 0 {
+    return Err(format!(
+      "Failed to set project ID on {}: {}",
+      dir.display(),
+      std::io::Error::last_os_error()
+    ));
+  }
+
+  set_block_limit(dir, project, bytes / 1024)
+}
+
+/// Removes the block limit of `project` on the filesystem containing `path`.
+pub(crate) fn clear_project_quota(path: &Path, project: u32) -> Result<(), String> {
+  set_block_limit(path, project, 0)
+}
+
+fn set_block_limit(path: &Path, project: u32, kib: u64) -> Result<(), String> {
+  let device = mount_source(path).ok_or_else(|| {
+    format!(
+      "Failed to find the filesystem containing {}",
+      path.display()
+    )
+  })?;
+  let device_c =
+    CString::new(device.as_str()).map_err(|_| format!("Invalid device name {}", device))?;
+
+  // SAFETY: dqblk is a plain C struct; all-zero is a valid initial value.
+  let mut quota: libc::dqblk = unsafe { std::mem::zeroed() };
+  quota.dqb_bhardlimit = kib;
+  quota.dqb_bsoftlimit = kib;
+  quota.dqb_valid = libc::QIF_BLIMITS;
+
+  // SAFETY: device_c is NUL-terminated and quota outlives the call.
+  let rc = unsafe {
+    libc::quotactl(
+      libc::QCMD(libc::Q_SETQUOTA, PRJQUOTA),
+      device_c.as_ptr(),
+      project as libc::c_int,
+      (&mut quota as *mut libc::dqblk).cast(),
+    )
+  };
+  if rc != 0 {
+    return Err(format!(
+      "Failed to set project quota on {}: {}",
+      device,
+      std::io::Error::last_os_error()
+    ));
+  }
+  Ok(())
+}
+
+/// The device mounted at the deepest mount point containing `path`.
+fn mount_source(path: &Path) -> Option<String> {
+  let path = fs::canonicalize(path).ok()?;
+  let mountinfo = fs::read_to_string("/proc/self/mountinfo").ok()?;
+
+  mountinfo
+    .lines()
+    .filter_map(|line| {
+      let fields: Vec<&str> = line.split(' ').collect();
+      let mount_point = *fields.get(4)?;
+      let separator = fields.iter().position(|f| *f == "-")?;
+      let source = *fields.get(separator + 2)?;
+      path
+        .starts_with(mount_point)
+        .then(|| (mount_point.len(), source.to_string()))
+    })
+    .max_by_key(|(len, _)| *len)
+    .map(|(_, source)| source)
+}
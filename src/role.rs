@@ -0,0 +1,1267 @@
+use crate::acl::{self, Tag};
+use crate::cli::ListFormat;
+use crate::db::{self, Database};
+use crate::{perm, sudo, time, user};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fs;
+use std::path::Path;
+use tabled::{Table, Tabled};
+
+pub(crate) const ROLE_STORE_PATH: &str = "/etc/userkit/roles.toml";
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Role {
+  pub(crate) name: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) description: Option<String>,
+  /// Roles whose permissions this role includes
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub(crate) inherits: Vec<String>,
+  #[serde(default)]
+  pub(crate) permissions: Vec<String>,
+  /// Users the role is assigned to
+  #[serde(default)]
+  pub(crate) users: Vec<String>,
+  /// When time-bound assignments end, by user
+  #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+  pub(crate) expires: BTreeMap<String, u64>,
+}
+
+/// A change userkit made to give a user one permission of a role, kept so
+/// that revoking the role undoes exactly that and nothing granted elsewhere.
+#[derive(Serialize, Deserialize, Clone)]
+struct Applied {
+  user: String,
+  role: String,
+  permission: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  sudo_rule: Option<u32>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  previous_shell: Option<String>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct RoleStore {
+  #[serde(default, rename = "role")]
+  roles: Vec<Role>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  applied: Vec<Applied>,
+}
+
+/// What a role permission does when the role is assigned. Permissions
+/// without a known prefix are plain labels.
+enum Permission<'a> {
+  /// `group:NAME`: membership of a supplementary group
+  Group(&'a str),
+  /// `sudo:COMMAND`: a sudo rule for the command
+  Sudo(&'a str),
+  /// `path:PATH:PERMS`: a named user ACL entry
+  Path {
+    path: &'a str,
+    perms: &'a str,
+  },
+  /// `shell:PATH`: the login shell
+  Shell(&'a str),
+  Label,
+}
+
+impl<'a> Permission<'a> {
+  fn parse(text: &'a str) -> Self {
+    match text.split_once(':') {
+      Some(("group", name)) => Permission::Group(name),
+      Some(("sudo", command)) => Permission::Sudo(command),
+      Some(("path", rest)) => {
+        let (path, perms) = rest.rsplit_once(':').unwrap_or((rest, ""));
+        Permission::Path { path, perms }
+      }
+      Some(("shell", shell)) => Permission::Shell(shell),
+      _ => Permission::Label,
+    }
+  }
+
+  fn validate(&self, db: &Database) -> Result<(), String> {
+    match *self {
+      Permission::Group(name) if db.group(name).is_none() => {
+        Err(format!("Group {} does not exist", name))
+      }
+      Permission::Sudo(command) if !sudo::is_valid_command(command) => Err(format!(
+        "Invalid command '{}': use an absolute path or a command alias",
+        command
+      )),
+      Permission::Path { path, perms } => {
+        if !path.starts_with('/') || perms.is_empty() || perm::parse_acl_perms(perms).is_none() {
+          Err(String::from(
+            "Invalid path permission: expected path:/absolute/path:PERMS, e.g. path:/srv/app:rwx",
+          ))
+        } else {
+          Ok(())
+        }
+      }
+      Permission::Shell(shell) if !shell.starts_with('/') || !Path::new(shell).is_file() => {
+        Err(format!("Shell {} does not exist", shell))
+      }
+      _ => Ok(()),
+    }
+  }
+}
+
+impl RoleStore {
+  fn load() -> Result<Self, String> {
+    let path = crate::root::path(ROLE_STORE_PATH);
+    match fs::read_to_string(&path) {
+      Ok(content) => {
+        toml::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+      }
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(RoleStore::default()),
+      Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+  }
+
+  fn save(&self) -> Result<(), String> {
+    crate::backup::before_write()?;
+    let path = crate::root::path(ROLE_STORE_PATH);
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let content =
+      toml::to_string_pretty(self).map_err(|e| format!("Failed to serialize roles: {}", e))?;
+    fs::write(&path, content).map_err(|e| format!("Failed to write to {}: {}", path.display(), e))
+  }
+
+  /// Loads the store, applies `f` and saves the result if `f` succeeded.
+  fn update<T>(f: impl FnOnce(&mut RoleStore) -> Result<T, String>) -> Result<T, String> {
+    let mut store = RoleStore::load()?;
+    let result = f(&mut store)?;
+    store.save()?;
+    Ok(result)
+  }
+
+  /// Whether `role` exists, is assigned to `user` and has `permission`,
+  /// directly or inherited.
+  fn grants(&self, role: &str, user: &str, permission: &str) -> bool {
+    self
+      .role(role)
+      .is_ok_and(|r| r.users.iter().any(|u| u == user))
+      && self
+        .effective(role)
+        .is_ok_and(|permissions| permissions.iter().any(|(p, _)| p == permission))
+  }
+
+  /// `name` followed by every role it inherits from, depth first, each
+  /// once. Fails on a missing parent or an inheritance cycle.
+  fn lineage(&self, name: &str) -> Result<Vec<&Role>, String> {
+    let mut lineage = Vec::new();
+    self.visit_lineage(name, &mut Vec::new(), &mut lineage)?;
+    Ok(lineage)
+  }
+
+  fn visit_lineage<'a>(
+    &'a self,
+    name: &str,
+    path: &mut Vec<String>,
+    lineage: &mut Vec<&'a Role>,
+  ) -> Result<(), String> {
+    if let Some(start) = path.iter().position(|role| role == name) {
+      let mut cycle = path[start..].to_vec();
+      cycle.push(name.to_string());
+      return Err(format!("Role inheritance cycle: {}", cycle.join(" -> ")));
+    }
+    let role = self.role(name)?;
+    if !lineage.iter().any(|r| r.name == name) {
+      lineage.push(role);
+    }
+    path.push(name.to_string());
+    for parent in &role.inherits {
+      self.visit_lineage(parent, path, lineage)?;
+    }
+    path.pop();
+    Ok(())
+  }
+
+  /// The permissions of `name`, its own first, each with the role it comes
+  /// from.
+  fn effective(&self, name: &str) -> Result<Vec<(String, String)>, String> {
+    let mut permissions: Vec<(String, String)> = Vec::new();
+    for role in self.lineage(name)? {
+      for permission in &role.permissions {
+        if !permissions.iter().any(|(p, _)| p == permission) {
+          permissions.push((permission.clone(), role.name.clone()));
+        }
+      }
+    }
+    Ok(permissions)
+  }
+
+  /// Every assignment of a role that is `name` or inherits from it, as
+  /// (role, user) pairs.
+  fn assignments_including(&self, name: &str) -> Vec<(String, String)> {
+    self
+      .roles
+      .iter()
+      .filter(|role| {
+        self
+          .lineage(&role.name)
+          .is_ok_and(|lineage| lineage.iter().any(|r| r.name == name))
+      })
+      .flat_map(|role| {
+        role
+          .users
+          .iter()
+          .map(|user| (role.name.clone(), user.clone()))
+      })
+      .collect()
+  }
+
+  fn role(&self, name: &str) -> Result<&Role, String> {
+    self
+      .roles
+      .iter()
+      .find(|role| role.name == name)
+      .ok_or_else(|| format!("Role {} does not exist", name))
+  }
+
+  fn role_mut(&mut self, name: &str) -> Result<&mut Role, String> {
+    self
+      .roles
+      .iter_mut()
+      .find(|role| role.name == name)
+      .ok_or_else(|| format!("Role {} does not exist", name))
+  }
+}
+
+/// Runs a store modification that needs root, printing its message on
+/// success and the error otherwise.
+fn modify(f: impl FnOnce(&mut RoleStore) -> Result<String, String>) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+  match RoleStore::update(f) {
+    Ok(message) => {
+      println!("{}", message);
+      true
+    }
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      false
+    }
+  }
+}
+
+/// Every role in the store.
+pub(crate) fn roles() -> Result<Vec<Role>, String> {
+  Ok(RoleStore::load()?.roles)
+}
+
+/// Groups `username` is a member of because a role put it there.
+pub(crate) fn held_groups(username: &str) -> Result<Vec<String>, String> {
+  Ok(
+    RoleStore::load()?
+      .applied
+      .iter()
+      .filter(|record| record.user == username)
+      .filter_map(|record| match Permission::parse(&record.permission) {
+        Permission::Group(name) => Some(name.to_string()),
+        _ => None,
+      })
+      .collect(),
+  )
+}
+
+pub(crate) fn create(name: &str, description: Option<&str>, inherits: &[String]) -> bool {
+  modify(|store| {
+    if !db::is_valid_name(name) {
+      return Err(format!(
+        "Invalid role name '{}': use lowercase letters, digits, underscores and dashes",
+        name
+      ));
+    }
+    if store.roles.iter().any(|role| role.name == name) {
+      return Err(format!("Role {} already exists", name));
+    }
+    let mut parents: Vec<String> = Vec::new();
+    for parent in inherits {
+      store.role(parent)?;
+      if !parents.contains(parent) {
+        parents.push(parent.clone());
+      }
+    }
+    store.roles.push(Role {
+      name: name.to_string(),
+      description: description.map(str::to_string),
+      inherits: parents,
+      permissions: Vec::new(),
+      users: Vec::new(),
+      expires: BTreeMap::new(),
+    });
+    // Parents exist, so a cycle can only come from a stale reference to a
+    // role of the same name.
+    store.lineage(name)?;
+    Ok(format!("Role {} created", name))
+  })
+}
+
+pub(crate) fn delete(name: &str, force: bool) -> bool {
+  modify(|store| {
+    let role = store.role(name)?;
+    if !role.users.is_empty() && !force {
+      return Err(format!(
+        "Role {} is assigned to {}; use --force to revoke it and delete the role",
+        name,
+        role.users.join(", ")
+      ));
+    }
+    let children: Vec<String> = store
+      .roles
+      .iter()
+      .filter(|role| role.inherits.iter().any(|parent| parent == name))
+      .map(|role| role.name.clone())
+      .collect();
+    if !children.is_empty() && !force {
+      return Err(format!(
+        "Role {} is inherited by {}; use --force to remove it from them and delete the role",
+        name,
+        children.join(", ")
+      ));
+    }
+
+    let users = role.users.clone();
+    store.roles.retain(|role| role.name != name);
+    for role in &mut store.roles {
+      role.inherits.retain(|parent| parent != name);
+    }
+
+    let mut lines = vec![format!("Role {} deleted", name)];
+    for username in users {
+      lines.push(format!("Role {} revoked from user {}", name, username));
+    }
+    for child in children {
+      lines.push(format!("Role {} no longer inherits from {}", child, name));
+    }
+    lines.extend(release_stale(store).into_iter().map(|(_, change)| change));
+    Ok(lines.join("\n"))
+  })
+}
+
+/// Replaces the description and parents of a role, applying permissions it
+/// now inherits to its users and undoing those it no longer does.
+pub(crate) fn redefine(name: &str, description: Option<&str>, inherits: &[String]) -> bool {
+  modify(|store| {
+    let mut parents: Vec<String> = Vec::new();
+    for parent in inherits {
+      store.role(parent)?;
+      if !parents.contains(parent) {
+        parents.push(parent.clone());
+      }
+    }
+    let role = store.role_mut(name)?;
+    role.description = description.map(str::to_string);
+    role.inherits = parents;
+    store.lineage(name)?;
+
+    let mut lines = vec![format!("Role {} updated", name)];
+    for (role, username) in store.assignments_including(name) {
+      let permissions: Vec<String> = store
+        .effective(&role)?
+        .into_iter()
+        .map(|(p, _)| p)
+        .collect();
+      lines.extend(apply_all(store, &[(role, username)], &permissions)?);
+    }
+    lines.extend(release_stale(store).into_iter().map(|(_, change)| change));
+    Ok(lines.join("\n"))
+  })
+}
+
+/// Assigns a role, for a limited time when `duration` (e.g. `4h`) or
+/// `until` (a date or date and time) is given. Assigning a role again
+/// changes when the assignment ends.
+pub(crate) fn assign(
+  name: &str,
+  username: &str,
+  duration: Option<&str>,
+  until: Option<&str>,
+) -> bool {
+  let expires = match (duration, until) {
+    (Some(duration), _) => time::parse_duration(duration).map(|secs| Some(time::now() + secs)),
+    (None, Some(until)) => time::parse_expiry(until).map(Some),
+    (None, None) => Ok(None),
+  };
+  let expires = match expires {
+    Ok(Some(expires)) if expires <= time::now() => {
+      eprintln!("Error: The assignment would end in the past");
+      return false;
+    }
+    Ok(expires) => expires,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  let until_text = expires.map_or(String::new(), |expires| {
+    format!(" until {}", time::format_timestamp(expires))
+  });
+
+  modify(|store| {
+    let role = store.role(name)?;
+    if Database::load()?.user(username).is_none() {
+      return Err(format!("User {} does not exist", username));
+    }
+    if role.users.iter().any(|user| user == username) {
+      if expires.is_none() && !role.expires.contains_key(username) {
+        return Err(format!(
+          "Role {} is already assigned to user {}",
+          name, username
+        ));
+      }
+      set_expiry(store.role_mut(name)?, username, expires);
+      return Ok(match expires {
+        Some(_) => format!(
+          "Role {} assignment for user {} now lasts{}",
+          name, username, until_text
+        ),
+        None => format!(
+          "Role {} assignment for user {} is now permanent",
+          name, username
+        ),
+      });
+    }
+
+    let permissions: Vec<String> = store.effective(name)?.into_iter().map(|(p, _)| p).collect();
+    let mut lines = vec![format!(
+      "Role {} assigned to user {}{}",
+      name, username, until_text
+    )];
+    lines.extend(apply_all(
+      store,
+      &[(name.to_string(), username.to_string())],
+      &permissions,
+    )?);
+    let role = store.role_mut(name)?;
+    role.users.push(username.to_string());
+    set_expiry(role, username, expires);
+    Ok(lines.join("\n"))
+  })
+}
+
+fn set_expiry(role: &mut Role, username: &str, expires: Option<u64>) {
+  match expires {
+    Some(expires) => role.expires.insert(username.to_string(), expires),
+    None => role.expires.remove(username),
+  };
+}
+
+pub(crate) fn revoke(name: &str, username: &str) -> bool {
+  modify(|store| {
+    let role = store.role_mut(name)?;
+    if !role.users.iter().any(|user| user == username) {
+      return Err(format!(
+        "Role {} is not assigned to user {}",
+        name, username
+      ));
+    }
+    role.users.retain(|user| user != username);
+    role.expires.remove(username);
+
+    let mut lines = vec![format!("Role {} revoked from user {}", name, username)];
+    lines.extend(release_stale(store).into_iter().map(|(_, change)| change));
+    Ok(lines.join("\n"))
+  })
+}
+
+pub(crate) fn add_perm(name: &str, permission: &str) -> bool {
+  modify(|store| {
+    let role = store.role(name)?;
+    let permission = permission.trim();
+    if permission.is_empty() || permission.contains(char::is_control) {
+      return Err(format!("Invalid permission '{}'", permission));
+    }
+    if role.permissions.iter().any(|p| p == permission) {
+      return Err(format!(
+        "Role {} already has permission {}",
+        name, permission
+      ));
+    }
+    Permission::parse(permission).validate(&Database::load()?)?;
+    store.lineage(name)?;
+
+    let assignments = store.assignments_including(name);
+    let mut lines = vec![format!("Permission {} added to role {}", permission, name)];
+    lines.extend(apply_all(store, &assignments, &[permission.to_string()])?);
+    store
+      .role_mut(name)?
+      .permissions
+      .push(permission.to_string());
+    Ok(lines.join("\n"))
+  })
+}
+
+pub(crate) fn remove_perm(name: &str, permission: &str) -> bool {
+  modify(|store| {
+    let role = store.role_mut(name)?;
+    if !role.permissions.iter().any(|p| p == permission) {
+      return Err(format!(
+        "Role {} does not have permission {}",
+        name, permission
+      ));
+    }
+    role.permissions.retain(|p| p != permission);
+
+    let mut lines = vec![format!(
+      "Permission {} removed from role {}",
+      permission, name
+    )];
+    lines.extend(release_stale(store).into_iter().map(|(_, change)| change));
+    Ok(lines.join("\n"))
+  })
+}
+
+/// Re-applies every role to its users, reporting and fixing drift: changes
+/// that were undone behind userkit's back, permissions that were never
+/// applied and changes for roles or assignments that no longer exist.
+pub(crate) fn sync() -> bool {
+  modify(|store| {
+    let db = Database::load()?;
+    let mut lines = Vec::new();
+
+    // Changes nothing grants any more
+    for (record, change) in release_stale(store) {
+      lines.push(format!(
+        "{} ({} no longer grants {} to {})",
+        change.trim(),
+        record.role,
+        record.permission,
+        record.user
+      ));
+    }
+
+    // Changes that were undone since
+    let (kept, drifted): (Vec<Applied>, Vec<Applied>) =
+      store.applied.drain(..).partition(in_effect);
+    store.applied = kept;
+    for record in drifted {
+      if let Some(change) = apply(store, &record.role, &record.user, &record.permission)? {
+        lines.push(format!("{} (had been undone outside userkit)", change));
+      }
+    }
+
+    // Permissions that were never applied
+    let mut assignments = Vec::new();
+    for role in &store.roles {
+      for (permission, _) in store.effective(&role.name)? {
+        for user in &role.users {
+          assignments.push((role.name.clone(), user.clone(), permission.clone()));
+        }
+      }
+    }
+    for (role, username, permission) in assignments {
+      if db.user(&username).is_none() {
+        lines.push(format!(
+          "Warning: role {} is assigned to {}, who no longer exists",
+          role, username
+        ));
+        continue;
+      }
+      if let Some(change) = apply(store, &role, &username, &permission)? {
+        lines.push(format!("{} (missing for {})", change, username));
+      }
+    }
+
+    lines.dedup();
+    if lines.is_empty() {
+      Ok(String::from("Roles are in sync"))
+    } else {
+      lines.insert(0, String::from("Roles synced, drift corrected:"));
+      Ok(lines.join("\n  "))
+    }
+  })
+}
+
+/// Applies `permissions` for each (role, user) assignment. On failure, the
+/// changes already made are undone before the error is returned.
+fn apply_all(
+  store: &mut RoleStore,
+  assignments: &[(String, String)],
+  permissions: &[String],
+) -> Result<Vec<String>, String> {
+  let before = store.applied.len();
+  let mut lines = Vec::new();
+  for (role, username) in assignments {
+    for permission in permissions {
+      match apply(store, role, username, permission) {
+        Ok(Some(change)) => lines.push(format!("  {}", change)),
+        Ok(None) => {}
+        Err(e) => {
+          let made: Vec<Applied> = store.applied.drain(before..).collect();
+          for record in made.iter().rev() {
+            if let Err(undo_error) = undo(record) {
+              eprintln!("Warning: {}", undo_error);
+            }
+          }
+          return Err(e);
+        }
+      }
+    }
+  }
+  Ok(lines)
+}
+
+/// Gives `username` one permission of `role` and records the change, unless
+/// the user already has it, from another role or from elsewhere.
+fn apply(
+  store: &mut RoleStore,
+  role: &str,
+  username: &str,
+  permission: &str,
+) -> Result<Option<String>, String> {
+  if store
+    .applied
+    .iter()
+    .any(|record| record.user == username && record.permission == permission)
+  {
+    return Ok(None);
+  }
+  let mut record = Applied {
+    user: username.to_string(),
+    role: role.to_string(),
+    permission: permission.to_string(),
+    sudo_rule: None,
+    previous_shell: None,
+  };
+
+  let change = match Permission::parse(permission) {
+    Permission::Group(group) => {
+      let mut db = Database::open()?;
+      let user = account(&db, username)?;
+      let entry = db
+        .group(group)
+        .ok_or_else(|| format!("Group {} does not exist", group))?;
+      if entry.gid == user.gid || entry.members.iter().any(|m| m == username) {
+        return Ok(None);
+      }
+      db.add_group_member(group, username);
+      db.commit()?;
+      format!("{} added to group {}", username, group)
+    }
+    Permission::Sudo(command) => {
+      let (id, rendered) = sudo::add_role_rule(username, command, role)?;
+      record.sudo_rule = Some(id);
+      format!("sudo rule {} added: {}", id, rendered)
+    }
+    Permission::Path { path, perms } => {
+      let uid = account(&Database::load()?, username)?.uid;
+      if has_user_acl(Path::new(path), uid)? {
+        return Ok(None);
+      }
+      perm::set_user_acl(Path::new(path), uid, Some(perms))?;
+      format!("ACL entry u:{}:{} added to {}", username, perms, path)
+    }
+    Permission::Shell(shell) => {
+      let mut db = Database::open()?;
+      let mut user = account(&db, username)?;
+      if user.shell == shell {
+        return Ok(None);
+      }
+      record.previous_shell = Some(std::mem::replace(&mut user.shell, shell.to_string()));
+      db.set_user(&user);
+      db.commit()?;
+      format!("login shell of {} set to {}", username, shell)
+    }
+    Permission::Label => return Ok(None),
+  };
+  store.applied.push(record);
+  Ok(Some(change))
+}
+
+/// Undoes the recorded changes that their role no longer calls for, and
+/// returns them with a description of each. A change another role assigned
+/// to the same user still calls for is handed over to that role instead.
+fn release_stale(store: &mut RoleStore) -> Vec<(Applied, String)> {
+  let (stale, kept): (Vec<Applied>, Vec<Applied>) = std::mem::take(&mut store.applied)
+    .into_iter()
+    .partition(|record| !store.grants(&record.role, &record.user, &record.permission));
+  store.applied = kept;
+
+  let mut released = Vec::new();
+  for mut record in stale {
+    let heir = store
+      .roles
+      .iter()
+      .find(|other| store.grants(&other.name, &record.user, &record.permission))
+      .map(|other| other.name.clone());
+    if let Some(heir) = heir {
+      record.role = heir;
+      store.applied.push(record);
+      continue;
+    }
+    match undo(&record) {
+      Ok(Some(change)) => released.push((record, format!("  {}", change))),
+      Ok(None) => {}
+      Err(e) => {
+        eprintln!("Warning: {}", e);
+        store.applied.push(record);
+      }
+    }
+  }
+  released
+}
+
+/// Reverts a recorded change, leaving alone anything changed again since.
+fn undo(record: &Applied) -> Result<Option<String>, String> {
+  let username = record.user.as_str();
+  match Permission::parse(&record.permission) {
+    Permission::Group(group) => {
+      let mut db = Database::open()?;
+      if !db.remove_group_member(group, username) {
+        return Ok(None);
+      }
+      db.commit()?;
+      Ok(Some(format!("{} removed from group {}", username, group)))
+    }
+    Permission::Sudo(_) => match record.sudo_rule {
+      Some(id) if sudo::remove_rule(id)? => Ok(Some(format!("sudo rule {} removed", id))),
+      _ => Ok(None),
+    },
+    Permission::Path { path, .. } => {
+      let Some(user) = Database::load()?.user(username) else {
+        return Ok(None);
+      };
+      if !Path::new(path).exists() || !has_user_acl(Path::new(path), user.uid)? {
+        return Ok(None);
+      }
+      perm::set_user_acl(Path::new(path), user.uid, None)?;
+      Ok(Some(format!(
+        "ACL entry u:{} removed from {}",
+        username, path
+      )))
+    }
+    Permission::Shell(shell) => {
+      let mut db = Database::open()?;
+      let (Some(mut user), Some(previous)) = (db.user(username), record.previous_shell.clone())
+      else {
+        return Ok(None);
+      };
+      if user.shell != shell {
+        return Ok(None);
+      }
+      user.shell = previous;
+      db.set_user(&user);
+      db.commit()?;
+      Ok(Some(format!(
+        "login shell of {} restored to {}",
+        username, user.shell
+      )))
+    }
+    Permission::Label => Ok(None),
+  }
+}
+
+/// Whether a recorded change is still in place.
+fn in_effect(record: &Applied) -> bool {
+  let Ok(db) = Database::load() else {
+    return true;
+  };
+  let Some(user) = db.user(&record.user) else {
+    return true;
+  };
+  match Permission::parse(&record.permission) {
+    Permission::Group(group) => db
+      .group(group)
+      .is_some_and(|entry| entry.gid == user.gid || entry.members.contains(&record.user)),
+    Permission::Sudo(_) => record
+      .sudo_rule
+      .is_some_and(|id| sudo::rule_exists(id).unwrap_or(true)),
+    Permission::Path { path, .. } => has_user_acl(Path::new(path), user.uid).unwrap_or(true),
+    Permission::Shell(shell) => user.shell == shell,
+    Permission::Label => true,
+  }
+}
+
+fn account(db: &Database, username: &str) -> Result<db::PasswdEntry, String> {
+  db.user(username)
+    .ok_or_else(|| format!("User {} does not exist", username))
+}
+
+fn has_user_acl(path: &Path, uid: u32) -> Result<bool, String> {
+  Ok(
+    acl::read(path, false)?
+      .iter()
+      .any(|entry| entry.tag == Tag::User && entry.id == Some(uid)),
+  )
+}
+
+#[derive(Tabled)]
+struct RoleTable {
+  #[tabled(rename = "Name")]
+  name: String,
+  #[tabled(rename = "Description")]
+  description: String,
+  #[tabled(rename = "Inherits")]
+  inherits: String,
+  #[tabled(rename = "Permissions")]
+  permissions: usize,
+  #[tabled(rename = "Users")]
+  users: usize,
+}
+
+pub(crate) fn list(format: &ListFormat) -> bool {
+  let store = match RoleStore::load() {
+    Ok(store) => store,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  match format {
+    ListFormat::Table => {
+      let table_data: Vec<RoleTable> = store
+        .roles
+        .into_iter()
+        .map(|role| RoleTable {
+          name: role.name,
+          description: role.description.unwrap_or_default(),
+          inherits: role.inherits.join(", "),
+          permissions: role.permissions.len(),
+          users: role.users.len(),
+        })
+        .collect();
+      println!("Available roles:");
+      println!("{}", Table::new(table_data));
+    }
+    ListFormat::Json => match serde_json::to_string_pretty(&store.roles) {
+      Ok(json) => println!("{}", json),
+      Err(e) => {
+        eprintln!("Error: Failed to serialize roles to JSON: {}", e);
+        return false;
+      }
+    },
+    ListFormat::Csv => {
+      println!("name,description,inherits,permissions,users");
+      let quote = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+      for role in store.roles {
+        println!(
+          "{},{},{},{},{}",
+          role.name,
+          quote(role.description.as_deref().unwrap_or("")),
+          quote(&role.inherits.join(" ")),
+          quote(&role.permissions.join(" ")),
+          quote(&role.users.join(" "))
+        );
+      }
+    }
+  }
+  true
+}
+
+pub(crate) fn info(name: &str, effective: bool) -> bool {
+  let store = match RoleStore::load() {
+    Ok(store) => store,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  let role = match store.role(name) {
+    Ok(role) => role,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  let permissions = if effective {
+    match store.effective(name) {
+      Ok(permissions) => permissions,
+      Err(e) => {
+        eprintln!("Error: {}", e);
+        return false;
+      }
+    }
+  } else {
+    role
+      .permissions
+      .iter()
+      .map(|p| (p.clone(), role.name.clone()))
+      .collect()
+  };
+  println!("Role: {}", role.name);
+  if let Some(description) = &role.description {
+    println!("Description: {}", description);
+  }
+  if !role.inherits.is_empty() {
+    println!("Inherits: {}", role.inherits.join(", "));
+  }
+
+  let heading = if effective {
+    "Effective permissions"
+  } else {
+    "Permissions"
+  };
+  if permissions.is_empty() {
+    println!("{}: none", heading);
+  } else {
+    println!("{}:", heading);
+    for (permission, origin) in &permissions {
+      if origin == name {
+        println!("  {}", permission);
+      } else {
+        println!("  {} (from {})", permission, origin);
+      }
+    }
+  }
+  if role.users.is_empty() {
+    println!("Users: none");
+  } else {
+    let users: Vec<String> = role
+      .users
+      .iter()
+      .map(|user| match role.expires.get(user) {
+        Some(expires) => format!("{} (until {})", user, time::format_timestamp(*expires)),
+        None => user.clone(),
+      })
+      .collect();
+    println!("Users: {}", users.join(", "));
+  }
+  true
+}
+
+/// Prints the inheritance hierarchy with parents above the roles that
+/// inherit from them. A role with several parents appears under each.
+pub(crate) fn tree() -> bool {
+  let store = match RoleStore::load() {
+    Ok(store) => store,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  if store.roles.is_empty() {
+    println!("No roles defined");
+    return true;
+  }
+  if let Some(e) = store
+    .roles
+    .iter()
+    .find_map(|role| store.lineage(&role.name).err())
+  {
+    eprintln!("Error: {}", e);
+    return false;
+  }
+
+  let exists = |name: &str| store.roles.iter().any(|role| role.name == name);
+  for root in store
+    .roles
+    .iter()
+    .filter(|role| !role.inherits.iter().any(|parent| exists(parent)))
+  {
+    println!("{}", root.name);
+    print_children(&store, &root.name, "");
+  }
+  true
+}
+
+fn print_children(store: &RoleStore, name: &str, prefix: &str) {
+  let children: Vec<&Role> = store
+    .roles
+    .iter()
+    .filter(|role| role.inherits.iter().any(|parent| parent == name))
+    .collect();
+  for (index, child) in children.iter().enumerate() {
+    let last = index + 1 == children.len();
+    println!(
+      "{}{}{}",
+      prefix,
+      if last { "└── " } else { "├── " },
+      child.name
+    );
+    let indent = format!("{}{}", prefix, if last { "    " } else { "│   " });
+    print_children(store, &child.name, &indent);
+  }
+}
+
+#[derive(Tabled, Serialize)]
+struct GrantRow {
+  #[tabled(rename = "User")]
+  user: String,
+  #[tabled(rename = "Role")]
+  role: String,
+  /// The role the permission comes from, `role` or one it inherits from
+  #[tabled(rename = "From")]
+  from: String,
+  #[tabled(rename = "Permission")]
+  permission: String,
+}
+
+/// Every (user, role, origin, permission) that role assignments give,
+/// optionally only for one user or one permission.
+fn grant_rows(
+  store: &RoleStore,
+  username: Option<&str>,
+  permission: Option<&str>,
+) -> Result<Vec<GrantRow>, String> {
+  let mut rows = Vec::new();
+  for role in &store.roles {
+    let effective = store.effective(&role.name)?;
+    for user in &role.users {
+      if username.is_some_and(|u| u != user) {
+        continue;
+      }
+      for (granted, from) in &effective {
+        if permission.is_some_and(|p| p != granted) {
+          continue;
+        }
+        rows.push(GrantRow {
+          user: user.clone(),
+          role: role.name.clone(),
+          from: from.clone(),
+          permission: granted.clone(),
+        });
+      }
+    }
+  }
+  rows.sort_by(|a, b| a.user.cmp(&b.user));
+  Ok(rows)
+}
+
+fn print_grant_rows(rows: Vec<GrantRow>, format: &ListFormat, title: &str, empty: &str) -> bool {
+  match format {
+    ListFormat::Table => {
+      if rows.is_empty() {
+        println!("{}", empty);
+      } else {
+        println!("{}", title);
+        println!("{}", Table::new(rows));
+      }
+    }
+    ListFormat::Json => match serde_json::to_string_pretty(&rows) {
+      Ok(json) => println!("{}", json),
+      Err(e) => {
+        eprintln!("Error: Failed to serialize role grants to JSON: {}", e);
+        return false;
+      }
+    },
+    ListFormat::Csv => {
+      println!("user,role,from,permission");
+      let quote = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+      for row in rows {
+        println!(
+          "{},{},{},{}",
+          row.user,
+          row.role,
+          row.from,
+          quote(&row.permission)
+        );
+      }
+    }
+  }
+  true
+}
+
+/// Lists the users that get `permission` through any role.
+pub(crate) fn who_has(permission: &str, format: &ListFormat) -> bool {
+  let rows = match RoleStore::load().and_then(|store| grant_rows(&store, None, Some(permission))) {
+    Ok(rows) => rows,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  print_grant_rows(
+    rows,
+    format,
+    &format!("Users with permission {}:", permission),
+    &format!("No user has permission {} through a role", permission),
+  )
+}
+
+/// Lists the roles assigned to `username` and the permissions they give.
+pub(crate) fn user_roles(username: &str, format: &ListFormat) -> bool {
+  let result = (|| {
+    if !db::read_users()?.iter().any(|user| user.name == username) {
+      return Err(format!("User {} does not exist", username));
+    }
+    let store = RoleStore::load()?;
+    let roles: Vec<String> = store
+      .roles
+      .iter()
+      .filter(|role| role.users.iter().any(|u| u == username))
+      .map(|role| match role.expires.get(username) {
+        Some(expires) => format!("{} (until {})", role.name, time::format_timestamp(*expires)),
+        None => role.name.clone(),
+      })
+      .collect();
+    Ok((roles, grant_rows(&store, Some(username), None)?))
+  })();
+  let (roles, rows) = match result {
+    Ok(result) => result,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  if matches!(format, ListFormat::Table) {
+    if roles.is_empty() {
+      println!("User {} has no roles", username);
+      return true;
+    }
+    println!("Roles for user {}: {}", username, roles.join(", "));
+  }
+  print_grant_rows(
+    rows,
+    format,
+    "Effective permissions:",
+    "Effective permissions: none",
+  )
+}
+
+#[derive(Tabled, Serialize)]
+struct DiffRow {
+  #[tabled(rename = "Permission")]
+  permission: String,
+  #[tabled(rename = "Status")]
+  status: String,
+}
+
+/// Compares the effective permissions of two roles.
+pub(crate) fn diff(first: &str, second: &str, format: &ListFormat) -> bool {
+  let result = RoleStore::load().and_then(|store| {
+    let a = store.effective(first)?;
+    let b = store.effective(second)?;
+    let mut rows = Vec::new();
+    for (permission, _) in &a {
+      let status = if b.iter().any(|(p, _)| p == permission) {
+        String::from("in both")
+      } else {
+        format!("only in {}", first)
+      };
+      rows.push(DiffRow {
+        permission: permission.clone(),
+        status,
+      });
+    }
+    for (permission, _) in &b {
+      if !a.iter().any(|(p, _)| p == permission) {
+        rows.push(DiffRow {
+          permission: permission.clone(),
+          status: format!("only in {}", second),
+        });
+      }
+    }
+    Ok(rows)
+  });
+  let rows = match result {
+    Ok(rows) => rows,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  match format {
+    ListFormat::Table => {
+      if rows.is_empty() {
+        println!("Roles {} and {} have no permissions", first, second);
+      } else {
+        println!("Permissions of roles {} and {}:", first, second);
+        println!("{}", Table::new(rows));
+      }
+    }
+    ListFormat::Json => match serde_json::to_string_pretty(&rows) {
+      Ok(json) => println!("{}", json),
+      Err(e) => {
+        eprintln!("Error: Failed to serialize role diff to JSON: {}", e);
+        return false;
+      }
+    },
+    ListFormat::Csv => {
+      println!("permission,status");
+      let quote = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+      for row in rows {
+        println!("{},{}", quote(&row.permission), row.status);
+      }
+    }
+  }
+  true
+}
+
+/// Revokes time-bound assignments that have ended, logging each revocation
+/// to syslog.
+pub(crate) fn reap(dry_run: bool) -> bool {
+  if !dry_run && !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  let now = time::now();
+  let result = (|| {
+    let mut store = RoleStore::load()?;
+    let mut expired = Vec::new();
+    for role in &mut store.roles {
+      let ended: Vec<(String, u64)> = role
+        .expires
+        .iter()
+        .filter(|(_, expires)| **expires <= now)
+        .map(|(user, expires)| (user.clone(), *expires))
+        .collect();
+      for (user, expires) in ended {
+        if !dry_run {
+          role.users.retain(|u| *u != user);
+          role.expires.remove(&user);
+        }
+        expired.push((role.name.clone(), user, expires));
+      }
+    }
+    if dry_run || expired.is_empty() {
+      return Ok((expired, Vec::new()));
+    }
+    let changes = release_stale(&mut store);
+    store.save()?;
+    Ok::<_, String>((expired, changes))
+  })();
+
+  let (expired, changes) = match result {
+    Ok(result) => result,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  if expired.is_empty() {
+    println!("No expired role assignments");
+    return true;
+  }
+  for (role, user, expires) in &expired {
+    let message = format!(
+      "role {} from user {} (expired {})",
+      role,
+      user,
+      time::format_timestamp(*expires)
+    );
+    if dry_run {
+      println!("Would revoke {}", message);
+    } else {
+      println!("Revoked {}", message);
+      log_notice(&format!("revoked {}", message));
+    }
+  }
+  for (_, change) in changes {
+    println!("{}", change);
+  }
+  true
+}
+
+fn log_notice(message: &str) {
+  let (Ok(ident), Ok(message)) = (CString::new("userkit"), CString::new(message)) else {
+    return;
+  };
+  // SAFETY: all strings are NUL-terminated and the format takes one string.
+  unsafe {
+    libc::openlog(ident.as_ptr(), libc::LOG_PID, libc::LOG_AUTHPRIV);
+    libc::syslog(libc::LOG_NOTICE, c"%s".as_ptr(), message.as_ptr());
+    libc::closelog();
+  }
+}
@@ -0,0 +1,19 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Resolves system paths under the directory given with `--root`, as
+/// `useradd --root` does, instead of `/`.
+pub(crate) fn use_root(dir: &Path) {
+  let _ = ROOT.set(dir.to_path_buf());
+}
+
+/// `path` under the `--root` directory, or `path` itself without one.
+pub(crate) fn path(path: impl AsRef<Path>) -> PathBuf {
+  let path = path.as_ref();
+  match ROOT.get() {
+    Some(root) => root.join(path.strip_prefix("/").unwrap_or(path)),
+    None => path.to_path_buf(),
+  }
+}
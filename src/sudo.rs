@@ -0,0 +1,1032 @@
+use crate::cli::ListFormat;
+use crate::config::{Config, SudoMethod};
+use crate::db::{Accounts, Database};
+use crate::sudoers::{self, Principal, SUDOERS_DIR};
+use crate::user;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tabled::{Table, Tabled};
+
+pub(crate) const SUDO_STORE_PATH: &str = "/var/lib/userkit/sudo.toml";
+const DROPIN_PREFIX: &str = "userkit-";
+const MANAGED_HEADER: &str = "# Managed by userkit";
+/// The drop-in holding the rules added with `perm sudo grant`. Per-user
+/// drop-ins always have a dash after the prefix, so it cannot clash.
+const RULES_DROPIN: &str = "userkit";
+
+/// Admin groups distributions grant sudo to, in order of preference.
+const ADMIN_GROUPS: [&str; 3] = ["sudo", "wheel", "admin"];
+
+/// Sudo access userkit manages: group memberships it added to grant sudo
+/// access, so that disabling removes only those, and the rules added with
+/// `perm sudo grant`.
+#[derive(Default, Serialize, Deserialize)]
+struct SudoStore {
+  #[serde(default, rename = "grant")]
+  grants: Vec<GroupGrant>,
+  #[serde(default, rename = "rule")]
+  rules: Vec<SudoRule>,
+}
+
+/// A rule added with `perm sudo grant` or by a role, rendered into the rules
+/// drop-in.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct SudoRule {
+  pub(crate) id: u32,
+  /// A username or `%group`
+  pub(crate) subject: String,
+  pub(crate) hosts: Vec<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) runas: Option<String>,
+  #[serde(default)]
+  pub(crate) nopasswd: bool,
+  /// Command paths with arguments, or names of configured command aliases
+  pub(crate) commands: Vec<String>,
+  /// The role the rule was added for, if any
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub(crate) role: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GroupGrant {
+  user: String,
+  group: String,
+}
+
+impl SudoStore {
+  fn load() -> Result<Self, String> {
+    let path = crate::root::path(SUDO_STORE_PATH);
+    match fs::read_to_string(&path) {
+      Ok(content) => {
+        toml::from_str(&content).map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+      }
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SudoStore::default()),
+      Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+  }
+
+  fn save(&self) -> Result<(), String> {
+    crate::backup::before_write()?;
+    let path = crate::root::path(SUDO_STORE_PATH);
+    if let Some(dir) = path.parent() {
+      fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+    }
+    let content = toml::to_string_pretty(self)
+      .map_err(|e| format!("Failed to serialize sudo grants: {}", e))?;
+    fs::write(&path, content).map_err(|e| format!("Failed to write to {}: {}", path.display(), e))
+  }
+
+  fn update(f: impl FnOnce(&mut SudoStore)) -> Result<(), String> {
+    let mut store = SudoStore::load()?;
+    f(&mut store);
+    store.save()
+  }
+}
+
+pub(crate) fn enable(username: &str) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  match grant_access(username) {
+    Ok(message) => {
+      println!("{}", message);
+      true
+    }
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      false
+    }
+  }
+}
+
+fn grant_access(username: &str) -> Result<String, String> {
+  let db = Accounts::load()?;
+  if db.user(username).is_none() {
+    return Err(format!("User {} does not exist", username));
+  }
+  let config = Config::load()?;
+
+  match config.sudo.method {
+    SudoMethod::Dropin => {
+      let content = format!(
+        "{} for {}\n{} ALL=(ALL:ALL) ALL\n",
+        MANAGED_HEADER, username, username
+      );
+      install_dropin(&dropin_path(username), &content)?;
+      warn_if_not_included();
+      Ok(format!("Sudo access enabled for {}", username))
+    }
+    SudoMethod::Group => {
+      let group = admin_group(&config, &db)?;
+      if principal(&db, username)?
+        .groups
+        .iter()
+        .any(|(name, _)| *name == group)
+      {
+        return Ok(format!(
+          "Sudo access enabled for {} (already a member of {})",
+          username, group
+        ));
+      }
+
+      let mut db = Database::open()?;
+      db.add_group_member(&group, username);
+      db.commit()?;
+      SudoStore::update(|store| {
+        store.grants.push(GroupGrant {
+          user: username.to_string(),
+          group: group.clone(),
+        })
+      })?;
+      Ok(format!(
+        "Sudo access enabled for {} through group {}",
+        username, group
+      ))
+    }
+  }
+}
+
+pub(crate) fn disable(username: &str) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  if let Err(e) = revoke_access(username) {
+    eprintln!("Error: {}", e);
+    return false;
+  }
+  println!("Sudo access disabled for {}", username);
+
+  // Access from rules userkit did not add is left alone, but worth a word.
+  if let Ok(sources) = access_sources(username)
+    && !sources.is_empty()
+  {
+    eprintln!("Warning: {} still has sudo access through:", username);
+    for source in sources {
+      eprintln!("  {}", source.description);
+    }
+  }
+  true
+}
+
+/// Removes the drop-in file and the group memberships userkit added.
+fn revoke_access(username: &str) -> Result<(), String> {
+  let path = dropin_path(username);
+  if is_managed_dropin(&path) {
+    crate::backup::before_write()?;
+    fs::remove_file(&path).map_err(|e| format!("Failed to remove {}: {}", path.display(), e))?;
+  }
+
+  let store = SudoStore::load()?;
+  let groups: Vec<&str> = store
+    .grants
+    .iter()
+    .filter(|grant| grant.user == username)
+    .map(|grant| grant.group.as_str())
+    .collect();
+  if !groups.is_empty() {
+    let mut db = Database::open()?;
+    for group in &groups {
+      db.remove_group_member(group, username);
+    }
+    db.commit()?;
+    SudoStore::update(|store| store.grants.retain(|grant| grant.user != username))?;
+  }
+  Ok(())
+}
+
+/// A sudoers rule that gives a user sudo rights.
+struct AccessSource {
+  description: String,
+  managed: bool,
+}
+
+pub(crate) fn status(username: &str) -> bool {
+  let db = match Accounts::load() {
+    Ok(db) => db,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  if db.user(username).is_none() {
+    eprintln!("Error: User {} does not exist", username);
+    return false;
+  }
+  let sources = match access_sources(username) {
+    Ok(sources) => sources,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+
+  let managed = sources.iter().any(|source| source.managed);
+  println!(
+    "Sudo access for {}: {}",
+    username,
+    match (sources.is_empty(), managed) {
+      (true, _) => "disabled",
+      (false, true) => "enabled (managed by userkit)",
+      (false, false) => "enabled",
+    }
+  );
+  for source in &sources {
+    println!("  {}", source.description);
+  }
+
+  let path = dropin_path(username);
+  if is_managed_dropin(&path) && !sudoers::includes_dir(SUDOERS_DIR) {
+    println!(
+      "  {} exists but {} does not include {}",
+      path.display(),
+      sudoers::SUDOERS_PATH,
+      SUDOERS_DIR
+    );
+  }
+  true
+}
+
+/// Every rule across the sudoers files that matches the user and allows at
+/// least one command.
+fn access_sources(username: &str) -> Result<Vec<AccessSource>, String> {
+  let db = Accounts::load()?;
+  let who = principal(&db, username)?;
+  let store = SudoStore::load()?;
+  let files = sudoers::load_all()?;
+  let aliases = sudoers::aliases(&files, sudoers::AliasKind::User);
+  let own_dropin = dropin_path(username);
+  let rules_dropin = crate::root::path(SUDOERS_DIR).join(RULES_DROPIN);
+
+  let mut sources = Vec::new();
+  for file in &files {
+    for entry in &file.entries {
+      let sudoers::Entry::Rule(rule) = entry else {
+        continue;
+      };
+      let allows_something = rule
+        .privileges
+        .iter()
+        .flat_map(|privilege| &privilege.commands)
+        .any(|command| !command.negated);
+      if !allows_something || !sudoers::user_list_matches(&rule.users, &who, &aliases) {
+        continue;
+      }
+
+      let mut description = format!("{}:{}: {}", file.path.display(), rule.line, rule.text);
+      let mut managed =
+        (file.path == own_dropin || file.path == rules_dropin) && is_managed_dropin(&file.path);
+      // A %group rule that matches because of a membership userkit added.
+      let added_group = store.grants.iter().find(|grant| {
+        grant.user == username && rule.users.iter().any(|u| *u == format!("%{}", grant.group))
+      });
+      if let Some(grant) = added_group {
+        description.push_str(&format!(
+          " (through group {}, added by userkit)",
+          grant.group
+        ));
+        managed = true;
+      } else if managed {
+        description.push_str(" (managed by userkit)");
+      }
+      sources.push(AccessSource {
+        description,
+        managed,
+      });
+    }
+  }
+  Ok(sources)
+}
+
+pub(crate) fn principal(db: &Accounts, username: &str) -> Result<Principal, String> {
+  let user = db
+    .user(username)
+    .ok_or_else(|| format!("User {} does not exist", username))?;
+  let mut groups: Vec<(String, u32)> = db
+    .groups()
+    .into_iter()
+    .filter(|group| group.gid == user.gid)
+    .map(|group| (group.name, group.gid))
+    .collect();
+  for group in db.supplementary_groups(username) {
+    if !groups.iter().any(|(name, _)| *name == group.name) {
+      groups.push((group.name, group.gid));
+    }
+  }
+  Ok(Principal {
+    name: user.name,
+    uid: user.uid,
+    groups,
+  })
+}
+
+/// The configured admin group, or the first of the usual ones that exists.
+fn admin_group(config: &Config, db: &Accounts) -> Result<String, String> {
+  if let Some(group) = &config.sudo.admin_group {
+    return match db.group(group) {
+      Some(_) => Ok(group.clone()),
+      None => Err(format!("Admin group {} does not exist", group)),
+    };
+  }
+  ADMIN_GROUPS
+    .iter()
+    .find(|group| db.group(group).is_some())
+    .map(|group| group.to_string())
+    .ok_or_else(|| {
+      format!(
+        "None of the admin groups {} exist; set sudo.admin_group in {}",
+        ADMIN_GROUPS.join(", "),
+        crate::config::CONFIG_PATH
+      )
+    })
+}
+
+fn warn_if_not_included() {
+  if !sudoers::includes_dir(SUDOERS_DIR) {
+    eprintln!(
+      "Warning: {} does not include {}, so the rule has no effect until it does",
+      sudoers::SUDOERS_PATH,
+      SUDOERS_DIR
+    );
+  }
+}
+
+/// sudo skips files whose names contain a dot, so dots in usernames are
+/// replaced.
+pub(crate) fn dropin_path(username: &str) -> PathBuf {
+  crate::root::path(SUDOERS_DIR).join(format!("{}{}", DROPIN_PREFIX, username.replace('.', "_")))
+}
+
+/// Whether a sudoers.d file is one userkit wrote.
+pub(crate) fn is_managed_dropin(path: &Path) -> bool {
+  fs::read_to_string(path).is_ok_and(|content| content.starts_with(MANAGED_HEADER))
+}
+
+/// Checks `content` with the built-in sudoers parser, then installs it
+/// atomically as a root-owned 0440 file. The temporary name contains a dot
+/// so sudo never reads a half-written file.
+pub(crate) fn install_dropin(path: &Path, content: &str) -> Result<(), String> {
+  use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt, PermissionsExt};
+
+  sudoers::parse(content).map_err(|e| format!("Generated sudoers rule is invalid: {}", e))?;
+
+  crate::backup::before_write()?;
+  let dir = path.parent().unwrap_or(Path::new("/"));
+  if !dir.exists() {
+    fs::DirBuilder::new()
+      .recursive(true)
+      .mode(0o750)
+      .create(dir)
+      .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+  }
+
+  let name = path
+    .file_name()
+    .map_or_else(String::new, |n| n.to_string_lossy().to_string());
+  let temp = dir.join(format!(".{}.tmp", name));
+  let write = || -> std::io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+      .write(true)
+      .create(true)
+      .truncate(true)
+      .mode(0o440)
+      .open(&temp)?;
+    file.write_all(content.as_bytes())?;
+    file.set_permissions(fs::Permissions::from_mode(0o440))?;
+    std::os::unix::fs::fchown(&file, Some(0), Some(0))?;
+    file.sync_all()?;
+    fs::rename(&temp, path)
+  };
+  write().map_err(|e| {
+    let _ = fs::remove_file(&temp);
+    format!("Failed to install {}: {}", path.display(), e)
+  })
+}
+
+pub(crate) fn grant(
+  subject: &str,
+  commands: &[String],
+  runas: Option<&str>,
+  nopasswd: bool,
+  hosts: &[String],
+) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  let result = (|| {
+    validate_subject(subject)?;
+    if let Some(runas) = runas {
+      validate_runas(runas)?;
+    }
+    add_rule(SudoRule {
+      id: 0,
+      subject: subject.to_string(),
+      hosts: if hosts.is_empty() {
+        vec![String::from("ALL")]
+      } else {
+        hosts.to_vec()
+      },
+      runas: runas.map(str::to_string),
+      nopasswd,
+      commands: commands.to_vec(),
+      role: None,
+    })
+  })();
+
+  match result {
+    Ok((id, rendered)) => {
+      println!("Sudo rule {} granted: {}", id, rendered);
+      warn_if_not_included();
+      true
+    }
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      false
+    }
+  }
+}
+
+pub(crate) fn revoke(id: u32) -> bool {
+  if !user::has_escalated_privileges() {
+    eprintln!("Error: This operation requires root privileges. Please run with sudo.");
+    return false;
+  }
+
+  let result = remove_rule(id).and_then(|removed| {
+    if removed {
+      Ok(())
+    } else {
+      Err(format!("Sudo rule {} does not exist", id))
+    }
+  });
+
+  match result {
+    Ok(()) => {
+      println!("Sudo rule {} revoked", id);
+      true
+    }
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      false
+    }
+  }
+}
+
+#[derive(Tabled, Serialize)]
+struct RuleRow {
+  #[tabled(rename = "ID")]
+  id: u32,
+  #[tabled(rename = "Subject")]
+  subject: String,
+  #[tabled(rename = "Hosts")]
+  hosts: String,
+  #[tabled(rename = "Run As")]
+  runas: String,
+  #[tabled(rename = "NOPASSWD")]
+  nopasswd: bool,
+  #[tabled(rename = "Commands")]
+  commands: String,
+  #[tabled(rename = "Role")]
+  role: String,
+}
+
+pub(crate) fn list(format: &ListFormat) -> bool {
+  let store = match SudoStore::load() {
+    Ok(store) => store,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  let rows: Vec<RuleRow> = store
+    .rules
+    .iter()
+    .map(|rule| RuleRow {
+      id: rule.id,
+      subject: rule.subject.clone(),
+      hosts: rule.hosts.join(", "),
+      runas: rule.runas.clone().unwrap_or_else(|| String::from("root")),
+      nopasswd: rule.nopasswd,
+      commands: rule.commands.join(", "),
+      role: rule.role.clone().unwrap_or_default(),
+    })
+    .collect();
+
+  match format {
+    ListFormat::Table => {
+      if rows.is_empty() {
+        println!("No sudo rules managed by userkit");
+      } else {
+        println!("Sudo rules:");
+        println!("{}", Table::new(rows));
+      }
+    }
+    ListFormat::Json => match serde_json::to_string_pretty(&rows) {
+      Ok(json) => println!("{}", json),
+      Err(e) => {
+        eprintln!("Error: Failed to serialize sudo rules to JSON: {}", e);
+        return false;
+      }
+    },
+    ListFormat::Csv => {
+      println!("id,subject,hosts,runas,nopasswd,commands,role");
+      for row in rows {
+        let quote = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+        println!(
+          "{},{},{},{},{},{},{}",
+          row.id,
+          row.subject,
+          quote(&row.hosts),
+          row.runas,
+          row.nopasswd,
+          quote(&row.commands),
+          row.role
+        );
+      }
+    }
+  }
+  true
+}
+
+/// Rules added with `perm sudo grant`, leaving out those of roles.
+pub(crate) fn granted_rules() -> Result<Vec<SudoRule>, String> {
+  let mut rules = SudoStore::load()?.rules;
+  rules.retain(|rule| rule.role.is_none());
+  Ok(rules)
+}
+
+/// Admin groups `username` was added to by `perm sudo enable`.
+pub(crate) fn held_groups(username: &str) -> Result<Vec<String>, String> {
+  Ok(
+    SudoStore::load()?
+      .grants
+      .into_iter()
+      .filter(|grant| grant.user == username)
+      .map(|grant| grant.group)
+      .collect(),
+  )
+}
+
+/// Assigns the rule an ID, stores it and rewrites the rules drop-in,
+/// returning the ID and the rule as rendered.
+fn add_rule(mut rule: SudoRule) -> Result<(u32, String), String> {
+  validate_rule(&rule)?;
+  let mut store = SudoStore::load()?;
+  rule.id = store.rules.iter().map(|rule| rule.id).max().unwrap_or(0) + 1;
+  let result = (rule.id, render_rule(&rule));
+  store.rules.push(rule);
+  write_rules(&store.rules)?;
+  store.save()?;
+  Ok(result)
+}
+
+/// Adds a rule letting `username` run `command` on behalf of a role.
+pub(crate) fn add_role_rule(
+  username: &str,
+  command: &str,
+  role: &str,
+) -> Result<(u32, String), String> {
+  add_rule(SudoRule {
+    id: 0,
+    subject: username.to_string(),
+    hosts: vec![String::from("ALL")],
+    runas: None,
+    nopasswd: false,
+    commands: vec![command.to_string()],
+    role: Some(role.to_string()),
+  })
+}
+
+/// Removes a rule, returning whether it existed.
+pub(crate) fn remove_rule(id: u32) -> Result<bool, String> {
+  let mut store = SudoStore::load()?;
+  let before = store.rules.len();
+  store.rules.retain(|rule| rule.id != id);
+  if store.rules.len() == before {
+    return Ok(false);
+  }
+  write_rules(&store.rules)?;
+  store.save()?;
+  Ok(true)
+}
+
+pub(crate) fn rule_exists(id: u32) -> Result<bool, String> {
+  Ok(SudoStore::load()?.rules.iter().any(|rule| rule.id == id))
+}
+
+/// Whether `command` can be used in a rule: an absolute path with optional
+/// arguments, or the name of a command alias. Control characters are never
+/// allowed, as a newline would start a rule of its own.
+pub(crate) fn is_valid_command(command: &str) -> bool {
+  (command.starts_with('/') && !command.contains(char::is_control))
+    || sudoers::is_alias_name(command)
+}
+
+/// Checks every part of a rule that ends up in the drop-in, so that nothing
+/// can add a line or a rule of its own to it.
+pub(crate) fn validate_rule(rule: &SudoRule) -> Result<(), String> {
+  // Existing accounts may have names userkit would not create, such as
+  // Debian-exim, so only characters sudoers gives a meaning are refused
+  let is_name = |name: &str| {
+    !name.is_empty()
+      && name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '$'))
+  };
+  let subject = rule.subject.strip_prefix('%').unwrap_or(&rule.subject);
+  if !is_name(subject) {
+    return Err(format!("Invalid subject '{}'", rule.subject));
+  }
+  if let Some(host) = rule.hosts.iter().find(|h| !sudoers::is_valid_host(h)) {
+    return Err(format!(
+      "Invalid host '{}': use ALL, a host name, an address or a network",
+      host.escape_debug()
+    ));
+  }
+  if let Some(runas) = &rule.runas {
+    let (user, group) = runas.split_once(':').unwrap_or((runas, ""));
+    let valid = |name: &str| name.is_empty() || is_name(name);
+    if !valid(user) || !valid(group) {
+      return Err(format!("Invalid runas '{}'", runas));
+    }
+  }
+  if let Some(command) = rule.commands.iter().find(|c| !is_valid_command(c)) {
+    return Err(format!(
+      "Invalid command '{}': use an absolute path or a command alias",
+      command.escape_debug()
+    ));
+  }
+  Ok(())
+}
+
+fn validate_subject(subject: &str) -> Result<(), String> {
+  let db = Database::load()?;
+  match subject.strip_prefix('%') {
+    Some(group) if db.group(group).is_some() => Ok(()),
+    Some(group) => Err(format!("Group {} does not exist", group)),
+    None if db.user(subject).is_some() => Ok(()),
+    None => Err(format!("User {} does not exist", subject)),
+  }
+}
+
+/// Accepts `USER`, `USER:GROUP` or `:GROUP`, where either may be `ALL`.
+fn validate_runas(runas: &str) -> Result<(), String> {
+  let db = Database::load()?;
+  let (user, group) = runas.split_once(':').unwrap_or((runas, ""));
+  if !user.is_empty() && user != "ALL" && db.user(user).is_none() {
+    return Err(format!("User {} does not exist", user));
+  }
+  if !group.is_empty() && group != "ALL" && db.group(group).is_none() {
+    return Err(format!("Group {} does not exist", group));
+  }
+  if user.is_empty() && group.is_empty() {
+    return Err(format!("Invalid runas '{}'", runas));
+  }
+  Ok(())
+}
+
+/// Renders the rules, with the command aliases they use, into the rules
+/// drop-in, or removes it once no rules are left.
+fn write_rules(rules: &[SudoRule]) -> Result<(), String> {
+  let path = crate::root::path(SUDOERS_DIR).join(RULES_DROPIN);
+  if rules.is_empty() {
+    if !path.exists() {
+      return Ok(());
+    }
+    crate::backup::before_write()?;
+    return match fs::remove_file(&path) {
+      Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+        Err(format!("Failed to remove {}: {}", path.display(), e))
+      }
+      _ => Ok(()),
+    };
+  }
+
+  let config = Config::load()?;
+  let mut content = format!(
+    "{}; edit with `userkit perm sudo grant` and `userkit perm sudo revoke`\n",
+    MANAGED_HEADER
+  );
+
+  let mut used: Vec<&str> = Vec::new();
+  for command in rules.iter().flat_map(|rule| &rule.commands) {
+    if !command.starts_with('/') && !used.contains(&command.as_str()) {
+      used.push(command);
+    }
+  }
+  for name in used {
+    let members = config.sudo.command_aliases.get(name).ok_or_else(|| {
+      format!(
+        "Command alias {} is not defined in {}",
+        name,
+        crate::config::CONFIG_PATH
+      )
+    })?;
+    if !sudoers::is_alias_name(name) {
+      return Err(format!(
+        "Invalid command alias name {}: use uppercase letters, digits and underscores",
+        name
+      ));
+    }
+    if let Some(member) = members
+      .iter()
+      .find(|m| !m.starts_with('/') || m.contains(char::is_control))
+    {
+      return Err(format!(
+        "Invalid command '{}' in alias {}: use an absolute path",
+        member.escape_debug(),
+        name
+      ));
+    }
+    let members: Vec<String> = members.iter().map(|m| escape_command(m)).collect();
+    content.push_str(&format!("Cmnd_Alias {} = {}\n", name, members.join(", ")));
+  }
+
+  for rule in rules {
+    content.push_str(&format!("\n# rule {}\n{}\n", rule.id, render_rule(rule)));
+  }
+  install_dropin(&path, &content)
+}
+
+pub(crate) fn render_rule(rule: &SudoRule) -> String {
+  let commands: Vec<String> = rule.commands.iter().map(|c| escape_command(c)).collect();
+  format!(
+    "{} {} = {}{}{}",
+    rule.subject,
+    rule.hosts.join(", "),
+    rule
+      .runas
+      .as_ref()
+      .map_or(String::new(), |runas| format!("({}) ", runas)),
+    if rule.nopasswd { "NOPASSWD: " } else { "" },
+    commands.join(", ")
+  )
+}
+
+/// Escapes the characters sudoers treats specially in command arguments.
+fn escape_command(command: &str) -> String {
+  let mut escaped = String::new();
+  for c in command.chars() {
+    if matches!(c, ',' | ':' | '=' | '\\') {
+      escaped.push('\\');
+    }
+    escaped.push(c);
+  }
+  escaped
+}
+
+/// Programs that start a shell when run through sudo.
+const SHELLS: [&str; 10] = [
+  "sh", "bash", "dash", "zsh", "ksh", "csh", "tcsh", "fish", "su", "sudo",
+];
+
+/// Programs that can run arbitrary commands or write arbitrary files, so
+/// granting them is as good as granting a shell unless NOEXEC is set.
+const SHELL_ESCAPES: [&str; 27] = [
+  "vi", "vim", "nvim", "view", "ed", "emacs", "nano", "less", "more", "man", "find", "awk", "gawk",
+  "env", "perl", "python", "python3", "ruby", "lua", "php", "tar", "zip", "rsync", "git", "ssh",
+  "tee", "dd",
+];
+
+#[derive(Tabled, Serialize)]
+struct AuditRow {
+  #[tabled(rename = "User")]
+  user: String,
+  #[tabled(rename = "Source")]
+  source: String,
+  #[tabled(rename = "Hosts")]
+  hosts: String,
+  #[tabled(rename = "Run As")]
+  runas: String,
+  #[tabled(rename = "Commands")]
+  commands: String,
+  #[tabled(rename = "Flags")]
+  flags: String,
+  #[tabled(rename = "Danger")]
+  danger: String,
+}
+
+/// Prints what each user (or just `username`) may run through sudo, with
+/// aliases and `%group` references resolved and dangerous grants flagged.
+pub(crate) fn audit(username: Option<&str>, format: &ListFormat) -> bool {
+  let result = (|| {
+    let db = Accounts::load()?;
+    let users = match username {
+      Some(username) => vec![
+        db.user(username)
+          .ok_or_else(|| format!("User {} does not exist", username))?,
+      ],
+      None => db.users(),
+    };
+    let files = sudoers::load_all()?;
+    let mut rows = Vec::new();
+    for user in users {
+      rows.extend(audit_user(&db, &files, &user.name)?);
+    }
+    // Unknown names are worth reporting when auditing the whole system only.
+    let warnings = match username {
+      Some(_) => Vec::new(),
+      None => unresolved_references(&db, &files),
+    };
+    Ok::<_, String>((rows, warnings))
+  })();
+
+  let (rows, warnings) = match result {
+    Ok(result) => result,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      return false;
+    }
+  };
+  for warning in warnings {
+    eprintln!("Warning: {}", warning);
+  }
+
+  match format {
+    ListFormat::Table => {
+      if rows.is_empty() {
+        println!("No user has sudo access");
+      } else {
+        println!("Sudo access audit:");
+        println!("{}", Table::new(rows));
+      }
+    }
+    ListFormat::Json => match serde_json::to_string_pretty(&rows) {
+      Ok(json) => println!("{}", json),
+      Err(e) => {
+        eprintln!("Error: Failed to serialize sudo audit to JSON: {}", e);
+        return false;
+      }
+    },
+    ListFormat::Csv => {
+      println!("user,source,hosts,runas,commands,flags,danger");
+      let quote = |value: &str| format!("\"{}\"", value.replace('"', "\"\""));
+      for row in rows {
+        println!(
+          "{},{},{},{},{},{},{}",
+          row.user,
+          row.source,
+          quote(&row.hosts),
+          quote(&row.runas),
+          quote(&row.commands),
+          quote(&row.flags),
+          quote(&row.danger)
+        );
+      }
+    }
+  }
+  true
+}
+
+/// One row per command of each rule that applies to `username`.
+fn audit_user(
+  db: &Accounts,
+  files: &[sudoers::SudoersFile],
+  username: &str,
+) -> Result<Vec<AuditRow>, String> {
+  let who = principal(db, username)?;
+  let user_aliases = sudoers::aliases(files, sudoers::AliasKind::User);
+  let host_aliases = sudoers::aliases(files, sudoers::AliasKind::Host);
+  let runas_aliases = sudoers::aliases(files, sudoers::AliasKind::Runas);
+  let command_aliases = sudoers::aliases(files, sudoers::AliasKind::Cmnd);
+
+  let mut rows = Vec::new();
+  for file in files {
+    for entry in &file.entries {
+      let sudoers::Entry::Rule(rule) = entry else {
+        continue;
+      };
+      if !sudoers::user_list_matches(&rule.users, &who, &user_aliases) {
+        continue;
+      }
+      for privilege in &rule.privileges {
+        for spec in &privilege.commands {
+          let mut commands = sudoers::expand_aliases(
+            &[format!(
+              "{}{}",
+              if spec.negated { "!" } else { "" },
+              spec.command
+            )],
+            &command_aliases,
+          );
+          // `!!/bin/sh` from a negated alias of negated commands is a grant.
+          for command in &mut commands {
+            while let Some(rest) = command.strip_prefix("!!") {
+              *command = rest.to_string();
+            }
+          }
+
+          let mut flags: Vec<&str> = spec
+            .tags
+            .iter()
+            .map(String::as_str)
+            .filter(|tag| matches!(*tag, "NOPASSWD" | "SETENV" | "NOEXEC"))
+            .collect();
+          // sudo implies SETENV for the ALL command.
+          if commands.iter().any(|c| c == "ALL") && !spec.tags.iter().any(|t| t == "NOSETENV") {
+            flags.push("SETENV");
+          }
+          flags.sort_unstable();
+          flags.dedup();
+
+          rows.push(AuditRow {
+            user: username.to_string(),
+            source: format!("{}:{}", file.path.display(), rule.line),
+            hosts: sudoers::expand_aliases(&privilege.hosts, &host_aliases).join(", "),
+            runas: runas_string(spec.runas.as_ref(), &runas_aliases),
+            danger: dangers(&commands, spec.tags.iter().any(|t| t == "NOEXEC")).join(", "),
+            commands: commands.join(", "),
+            flags: flags.join(", "),
+          });
+        }
+      }
+    }
+  }
+  Ok(rows)
+}
+
+/// Renders a runas list as `users:groups`; no list means root.
+fn runas_string(runas: Option<&sudoers::Runas>, aliases: &HashMap<String, Vec<String>>) -> String {
+  let Some(runas) = runas else {
+    return String::from("root");
+  };
+  let users = sudoers::expand_aliases(&runas.users, aliases).join(", ");
+  let groups = sudoers::expand_aliases(&runas.groups, aliases).join(", ");
+  if groups.is_empty() {
+    if users.is_empty() {
+      String::from("root")
+    } else {
+      users
+    }
+  } else {
+    format!("{}:{}", users, groups)
+  }
+}
+
+/// Why granting `commands` is dangerous, if it is. Negated commands are not
+/// grants, and do not make an `ALL` in the same list any safer.
+fn dangers(commands: &[String], noexec: bool) -> Vec<&'static str> {
+  let mut dangers = Vec::new();
+  for command in commands.iter().filter(|c| !c.starts_with('!')) {
+    let mut words = command.split_whitespace();
+    let program = words.next().unwrap_or("");
+    let name = program.rsplit('/').next().unwrap_or(program);
+    let danger = if command == "ALL" {
+      Some("any command")
+    } else if SHELLS.contains(&name) {
+      Some("shell")
+    } else if !noexec && SHELL_ESCAPES.contains(&name) {
+      Some("shell escape")
+    } else if words.any(|arg| arg.contains('*')) {
+      Some("wildcard arguments")
+    } else {
+      None
+    };
+    if let Some(danger) = danger
+      && !dangers.contains(&danger)
+    {
+      dangers.push(danger);
+    }
+  }
+  dangers
+}
+
+/// Users and groups that rules name but the account database lacks.
+fn unresolved_references(db: &Accounts, files: &[sudoers::SudoersFile]) -> Vec<String> {
+  let user_aliases = sudoers::aliases(files, sudoers::AliasKind::User);
+  let mut warnings = Vec::new();
+  for file in files {
+    for entry in &file.entries {
+      let sudoers::Entry::Rule(rule) = entry else {
+        continue;
+      };
+      for item in sudoers::expand_aliases(&rule.users, &user_aliases) {
+        let item = item.trim_start_matches('!');
+        let missing = if let Some(group) = item.strip_prefix("%:").or(item.strip_prefix('%')) {
+          (!group.starts_with('#') && db.group(group).is_none()).then(|| format!("group {}", group))
+        } else if item == "ALL" || item.starts_with('#') || user_aliases.contains_key(item) {
+          None
+        } else {
+          db.user(item).is_none().then(|| format!("user {}", item))
+        };
+        if let Some(missing) = missing {
+          warnings.push(format!(
+            "{}:{}: unknown {}",
+            file.path.display(),
+            rule.line,
+            missing
+          ));
+        }
+      }
+    }
+  }
+  warnings
+}
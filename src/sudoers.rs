@@ -0,0 +1,603 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub(crate) const SUDOERS_PATH: &str = "/etc/sudoers";
+pub(crate) const SUDOERS_DIR: &str = "/etc/sudoers.d";
+
+/// Tags that may prefix a command, e.g. `NOPASSWD:`.
+const TAGS: [&str; 16] = [
+  "EXEC",
+  "NOEXEC",
+  "FOLLOW",
+  "NOFOLLOW",
+  "LOG_INPUT",
+  "NOLOG_INPUT",
+  "LOG_OUTPUT",
+  "NOLOG_OUTPUT",
+  "MAIL",
+  "NOMAIL",
+  "INTERCEPT",
+  "NOINTERCEPT",
+  "PASSWD",
+  "NOPASSWD",
+  "SETENV",
+  "NOSETENV",
+];
+
+/// Options that may prefix a command, e.g. `CWD=/srv`.
+const OPTIONS: [&str; 8] = [
+  "CWD",
+  "CHROOT",
+  "TIMEOUT",
+  "NOTBEFORE",
+  "NOTAFTER",
+  "ROLE",
+  "TYPE",
+  "APPARMOR_PROFILE",
+];
+
+const DIGESTS: [&str; 4] = ["sha224", "sha256", "sha384", "sha512"];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum AliasKind {
+  User,
+  Runas,
+  Host,
+  Cmnd,
+}
+
+pub(crate) enum Entry {
+  Defaults,
+  Alias {
+    kind: AliasKind,
+    name: String,
+    members: Vec<String>,
+  },
+  Include {
+    path: String,
+    dir: bool,
+  },
+  Rule(Rule),
+}
+
+/// A user specification: who may run which commands on which hosts.
+pub(crate) struct Rule {
+  pub(crate) line: usize,
+  /// The rule as written, continuation lines joined
+  pub(crate) text: String,
+  pub(crate) users: Vec<String>,
+  pub(crate) privileges: Vec<Privilege>,
+}
+
+/// Commands a rule grants on a list of hosts.
+pub(crate) struct Privilege {
+  pub(crate) hosts: Vec<String>,
+  pub(crate) commands: Vec<CommandSpec>,
+}
+
+/// One command of a rule, with the runas list and tags in effect for it.
+pub(crate) struct CommandSpec {
+  pub(crate) negated: bool,
+  /// A path with optional arguments, `ALL`, `sudoedit` or a command alias
+  pub(crate) command: String,
+  /// `None` when no runas list was given, which means root
+  pub(crate) runas: Option<Runas>,
+  /// Tags such as `NOPASSWD`, as inherited from earlier commands
+  pub(crate) tags: Vec<String>,
+}
+
+/// A `(users : groups)` runas specification.
+#[derive(Clone)]
+pub(crate) struct Runas {
+  pub(crate) users: Vec<String>,
+  pub(crate) groups: Vec<String>,
+}
+
+/// A user as sudoers lists match it: by name, UID and group membership.
+pub(crate) struct Principal {
+  pub(crate) name: String,
+  pub(crate) uid: u32,
+  /// Names and GIDs of the primary and supplementary groups
+  pub(crate) groups: Vec<(String, u32)>,
+}
+
+/// A parsed sudoers file.
+pub(crate) struct SudoersFile {
+  pub(crate) path: PathBuf,
+  pub(crate) entries: Vec<Entry>,
+}
+
+/// Parses sudoers syntax, returning the first error with its line number.
+pub(crate) fn parse(content: &str) -> Result<Vec<Entry>, String> {
+  let mut entries = Vec::new();
+  let mut logical = String::new();
+  let mut start = 0;
+
+  for (index, raw) in content.lines().enumerate() {
+    if logical.is_empty() {
+      start = index + 1;
+    }
+    // A trailing backslash continues the line.
+    if let Some(stripped) = raw.strip_suffix('\\')
+      && !stripped.ends_with('\\')
+    {
+      logical.push_str(stripped);
+      logical.push(' ');
+      continue;
+    }
+    logical.push_str(raw);
+
+    entries.extend(parse_line(&logical, start).map_err(|e| format!("line {}: {}", start, e))?);
+    logical.clear();
+  }
+  if !logical.is_empty() {
+    return Err(format!("line {}: unterminated line continuation", start));
+  }
+  Ok(entries)
+}
+
+/// Parses `/etc/sudoers` and every file it includes, in the order sudo
+/// reads them. A missing main file yields an empty list.
+pub(crate) fn load_all() -> Result<Vec<SudoersFile>, String> {
+  let mut files = Vec::new();
+  let mut seen = HashSet::new();
+  let path = crate::root::path(SUDOERS_PATH);
+  if path.exists() {
+    load_file(&path, &mut files, &mut seen)?;
+  }
+  Ok(files)
+}
+
+fn load_file(
+  path: &Path,
+  files: &mut Vec<SudoersFile>,
+  seen: &mut HashSet<PathBuf>,
+) -> Result<(), String> {
+  if !seen.insert(path.to_path_buf()) {
+    return Ok(());
+  }
+  let content = fs::read_to_string(path).map_err(|e| match e.kind() {
+    std::io::ErrorKind::PermissionDenied => format!("Reading {} needs root", path.display()),
+    _ => format!("Failed to read {}: {}", path.display(), e),
+  })?;
+  let entries = parse(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+  let base = path.parent().unwrap_or(Path::new("/"));
+  let includes: Vec<(PathBuf, bool)> = entries
+    .iter()
+    .filter_map(|entry| match entry {
+      Entry::Include { path, dir } if Path::new(path).is_absolute() => {
+        Some((crate::root::path(path), *dir))
+      }
+      Entry::Include { path, dir } => Some((base.join(path), *dir)),
+      _ => None,
+    })
+    .collect();
+  files.push(SudoersFile {
+    path: path.to_path_buf(),
+    entries,
+  });
+
+  for (include, dir) in includes {
+    if dir {
+      for file in included_dir_files(&include) {
+        load_file(&file, files, seen)?;
+      }
+    } else if include.exists() {
+      load_file(&include, files, seen)?;
+    }
+  }
+  Ok(())
+}
+
+/// Files sudo reads from an `@includedir`: sorted, skipping names that
+/// contain a dot or end in a tilde.
+pub(crate) fn included_dir_files(dir: &Path) -> Vec<PathBuf> {
+  let Ok(entries) = fs::read_dir(dir) else {
+    return Vec::new();
+  };
+  let mut files: Vec<PathBuf> = entries
+    .flatten()
+    .filter(|entry| {
+      let name = entry.file_name().to_string_lossy().to_string();
+      !name.contains('.') && !name.ends_with('~') && entry.path().is_file()
+    })
+    .map(|entry| entry.path())
+    .collect();
+  files.sort();
+  files
+}
+
+/// Whether the main sudoers file reads `dir` through an includedir.
+pub(crate) fn includes_dir(dir: &str) -> bool {
+  let Ok(content) = fs::read_to_string(crate::root::path(SUDOERS_PATH)) else {
+    return false;
+  };
+  let Ok(entries) = parse(&content) else {
+    return false;
+  };
+  entries.iter().any(|entry| {
+    matches!(entry, Entry::Include { path, dir: true } if Path::new(path) == Path::new(dir))
+  })
+}
+
+/// Aliases of one kind defined across all files, by name.
+pub(crate) fn aliases(files: &[SudoersFile], kind: AliasKind) -> HashMap<String, Vec<String>> {
+  files
+    .iter()
+    .flat_map(|file| &file.entries)
+    .filter_map(|entry| match entry {
+      Entry::Alias {
+        kind: alias_kind,
+        name,
+        members,
+      } if *alias_kind == kind => Some((name.clone(), members.clone())),
+      _ => None,
+    })
+    .collect()
+}
+
+/// Replaces alias names in a list with their members, recursively. A
+/// negated alias negates each of its members, so `!` may end up doubled.
+pub(crate) fn expand_aliases(
+  list: &[String],
+  aliases: &HashMap<String, Vec<String>>,
+) -> Vec<String> {
+  expand(list, aliases, 0)
+}
+
+fn expand(list: &[String], aliases: &HashMap<String, Vec<String>>, depth: usize) -> Vec<String> {
+  let mut expanded = Vec::new();
+  for item in list {
+    let negated = item.starts_with('!');
+    let name = item.trim_start_matches('!').trim();
+    match aliases.get(name).filter(|_| depth < 16) {
+      Some(members) => {
+        for member in expand(members, aliases, depth + 1) {
+          expanded.push(if negated {
+            format!("!{}", member)
+          } else {
+            member
+          });
+        }
+      }
+      None => expanded.push(item.clone()),
+    }
+  }
+  expanded
+}
+
+/// Whether a user list such as `alice, %wheel, !bob` matches `who`. As in
+/// sudo, the last matching item decides and `!` negates it.
+pub(crate) fn user_list_matches(
+  list: &[String],
+  who: &Principal,
+  aliases: &HashMap<String, Vec<String>>,
+) -> bool {
+  list_match(list, who, aliases, 0) == Some(true)
+}
+
+fn list_match(
+  list: &[String],
+  who: &Principal,
+  aliases: &HashMap<String, Vec<String>>,
+  depth: usize,
+) -> Option<bool> {
+  let mut result = None;
+  for item in list {
+    let negated = item.starts_with('!');
+    let item = item.trim_start_matches('!').trim();
+    let group = |name: &str| who.groups.iter().any(|(group, _)| group == name);
+    let matched = if let Some(gid) = item.strip_prefix("%#") {
+      who.groups.iter().any(|(_, id)| gid.parse() == Ok(*id))
+    } else if let Some(name) = item.strip_prefix("%:") {
+      group(name)
+    } else if let Some(name) = item.strip_prefix('%') {
+      group(name)
+    } else if let Some(uid) = item.strip_prefix('#') {
+      uid.parse() == Ok(who.uid)
+    } else if item == "ALL" {
+      true
+    } else if let Some(members) = aliases.get(item).filter(|_| depth < 16) {
+      list_match(members, who, aliases, depth + 1) == Some(true)
+    } else {
+      item == who.name
+    };
+    if matched {
+      result = Some(!negated);
+    }
+  }
+  result
+}
+
+fn parse_line(line: &str, number: usize) -> Result<Vec<Entry>, String> {
+  let trimmed = line.trim();
+  for (directive, dir) in [
+    ("@includedir", true),
+    ("#includedir", true),
+    ("@include", false),
+    ("#include", false),
+  ] {
+    if let Some(rest) = trimmed.strip_prefix(directive)
+      && rest.starts_with(char::is_whitespace)
+    {
+      let path = rest.trim().trim_matches('"');
+      if path.is_empty() {
+        return Err(format!("{} needs a path", directive));
+      }
+      return Ok(vec![Entry::Include {
+        path: path.to_string(),
+        dir,
+      }]);
+    }
+  }
+
+  let line = strip_comment(trimmed);
+  let line = line.trim();
+  if line.is_empty() {
+    return Ok(Vec::new());
+  }
+
+  let keyword = line.split(|c: char| c.is_whitespace()).next().unwrap_or("");
+  if keyword.starts_with("Defaults") {
+    let rest = line["Defaults".len()..].trim();
+    if rest.is_empty() {
+      return Err(String::from("Defaults needs at least one setting"));
+    }
+    return Ok(vec![Entry::Defaults]);
+  }
+
+  let alias_kind = match keyword {
+    "User_Alias" => Some(AliasKind::User),
+    "Runas_Alias" => Some(AliasKind::Runas),
+    "Host_Alias" => Some(AliasKind::Host),
+    "Cmnd_Alias" | "Cmd_Alias" => Some(AliasKind::Cmnd),
+    _ => None,
+  };
+  if let Some(kind) = alias_kind {
+    return split_unescaped(&line[keyword.len()..], ':')
+      .into_iter()
+      .map(|definition| parse_alias(kind, definition))
+      .collect();
+  }
+
+  parse_rule(line, number).map(|rule| vec![Entry::Rule(rule)])
+}
+
+/// Drops a trailing comment. `#` followed by a digit is a numeric ID and an
+/// escaped `\#` is literal.
+fn strip_comment(line: &str) -> &str {
+  let bytes = line.as_bytes();
+  let mut quoted = false;
+  for (i, &b) in bytes.iter().enumerate() {
+    match b {
+      b'"' => quoted = !quoted,
+      b'#' if !quoted && (i == 0 || bytes[i - 1] != b'\\') => {
+        let digit = bytes.get(i + 1).is_some_and(u8::is_ascii_digit);
+        if !digit {
+          return &line[..i];
+        }
+      }
+      _ => {}
+    }
+  }
+  line
+}
+
+/// Parses one `NAME = member, ...` alias definition.
+fn parse_alias(kind: AliasKind, definition: &str) -> Result<Entry, String> {
+  let (name, members) = split_once_unescaped(definition, '=')
+    .ok_or_else(|| String::from("alias definition needs '='"))?;
+  let name = name.trim();
+  if !is_alias_name(name) {
+    return Err(format!("invalid alias name '{}'", name));
+  }
+  Ok(Entry::Alias {
+    kind,
+    name: name.to_string(),
+    members: parse_list(members)?,
+  })
+}
+
+pub(crate) fn is_alias_name(name: &str) -> bool {
+  let mut chars = name.chars();
+  chars.next().is_some_and(|c| c.is_ascii_uppercase())
+    && chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// Whether `host` is a host list item: `ALL`, a Host_Alias, a host name or
+/// wildcard pattern, an IPv4 or IPv6 address or network, or a `+netgroup`,
+/// optionally negated with `!`.
+pub(crate) fn is_valid_host(host: &str) -> bool {
+  let host = host.strip_prefix('!').unwrap_or(host);
+  let host = host.strip_prefix('+').unwrap_or(host);
+  !host.is_empty()
+    && host.chars().all(|c| {
+      c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | '*' | '?' | '[' | ']' | ':' | '/')
+    })
+}
+
+/// Parses `users hosts = [(runas)] [TAG:] command, ... [: hosts = ...]`.
+fn parse_rule(line: &str, number: usize) -> Result<Rule, String> {
+  let (left, right) =
+    split_once_unescaped(line, '=').ok_or_else(|| format!("syntax error near '{}'", line))?;
+
+  let (users, mut hosts) = split_user_host(left.trim())?;
+  let mut privileges = Vec::new();
+  let mut rest = right;
+  loop {
+    let (commands, next) = parse_commands(rest)?;
+    privileges.push(Privilege { hosts, commands });
+    let Some(next) = next else {
+      break;
+    };
+    let (host_part, remaining) = split_once_unescaped(next, '=')
+      .ok_or_else(|| String::from("expected 'hosts =' after ':'"))?;
+    hosts = parse_list(host_part)?;
+    rest = remaining;
+  }
+
+  Ok(Rule {
+    line: number,
+    text: line.to_string(),
+    users,
+    privileges,
+  })
+}
+
+/// Splits `alice, bob ALL` into the user list and the host list.
+fn split_user_host(left: &str) -> Result<(Vec<String>, Vec<String>), String> {
+  // Lists are comma separated; the whitespace not next to a comma separates
+  // the user list from the host list.
+  let normalized = left.split(',').map(str::trim).collect::<Vec<_>>().join(",");
+  let (users, hosts) = normalized.split_once(char::is_whitespace).ok_or_else(|| {
+    format!(
+      "expected a user list and a host list before '=' in '{}'",
+      left
+    )
+  })?;
+  Ok((parse_list(users)?, parse_list(hosts)?))
+}
+
+/// Parses a command list up to a top-level `:` that starts another
+/// `hosts = commands` group, which is returned as the remainder. As in sudo,
+/// a runas list and tags carry over to the following commands.
+fn parse_commands(text: &str) -> Result<(Vec<CommandSpec>, Option<&str>), String> {
+  let mut commands = Vec::new();
+  let mut rest = text.trim_start();
+  let mut runas = None;
+  let mut tags: Vec<String> = Vec::new();
+
+  loop {
+    if let Some(after) = rest.strip_prefix('(') {
+      let end = after
+        .find(')')
+        .ok_or_else(|| String::from("unterminated runas list"))?;
+      runas = Some(parse_runas(&after[..end])?);
+      rest = after[end + 1..].trim_start();
+    }
+
+    // Tags such as NOPASSWD: and options such as CWD=/srv
+    loop {
+      let word: String = rest
+        .chars()
+        .take_while(|c| c.is_ascii_uppercase() || *c == '_')
+        .collect();
+      if TAGS.contains(&word.as_str()) && rest[word.len()..].starts_with(':') {
+        // A tag overrides its opposite, e.g. PASSWD after NOPASSWD.
+        let opposite = match word.strip_prefix("NO") {
+          Some(positive) => positive.to_string(),
+          None => format!("NO{}", word),
+        };
+        tags.retain(|tag| *tag != opposite && *tag != word);
+        rest = rest[word.len() + 1..].trim_start();
+        tags.push(word);
+      } else if OPTIONS.contains(&word.as_str()) && rest[word.len()..].starts_with('=') {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        rest = rest[end..].trim_start();
+      } else {
+        break;
+      }
+    }
+
+    let mut negated = false;
+    while let Some(after) = rest.strip_prefix('!') {
+      negated = !negated;
+      rest = after.trim_start();
+    }
+
+    // The command runs to the next unescaped ',' or ':', except for the
+    // colon of a digest prefix.
+    let digest_len = DIGESTS
+      .iter()
+      .find(|d| rest.starts_with(&format!("{}:", d)))
+      .map_or(0, |d| d.len() + 1);
+    let end = find_unescaped(&rest[digest_len..], &[',', ':']).map(|i| i + digest_len);
+    let command = rest[..end.unwrap_or(rest.len())].trim();
+    if command.is_empty() {
+      return Err(String::from("missing command"));
+    }
+    if !(command == "ALL"
+      || command.starts_with('/')
+      || command.starts_with("sudoedit")
+      || command.starts_with("list")
+      || digest_len > 0
+      || is_alias_name(command))
+    {
+      return Err(format!(
+        "'{}' is not a fully qualified command path, ALL or a command alias",
+        command
+      ));
+    }
+    commands.push(CommandSpec {
+      negated,
+      command: command.to_string(),
+      runas: runas.clone(),
+      tags: tags.clone(),
+    });
+
+    match end {
+      None => return Ok((commands, None)),
+      Some(i) if rest.as_bytes()[i] == b',' => rest = rest[i + 1..].trim_start(),
+      Some(i) => return Ok((commands, Some(&rest[i + 1..]))),
+    }
+  }
+}
+
+/// Parses the inside of `(users : groups)`; either side may be empty.
+fn parse_runas(text: &str) -> Result<Runas, String> {
+  let (users, groups) = split_once_unescaped(text, ':').unwrap_or((text, ""));
+  let list = |part: &str| {
+    if part.trim().is_empty() {
+      Ok(Vec::new())
+    } else {
+      parse_list(part)
+    }
+  };
+  Ok(Runas {
+    users: list(users)?,
+    groups: list(groups)?,
+  })
+}
+
+/// Splits a comma separated list, honouring backslash escapes.
+fn parse_list(text: &str) -> Result<Vec<String>, String> {
+  let items: Vec<String> = split_unescaped(text, ',')
+    .into_iter()
+    .map(|item| item.trim().to_string())
+    .collect();
+  if items.iter().any(String::is_empty) {
+    return Err(format!("empty item in list '{}'", text.trim()));
+  }
+  Ok(items)
+}
+
+fn split_unescaped(text: &str, separator: char) -> Vec<&str> {
+  let mut parts = Vec::new();
+  let mut rest = text;
+  while let Some(i) = find_unescaped(rest, &[separator]) {
+    parts.push(&rest[..i]);
+    rest = &rest[i + 1..];
+  }
+  parts.push(rest);
+  parts
+}
+
+fn split_once_unescaped(text: &str, separator: char) -> Option<(&str, &str)> {
+  find_unescaped(text, &[separator]).map(|i| (&text[..i], &text[i + 1..]))
+}
+
+fn find_unescaped(text: &str, separators: &[char]) -> Option<usize> {
+  let mut escaped = false;
+  for (i, c) in text.char_indices() {
+    if escaped {
+      escaped = false;
+    } else if c == '\\' {
+      escaped = true;
+    } else if separators.contains(&c) {
+      return Some(i);
+    }
+  }
+  None
+}
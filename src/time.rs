@@ -0,0 +1,178 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MINUTE: u64 = 60;
+const HOUR: u64 = 60 * MINUTE;
+const DAY: u64 = 24 * HOUR;
+
+/// Current time in seconds since the epoch.
+pub(crate) fn now() -> u64 {
+  SystemTime::now()
+    .duration_since(UNIX_EPOCH)
+    .map(|d| d.as_secs())
+    .unwrap_or(0)
+}
+
+/// Parses a duration such as `90m`, `4h`, `2d` or `1w` into seconds.
+/// A bare number is taken as days.
+pub(crate) fn parse_duration(value: &str) -> Result<u64, String> {
+  let value = value.trim();
+  let split = value
+    .find(|c: char| !c.is_ascii_digit())
+    .unwrap_or(value.len());
+  let (amount, unit) = value.split_at(split);
+
+  let amount: u64 = amount
+    .parse()
+    .map_err(|_| format!("Invalid duration '{}'", value))?;
+  let unit = match unit {
+    "s" => 1,
+    "m" => MINUTE,
+    "h" => HOUR,
+    "" | "d" => DAY,
+    "w" => 7 * DAY,
+    _ => {
+      return Err(format!(
+        "Invalid duration '{}': expected a unit of s, m, h, d or w",
+        value
+      ));
+    }
+  };
+
+  amount
+    .checked_mul(unit)
+    .ok_or_else(|| format!("Duration '{}' is too large", value))
+}
+
+/// Parses a guest expiry into a timestamp. Accepts a duration from now as
+/// understood by `parse_duration`, or a local date (`2026-11-01`) or date and
+/// time (`2026-11-01T17:00`, seconds optional).
+pub(crate) fn parse_expiry(value: &str) -> Result<u64, String> {
+  let value = value.trim();
+  if value.contains('-') {
+    return parse_local_datetime(value)
+      .ok_or_else(|| format!("Invalid date '{}': expected YYYY-MM-DD[THH:MM[:SS]]", value));
+  }
+  parse_duration(value).map(|secs| now().saturating_add(secs))
+}
+
+/// Splits a duration into its largest whole unit, e.g. 5400 -> (90, "minute").
+pub(crate) fn duration_unit(secs: u64) -> (u64, &'static str) {
+  [(DAY, "day"), (HOUR, "hour"), (MINUTE, "minute")]
+    .into_iter()
+    .find(|(unit, _)| secs >= *unit && secs.is_multiple_of(*unit))
+    .map_or((secs, "second"), |(unit, name)| (secs / unit, name))
+}
+
+fn parse_local_datetime(value: &str) -> Option<u64> {
+  let (date, time) = match value.split_once(['T', ' ']) {
+    Some((date, time)) => (date, Some(time)),
+    None => (value, None),
+  };
+
+  let date: Vec<i32> = date
+    .split('-')
+    .map(|part| part.parse().ok())
+    .collect::<Option<_>>()?;
+  let time: Vec<i32> = match time {
+    Some(time) => time
+      .split(':')
+      .map(|part| part.parse().ok())
+      .collect::<Option<_>>()?,
+    None => vec![0, 0],
+  };
+  let ([year, month, day], [hour, minute, second @ ..]) = (date.as_slice(), time.as_slice()) else {
+    return None;
+  };
+  let second = match second {
+    [] => 0,
+    [second] => *second,
+    _ => return None,
+  };
+
+  // SAFETY: tm is a plain C struct; all-zero is a valid initial value.
+  let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+  tm.tm_year = year - 1900;
+  tm.tm_mon = month - 1;
+  tm.tm_mday = *day;
+  tm.tm_hour = *hour;
+  tm.tm_min = *minute;
+  tm.tm_sec = second;
+  tm.tm_isdst = -1;
+  let requested = (
+    tm.tm_year, tm.tm_mon, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec,
+  );
+
+  // SAFETY: tm is a valid, initialized struct.
+  let timestamp = unsafe { libc::mktime(&mut tm) };
+  // mktime normalizes out-of-range fields, so 2026-02-30 comes back as March.
+  let normalized = (
+    tm.tm_year, tm.tm_mon, tm.tm_mday, tm.tm_hour, tm.tm_min, tm.tm_sec,
+  );
+  if timestamp < 0 || requested != normalized {
+    return None;
+  }
+  Some(timestamp as u64)
+}
+
+fn local_time(timestamp: u64) -> Option<libc::tm> {
+  let time = timestamp as libc::time_t;
+  // SAFETY: tm is a plain C struct; all-zero is a valid initial value.
+  let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+  // SAFETY: both pointers are valid for the duration of the call.
+  if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+    return None;
+  }
+  Some(tm)
+}
+
+fn utc_time(timestamp: u64) -> Option<libc::tm> {
+  let time = timestamp as libc::time_t;
+  // SAFETY: tm is a plain C struct; all-zero is a valid initial value.
+  let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+  // SAFETY: both pointers are valid for the duration of the call.
+  if unsafe { libc::gmtime_r(&time, &mut tm) }.is_null() {
+    return None;
+  }
+  Some(tm)
+}
+
+/// Formats a timestamp in local time as `YYYY-MM-DD HH:MM ZONE`.
+pub(crate) fn format_timestamp(timestamp: u64) -> String {
+  let Some(tm) = local_time(timestamp) else {
+    return timestamp.to_string();
+  };
+
+  let zone = if tm.tm_zone.is_null() {
+    String::new()
+  } else {
+    // SAFETY: localtime_r points tm_zone at a static NUL-terminated string.
+    let zone = unsafe { std::ffi::CStr::from_ptr(tm.tm_zone) };
+    format!(" {}", zone.to_string_lossy())
+  };
+  format!(
+    "{:04}-{:02}-{:02} {:02}:{:02}{}",
+    tm.tm_year + 1900,
+    tm.tm_mon + 1,
+    tm.tm_mday,
+    tm.tm_hour,
+    tm.tm_min,
+    zone
+  )
+}
+
+/// Formats a timestamp in UTC as `YYYYMMDD-HHMMSS`, which sorts in time
+/// order whatever the time zone and daylight saving time do.
+pub(crate) fn format_stamp(timestamp: u64) -> String {
+  let Some(tm) = utc_time(timestamp) else {
+    return timestamp.to_string();
+  };
+  format!(
+    "{:04}{:02}{:02}-{:02}{:02}{:02}",
+    tm.tm_year + 1900,
+    tm.tm_mon + 1,
+    tm.tm_mday,
+    tm.tm_hour,
+    tm.tm_min,
+    tm.tm_sec
+  )
+}
@@ -1,12 +1,13 @@
+use crate::config::Config;
+use crate::db::{self, Database, PasswdEntry};
+use crate::import::{self, Row};
 use serde::Serialize;
 use std::fs;
+use std::path::Path;
 
 #[derive(Serialize)]
 pub(crate) struct User {
   pub(crate) username: String,
-  #[allow(dead_code)]
-  #[serde(skip)]
-  password: String,
   pub(crate) uid: u32,
   pub(crate) gid: u32,
   pub(crate) gecos: String,
@@ -15,29 +16,20 @@ pub(crate) struct User {
 }
 
 pub(crate) fn list_users() -> Vec<User> {
-  // Real implementation for production use
-  match fs::read_to_string("/etc/passwd") {
-    Ok(passwd_content) => {
-      let mut users = Vec::new();
-      for line in passwd_content.lines() {
-        let fields: Vec<&str> = line.split(':').collect();
-        if fields.len() >= 7 {
-          let user = User {
-            username: fields[0].to_string(),
-            password: fields[1].to_string(),
-            uid: fields[2].parse().unwrap_or(0),
-            gid: fields[3].parse().unwrap_or(0),
-            gecos: fields[4].to_string(),
-            home_dir: fields[5].to_string(),
-            shell: fields[6].to_string(),
-          };
-          users.push(user);
-        }
-      }
-      users
-    }
+  match db::read_users() {
+    Ok(entries) => entries
+      .into_iter()
+      .map(|entry| User {
+        username: entry.name,
+        uid: entry.uid,
+        gid: entry.gid,
+        gecos: entry.gecos,
+        home_dir: entry.home,
+        shell: entry.shell,
+      })
+      .collect(),
     Err(e) => {
-      eprintln!("Failed to read /etc/passwd: {}", e);
+      eprintln!("{}", e);
       Vec::new()
     }
   }
@@ -101,64 +93,98 @@ pub(crate) fn user_info(username: &str) -> bool {
   false
 }
 
-pub(crate) fn add_user(username: &str, home_dir: &str) -> bool {
+pub(crate) fn add_user(
+  username: &str,
+  home_dir: Option<&str>,
+  shell: Option<&str>,
+  uid: Option<u32>,
+  gid: Option<u32>,
+  gecos: Option<&str>,
+) -> bool {
   // Check if running with sudo/root privileges
   if !has_escalated_privileges() {
     eprintln!("Error: This operation requires root privileges. Please run with sudo.");
     return false;
   }
 
-  let passwd_path = "/etc/passwd";
-  let shadow_path = "/etc/shadow";
-
-  // Try to read the files, but handle errors gracefully
-  let passwd_content = match fs::read_to_string(passwd_path) {
-    Ok(content) => content,
+  let config = match Config::load() {
+    Ok(config) => config,
     Err(e) => {
-      eprintln!("Failed to read {}: {}", passwd_path, e);
+      eprintln!("Error: {}", e);
       return false;
     }
   };
-
-  // Check if user already exists
-  if passwd_content
-    .lines()
-    .any(|line| line.starts_with(&format!("{0}:", username)))
-  {
-    eprintln!("Error: User {} already exists", username);
-    return false;
-  }
-
-  let shadow_content = match fs::read_to_string(shadow_path) {
-    Ok(content) => content,
+  let row = Row {
+    username: username.to_string(),
+    home: home_dir.map(String::from),
+    shell: shell.map(String::from),
+    uid,
+    gid: gid.map(|gid| gid.to_string()),
+    gecos: gecos.map(String::from),
+    ..Row::default()
+  };
+  // The account is written in one locked transaction, so a failure leaves
+  // no half-created user behind
+  let added = Database::open().and_then(|mut db| {
+    let entry = import::add_row(&mut db, &row, &config)?;
+    db.commit()?;
+    Ok(entry)
+  });
+  let entry = match added {
+    Ok(entry) => entry,
     Err(e) => {
-      eprintln!("Failed to read {}: {}", shadow_path, e);
+      eprintln!("Error: {}", e);
       return false;
     }
   };
 
-  let uid = passwd_content.lines().count() + 1000; // Simple UID generation
-  let new_passwd_entry = format!(
-    "{0}:x:{1}:{1}::{2}/{0}:/bin/bash\n",
-    username, uid, home_dir
-  );
-  let new_shadow_entry = format!("{0}:*:18922:0:99999:7:::\n", username); // Placeholder password entry
-
-  let updated_passwd = passwd_content + &new_passwd_entry;
-  let updated_shadow = shadow_content + &new_shadow_entry;
-
-  // Try to write the files, but handle errors gracefully
-  if let Err(e) = fs::write(passwd_path, updated_passwd) {
-    eprintln!("Failed to write to {}: {}", passwd_path, e);
+  let home = crate::root::path(&entry.home);
+  let skel = crate::root::path(&config.default.skel);
+  if !home.exists()
+    && let Err(e) = create_home(&home, entry.uid, entry.gid, &skel)
+  {
+    eprintln!("Error: {}", e);
     return false;
   }
+  true
+}
 
-  if let Err(e) = fs::write(shadow_path, updated_shadow) {
-    eprintln!("Failed to write to {}: {}", shadow_path, e);
-    return false;
-  }
+/// Creates a home directory readable only by its owner and fills it from
+/// the skeleton directory.
+pub(crate) fn create_home(home: &Path, uid: u32, gid: u32, skel: &Path) -> Result<(), String> {
+  use std::os::unix::fs::DirBuilderExt;
+
+  fs::DirBuilder::new()
+    .mode(0o700)
+    .create(home)
+    .and_then(|()| std::os::unix::fs::chown(home, Some(uid), Some(gid)))
+    .and_then(|()| copy_skel(skel, home, uid, gid))
+    .map_err(|e| format!("Failed to create {}: {}", home.display(), e))
+}
 
-  true
+/// Copies the skeleton directory into a new home, owned by the account.
+pub(crate) fn copy_skel(from: &Path, to: &Path, uid: u32, gid: u32) -> std::io::Result<()> {
+  let entries = match fs::read_dir(from) {
+    Ok(entries) => entries,
+    Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+    Err(e) => return Err(e),
+  };
+
+  for entry in entries {
+    let entry = entry?;
+    let target = to.join(entry.file_name());
+    let file_type = entry.file_type()?;
+    if file_type.is_dir() {
+      fs::create_dir(&target)?;
+      copy_skel(&entry.path(), &target, uid, gid)?;
+    } else if file_type.is_symlink() {
+      std::os::unix::fs::symlink(fs::read_link(entry.path())?, &target)?;
+    } else {
+      fs::copy(entry.path(), &target)?;
+    }
+    std::os::unix::fs::lchown(&target, Some(uid), Some(gid))?;
+  }
+  Ok(())
 }
 
 pub(crate) fn delete_user(username: &str) -> bool {
@@ -168,53 +194,33 @@ pub(crate) fn delete_user(username: &str) -> bool {
     return false;
   }
 
-  let passwd_path = "/etc/passwd";
-  let shadow_path = "/etc/shadow";
-
-  // Try to read the files, but handle errors gracefully
-  let passwd_content = match fs::read_to_string(passwd_path) {
-    Ok(content) => content,
-    Err(e) => {
-      eprintln!("Failed to read {}: {}", passwd_path, e);
-      return false;
-    }
-  };
-
-  let shadow_content = match fs::read_to_string(shadow_path) {
-    Ok(content) => content,
-    Err(e) => {
-      eprintln!("Failed to read {}: {}", shadow_path, e);
-      return false;
-    }
-  };
-
-  let new_passwd_content: String = passwd_content
-    .lines()
-    .filter(|line| !line.starts_with(username))
-    .map(|line| format!("{0}\n", line))
-    .collect();
-
-  let new_shadow_content: String = shadow_content
-    .lines()
-    .filter(|line| !line.starts_with(username))
-    .map(|line| format!("{0}\n", line))
-    .collect();
-
-  // Try to write the files, but handle errors gracefully
-  if let Err(e) = fs::write(passwd_path, new_passwd_content) {
-    eprintln!("Failed to write to {}: {}", passwd_path, e);
+  let removed = Database::open().and_then(|mut db| {
+    remove_account(&mut db, username);
+    db.commit()
+  });
+  if let Err(e) = removed {
+    eprintln!("Error: {}", e);
     return false;
   }
+  true
+}
 
-  if let Err(e) = fs::write(shadow_path, new_shadow_content) {
-    eprintln!("Failed to write to {}: {}", shadow_path, e);
-    return false;
+/// Removes a user, and its private group too if nothing else uses it, as
+/// userdel does. Returns the removed entry.
+pub(crate) fn remove_account(db: &mut Database, username: &str) -> Option<PasswdEntry> {
+  let entry = db.user(username)?;
+  db.remove_user(username);
+  if let Some(group) = db.group(username)
+    && group.gid == entry.gid
+    && group.members.is_empty()
+    && !db.users().iter().any(|u| u.gid == group.gid)
+  {
+    db.remove_group(username);
   }
-
-  true
+  Some(entry)
 }
 
-fn has_escalated_privileges() -> bool {
+pub(crate) fn has_escalated_privileges() -> bool {
   // Check if running with sudo/root privileges
   #[cfg(unix)]
   {
@@ -233,43 +239,10 @@ fn has_escalated_privileges() -> bool {
 pub(crate) fn shell(username: Option<&str>, command: Option<&str>) -> bool {
   #[cfg(unix)]
   {
-    use std::os::unix::process::CommandExt;
-    use std::process::Command;
-
-    // Verify user exists if username is provided
-    if let Some(username) = username {
-      if !user_exists(username) {
-        eprintln!("Error: User {} not found", username);
-        return false;
-      }
-    }
-
-    // Get user's shell from passwd if username provided, otherwise use /bin/sh
-    let shell = if let Some(username) = username {
-      let users = list_users();
-      users
-        .iter()
-        .find(|u| u.username == username)
-        .map(|u| u.shell.clone())
-        .unwrap_or_else(|| String::from("/bin/sh"))
-    } else {
-      String::from("/bin/sh")
+    let Some(mut cmd) = shell_command(username, command) else {
+      return false;
     };
 
-    let mut cmd = Command::new(&shell);
-
-    // If command is provided, execute it with -c flag
-    if let Some(command_str) = command {
-      cmd.arg("-c").arg(command_str);
-    }
-
-    // Set user context if username is provided
-    if let Some(username) = username {
-      if let Some(user) = list_users().into_iter().find(|u| u.username == username) {
-        cmd.uid(user.uid).gid(user.gid);
-      }
-    }
-
     match cmd.status() {
       Ok(status) => status.success(),
       Err(e) => {
@@ -286,6 +259,66 @@ pub(crate) fn shell(username: Option<&str>, command: Option<&str>) -> bool {
   }
 }
 
-fn user_exists(username: &str) -> bool {
-  list_users().into_iter().any(|u| u.username == username)
+/// Builds the shell process for `shell`, switched to the given user's
+/// credentials, environment and home directory.
+#[cfg(unix)]
+pub(crate) fn shell_command(
+  username: Option<&str>,
+  command: Option<&str>,
+) -> Option<std::process::Command> {
+  shell_command_with(username, command, true)
+}
+
+/// Builds the shell command for `username`, switching to the user's UID and
+/// GID only with `switch_user`. Without it the caller switches in `pre_exec`,
+/// which then still runs as root, e.g. to raise resource limits.
+pub(crate) fn shell_command_with(
+  username: Option<&str>,
+  command: Option<&str>,
+  switch_user: bool,
+) -> Option<std::process::Command> {
+  use std::os::unix::process::CommandExt;
+  use std::process::Command;
+
+  // Verify user exists if username is provided
+  let user = match username {
+    Some(username) => match list_users().into_iter().find(|u| u.username == username) {
+      Some(user) => Some(user),
+      None => {
+        eprintln!("Error: User {} not found", username);
+        return None;
+      }
+    },
+    None => None,
+  };
+
+  // Use the user's shell from passwd if username provided, otherwise /bin/sh
+  let shell = user
+    .as_ref()
+    .map(|u| u.shell.clone())
+    .unwrap_or_else(|| String::from("/bin/sh"));
+
+  let mut cmd = Command::new(&shell);
+
+  // If command is provided, execute it with -c flag
+  if let Some(command_str) = command {
+    cmd.arg("-c").arg(command_str);
+  }
+
+  // Set user context if username is provided
+  if let Some(user) = user {
+    if switch_user {
+      cmd.uid(user.uid).gid(user.gid);
+    }
+    cmd
+      .env("HOME", &user.home_dir)
+      .env("USER", &user.username)
+      .env("LOGNAME", &user.username)
+      .env("SHELL", &shell);
+    if fs::metadata(&user.home_dir).is_ok_and(|m| m.is_dir()) {
+      cmd.current_dir(&user.home_dir);
+    }
+  }
+
+  Some(cmd)
 }
@@ -0,0 +1,101 @@
+use std::ffi::CString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+fn c_path(path: &Path) -> Result<CString, String> {
+  CString::new(path.as_os_str().as_bytes()).map_err(|_| format!("Invalid path {}", path.display()))
+}
+
+/// Reads the extended attribute `name` of `path`, following symlinks.
+/// Missing attributes and filesystems without xattr support yield `None`.
+pub(crate) fn get(path: &Path, name: &str) -> Result<Option<Vec<u8>>, String> {
+  let path_c = c_path(path)?;
+  let name_c = CString::new(name).map_err(|_| format!("Invalid attribute name {}", name))?;
+
+  loop {
+    // SAFETY: both strings are NUL-terminated; a null buffer asks for the size.
+    let size = unsafe { libc::getxattr(path_c.as_ptr(), name_c.as_ptr(), std::ptr::null_mut(), 0) };
+    if size < 0 {
+      return absent_or_error(path, name);
+    }
+
+    let mut buffer = vec![0u8; size as usize];
+    // SAFETY: buffer has room for `size` bytes.
+    let read = unsafe {
+      libc::getxattr(
+        path_c.as_ptr(),
+        name_c.as_ptr(),
+        buffer.as_mut_ptr().cast(),
+        buffer.len(),
+      )
+    };
+    if read >= 0 {
+      buffer.truncate(read as usize);
+      return Ok(Some(buffer));
+    }
+    // The attribute grew between the two calls; ask again.
+    if std::io::Error::last_os_error().raw_os_error() != Some(libc::ERANGE) {
+      return absent_or_error(path, name);
+    }
+  }
+}
+
+fn absent_or_error<T>(path: &Path, name: &str) -> Result<Option<T>, String> {
+  let error = std::io::Error::last_os_error();
+  match error.raw_os_error() {
+    Some(libc::ENODATA) | Some(libc::EOPNOTSUPP) => Ok(None),
+    _ => Err(format!(
+      "Failed to read {} of {}: {}",
+      name,
+      path.display(),
+      error
+    )),
+  }
+}
+
+/// Sets the extended attribute `name` of `path`, following symlinks.
+pub(crate) fn set(path: &Path, name: &str, value: &[u8]) -> Result<(), String> {
+  let path_c = c_path(path)?;
+  let name_c = CString::new(name).map_err(|_| format!("Invalid attribute name {}", name))?;
+
+  // SAFETY: both strings are NUL-terminated and value outlives the call.
+  let rc = unsafe {
+    libc::setxattr(
+      path_c.as_ptr(),
+      name_c.as_ptr(),
+      value.as_ptr().cast(),
+      value.len(),
+      0,
+    )
+  };
+  if rc != 0 {
+    return Err(format!(
+      "Failed to set {} on {}: {}",
+      name,
+      path.display(),
+      std::io::Error::last_os_error()
+    ));
+  }
+  Ok(())
+}
+
+/// Removes the extended attribute `name` of `path`; a missing attribute is
+/// not an error.
+pub(crate) fn remove(path: &Path, name: &str) -> Result<(), String> {
+  let path_c = c_path(path)?;
+  let name_c = CString::new(name).map_err(|_| format!("Invalid attribute name {}", name))?;
+
+  // SAFETY: both strings are NUL-terminated.
+  if unsafe { libc::removexattr(path_c.as_ptr(), name_c.as_ptr()) } != 0 {
+    let error = std::io::Error::last_os_error();
+    if error.raw_os_error() != Some(libc::ENODATA) {
+      return Err(format!(
+        "Failed to remove {} from {}: {}",
+        name,
+        path.display(),
+        error
+      ));
+    }
+  }
+  Ok(())
+}
@@ -0,0 +1,74 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+mod test_utils;
+use test_utils::{sudo_run_userkit_command, testuser_root};
+
+#[test]
+fn test_backup_diff_nonexistent() {
+  let mut cmd = sudo_run_userkit_command(vec!["backup", "diff", "19700101-000000"]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Backup 19700101-000000 does not exist",
+  ));
+}
+
+#[test]
+fn test_restore_rejects_path() {
+  let mut cmd = sudo_run_userkit_command(vec!["restore", "../etc"]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Backup ../etc does not exist"));
+}
+
+/// Runs the batch `script` under `root`.
+fn run_batch(root: &str, script: &str) {
+  let path = format!("{}/script", root);
+  std::fs::write(&path, script).unwrap();
+  let mut cmd = sudo_run_userkit_command(vec!["batch", &path, "--root", root]);
+  cmd.assert().success();
+}
+
+#[test]
+fn test_restore_undoes_changes() {
+  let root = testuser_root("backup_restore");
+  let passwd = std::fs::read(format!("{}/etc/passwd", root)).unwrap();
+  let group = std::fs::read(format!("{}/etc/group", root)).unwrap();
+
+  // The first write backs up the files as they are now.
+  run_batch(&root, "user modify testuser --shell /bin/bash\n");
+  let list = sudo_run_userkit_command(vec!["backup", "list", "--format", "csv", "--root", &root])
+    .output()
+    .unwrap();
+  let stdout = String::from_utf8(list.stdout).unwrap();
+  let rows: Vec<&str> = stdout.lines().skip(1).collect();
+  assert_eq!(rows.len(), 1, "expected one backup, got {:?}", rows);
+  let id = rows[0].split(',').next().unwrap().to_string();
+
+  run_batch(
+    &root,
+    "group new devs\nuser modify testuser --shell /bin/zsh\n",
+  );
+  assert_ne!(
+    std::fs::read(format!("{}/etc/passwd", root)).unwrap(),
+    passwd
+  );
+
+  // A drop-in userkit did not write is left alone by the restore.
+  let dropin = format!("{}/etc/sudoers.d/other", root);
+  std::fs::create_dir_all(format!("{}/etc/sudoers.d", root)).unwrap();
+  std::fs::write(&dropin, "testuser ALL=(ALL) ALL\n").unwrap();
+
+  let mut cmd = sudo_run_userkit_command(vec!["restore", &id, "--root", &root]);
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(format!("Restored backup {}", id)));
+  assert_eq!(
+    std::fs::read(format!("{}/etc/passwd", root)).unwrap(),
+    passwd
+  );
+  assert_eq!(std::fs::read(format!("{}/etc/group", root)).unwrap(), group);
+  assert!(std::path::Path::new(&dropin).exists());
+}
@@ -0,0 +1,92 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+mod test_utils;
+use test_utils::{sudo_run_userkit_command, testuser_root};
+
+#[test]
+fn test_batch_invalid_json() {
+  let dir = std::env::temp_dir().join("userkit_batch_test");
+  std::fs::create_dir_all(&dir).unwrap();
+  let path = dir.join("invalid.json");
+  std::fs::write(&path, "[\"user add alice\",").unwrap();
+  let mut cmd = sudo_run_userkit_command(vec!["batch", path.to_str().unwrap()]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Invalid JSON batch"));
+}
+
+#[test]
+fn test_batch_unsupported_command() {
+  let dir = std::env::temp_dir().join("userkit_batch_test");
+  std::fs::create_dir_all(&dir).unwrap();
+  let path = dir.join("unsupported.txt");
+  std::fs::write(&path, "role list\n").unwrap();
+  let mut cmd = sudo_run_userkit_command(vec!["batch", "--dry-run", path.to_str().unwrap()]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Step 1 (line 1: role list) failed",
+  ));
+}
+
+#[test]
+fn test_batch_commits_every_step() {
+  let root = testuser_root("batch_commit");
+  // An existing home is left as it is, so the batch creates none.
+  std::fs::create_dir_all(format!("{}/home/alice", root)).unwrap();
+  let script = format!("{}/script", root);
+  std::fs::write(
+    &script,
+    "group new devs --gid 1600\n\
+     user add alice --uid 1500 --shell /bin/sh\n\
+     group add-user devs alice\n\
+     user modify testuser --shell /bin/bash\n",
+  )
+  .unwrap();
+  let mut cmd = sudo_run_userkit_command(vec!["batch", &script, "--root", &root]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("Batch complete: 4 steps applied"));
+  assert_eq!(
+    std::fs::read_to_string(format!("{}/etc/passwd", root)).unwrap(),
+    "root:x:0:0:root:/root:/bin/sh\n\
+     testuser:x:1000:1000::/home/testuser:/bin/bash\n\
+     alice:x:1500:1500::/home/alice:/bin/sh\n"
+  );
+  assert_eq!(
+    std::fs::read_to_string(format!("{}/etc/group", root)).unwrap(),
+    "root:x:0:\ntestuser:x:1000:\ndevs:x:1600:alice\nalice:x:1500:\n"
+  );
+}
+
+#[test]
+fn test_batch_failing_step_changes_nothing() {
+  let root = testuser_root("batch_rollback");
+  let passwd = std::fs::read(format!("{}/etc/passwd", root)).unwrap();
+  let group = std::fs::read(format!("{}/etc/group", root)).unwrap();
+  let script = format!("{}/script", root);
+  std::fs::write(
+    &script,
+    "group new devs\n\
+     user modify testuser --shell /bin/bash\n\
+     user add testuser\n",
+  )
+  .unwrap();
+  let mut cmd = sudo_run_userkit_command(vec!["batch", &script, "--root", &root]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains(
+      "Step 3 (line 3: user add testuser) failed",
+    ))
+    .stderr(predicate::str::contains("No changes were made"));
+  assert_eq!(
+    std::fs::read(format!("{}/etc/passwd", root)).unwrap(),
+    passwd
+  );
+  assert_eq!(std::fs::read(format!("{}/etc/group", root)).unwrap(), group);
+}
@@ -1,11 +1,19 @@
 use assert_cmd::prelude::*;
 use predicates::prelude::*;
 mod test_utils;
-use test_utils::run_userkit_command;
+use test_utils::{run_userkit_command, temp_root};
 
 #[test]
 fn test_config_set() {
-  let mut cmd = run_userkit_command(vec!["config", "set", "default.shell", "/bin/bash"]);
+  let root = temp_root("config_set");
+  let mut cmd = run_userkit_command(vec![
+    "config",
+    "set",
+    "default.shell",
+    "/bin/bash",
+    "--root",
+    &root,
+  ]);
 
   cmd.assert().success().stdout(predicate::str::contains(
     "Configuration option default.shell set to /bin/bash",
@@ -34,7 +42,8 @@ fn test_config_list() {
 
 #[test]
 fn test_config_reset() {
-  let mut cmd = run_userkit_command(vec!["config", "reset"]);
+  let root = temp_root("config_reset");
+  let mut cmd = run_userkit_command(vec!["config", "reset", "--root", &root]);
 
   cmd
     .assert()
@@ -51,3 +60,64 @@ fn test_config_get_nonexistent() {
     .failure()
     .stderr(predicate::str::contains("Error"));
 }
+
+#[test]
+fn test_config_set_invalid_value() {
+  let root = temp_root("config_set_invalid_value");
+  let mut cmd = run_userkit_command(vec![
+    "config",
+    "set",
+    "default.home_base",
+    "home",
+    "--root",
+    &root,
+  ]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("not an absolute path"));
+}
+
+#[test]
+fn test_config_env_override() {
+  let mut cmd = run_userkit_command(vec!["config", "list", "--show-origin"]);
+  cmd.env("USERKIT_DEFAULT_OUTPUT_FORMAT", "json");
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("USERKIT_DEFAULT_OUTPUT_FORMAT"));
+}
+
+#[test]
+fn test_config_env_restricted_key() {
+  let mut cmd = run_userkit_command(vec!["config", "get", "sudo.method"]);
+  cmd.env("USERKIT_SUDO_METHOD", "group");
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("can only be set in"));
+}
+
+#[test]
+fn test_config_env_restricted_uid_range() {
+  let mut cmd = run_userkit_command(vec!["config", "get", "default.uid_min"]);
+  cmd.env("USERKIT_DEFAULT_UID_MIN", "0");
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("can only be set in"));
+}
+
+#[test]
+fn test_config_profile_show_nonexistent() {
+  let mut cmd = run_userkit_command(vec!["config", "profile", "show", "nonexistent"]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Profile nonexistent not found"));
+}
@@ -1,45 +1,111 @@
 use assert_cmd::prelude::*;
 use predicates::prelude::*;
 mod test_utils;
-use test_utils::run_userkit_command;
+use test_utils::{run_userkit_command, sudo_run_userkit_command};
+
+/// Creates a guest account for one test, which removes it again with
+/// `remove_guest`. Each test uses its own name, so tests can run in any
+/// order and in parallel.
+fn create_guest(name: &str, args: &[&str]) {
+  let mut command = vec!["guest", "create", name];
+  command.extend_from_slice(args);
+  sudo_run_userkit_command(command).assert().success();
+}
+
+fn remove_guest(name: &str) {
+  let _ = sudo_run_userkit_command(vec!["guest", "remove", name]).output();
+}
 
 #[test]
 fn test_guest_create() {
-  let mut cmd = run_userkit_command(vec!["guest", "create"]);
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "create"]);
 
-  cmd
-    .assert()
-    .success()
-    .stdout(predicate::str::contains("Guest account created"));
+  let output = cmd.assert().success().get_output().stdout.clone();
+  let stdout = String::from_utf8(output).unwrap();
+  assert!(stdout.contains("Guest account created"));
+  if let Some(name) = stdout
+    .lines()
+    .find_map(|line| line.strip_prefix("Guest account created: "))
+  {
+    remove_guest(name);
+  }
 }
 
 #[test]
 fn test_guest_create_with_name() {
-  let mut cmd = run_userkit_command(vec!["guest", "create", "testguest"]);
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "create", "testguest"]);
 
   cmd
     .assert()
     .success()
     .stdout(predicate::str::contains("Guest account testguest created"));
+  remove_guest("testguest");
 }
 
 #[test]
 fn test_guest_create_with_expiration() {
-  let mut cmd = run_userkit_command(vec!["guest", "create", "testguest2", "--expire", "7"]);
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "create", "testguest2", "--expire", "7"]);
 
   cmd.assert().success().stdout(predicate::str::contains(
     "Guest account testguest2 created with 7 day expiration",
   ));
+  remove_guest("testguest2");
 }
 
 #[test]
-fn test_guest_remove() {
-  let mut cmd = run_userkit_command(vec!["guest", "remove", "testguest"]);
+fn test_guest_create_with_hour_expiration() {
+  let mut cmd =
+    sudo_run_userkit_command(vec!["guest", "create", "workshopguest", "--expire", "4h"]);
+
+  cmd.assert().success().stdout(predicate::str::contains(
+    "Guest account workshopguest created with 4 hour expiration",
+  ));
+
+  remove_guest("workshopguest");
+}
+
+#[test]
+fn test_guest_expire_at_date() {
+  create_guest("dateguest", &[]);
 
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "expire", "dateguest", "2099-11-01T17:00"]);
+  cmd.assert().success().stdout(predicate::str::contains(
+    "Expiration for guest account dateguest set to 2099-11-0",
+  ));
+
+  let mut cmd = run_userkit_command(vec!["guest", "info", "dateguest"]);
   cmd
     .assert()
     .success()
-    .stdout(predicate::str::contains("Guest account testguest removed"));
+    .stdout(predicate::str::contains("Expires: 2099-11-0"));
+
+  remove_guest("dateguest");
+}
+
+#[test]
+fn test_guest_create_invalid_expiration() {
+  let mut cmd = sudo_run_userkit_command(vec![
+    "guest",
+    "create",
+    "badguest",
+    "--expire",
+    "2026-02-30",
+  ]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Error"));
+}
+
+#[test]
+fn test_guest_remove() {
+  create_guest("removeguest", &[]);
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "remove", "removeguest"]);
+
+  cmd.assert().success().stdout(predicate::str::contains(
+    "Guest account removeguest removed",
+  ));
 }
 
 #[test]
@@ -54,35 +120,68 @@ fn test_guest_list() {
 
 #[test]
 fn test_guest_info() {
-  let mut cmd = run_userkit_command(vec!["guest", "info", "testguest2"]);
+  create_guest("infoguest", &["--expire", "7"]);
+  let mut cmd = run_userkit_command(vec!["guest", "info", "infoguest"]);
 
   cmd
     .assert()
     .success()
-    .stdout(predicate::str::contains("Guest account: testguest2"));
+    .stdout(predicate::str::contains("Guest account: infoguest"));
+  remove_guest("infoguest");
 }
 
 #[test]
 fn test_guest_shell() {
-  let mut cmd = run_userkit_command(vec!["guest", "shell"]);
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "shell"]);
 
   // This is a special case as it would spawn a shell
   // We're just testing that the command doesn't fail immediately
   cmd.assert().success();
 }
 
+#[test]
+fn test_guest_shell_command_removes_account() {
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "shell", "id -un"]);
+
+  let output = cmd.assert().success().get_output().stdout.clone();
+  let stdout = String::from_utf8(output).unwrap();
+  let name = stdout
+    .lines()
+    .find_map(|line| line.strip_prefix("Guest account "))
+    .and_then(|rest| rest.strip_suffix(" created"))
+    .unwrap()
+    .to_string();
+
+  assert!(stdout.contains(&format!("Guest account {} removed", name)));
+  let passwd = std::fs::read_to_string("/etc/passwd").unwrap();
+  assert!(!passwd.contains(&format!("{}:", name)));
+  assert!(!std::env::temp_dir().join(&name).exists());
+}
+
+#[test]
+fn test_guest_shell_exit_status() {
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "shell", "exit 3"]);
+
+  cmd
+    .assert()
+    .failure()
+    .stdout(predicate::str::contains("removed"));
+}
+
 #[test]
 fn test_guest_expire() {
-  let mut cmd = run_userkit_command(vec!["guest", "expire", "testguest2", "14"]);
+  create_guest("expireguest", &["--expire", "7"]);
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "expire", "expireguest", "14"]);
 
   cmd.assert().success().stdout(predicate::str::contains(
-    "Expiration for guest account testguest2 set to 14 days",
+    "Expiration for guest account expireguest set to 14 days",
   ));
+  remove_guest("expireguest");
 }
 
 #[test]
 fn test_guest_remove_nonexistent() {
-  let mut cmd = run_userkit_command(vec!["guest", "remove", "nonexistentguest"]);
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "remove", "nonexistentguest"]);
 
   cmd
     .assert()
@@ -99,3 +198,124 @@ fn test_guest_info_nonexistent() {
     .failure()
     .stderr(predicate::str::contains("Error"));
 }
+
+#[test]
+fn test_guest_reap_expired() {
+  create_guest("reapguest", &["--expire", "0"]);
+
+  // A real reap would also remove other expired guests on this system, so
+  // only the dry run is checked
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "reap", "--dry-run", "--grace", "1w"]);
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("reapguest").not());
+
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "reap", "--dry-run"]);
+  cmd.assert().success().stdout(predicate::str::contains(
+    "Would remove guest account reapguest",
+  ));
+  remove_guest("reapguest");
+}
+
+#[test]
+fn test_guest_reap_print_units() {
+  let mut cmd = run_userkit_command(vec!["guest", "reap", "--print-units", "--grace", "30m"]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("guest reap --grace 30m"))
+    .stdout(predicate::str::contains("[Timer]"));
+}
+
+#[test]
+fn test_guest_reap_invalid_grace() {
+  let mut cmd = run_userkit_command(vec!["guest", "reap", "--grace", "soon"]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Error"));
+}
+
+#[test]
+fn test_guest_shell_restricted_commands() {
+  let mut cmd = sudo_run_userkit_command(vec![
+    "guest",
+    "shell",
+    "--restricted-shell",
+    "--allow-command",
+    "echo",
+    "echo allowed; cat /etc/hostname",
+  ]);
+
+  cmd
+    .assert()
+    .failure()
+    .stdout(predicate::str::contains("allowed"))
+    .stderr(predicate::str::contains("cat: command not found"));
+}
+
+#[test]
+fn test_guest_shell_limits() {
+  let mut cmd =
+    sudo_run_userkit_command(vec!["guest", "shell", "--limit", "nofile=64", "ulimit -n"]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("64"));
+}
+
+#[test]
+fn test_guest_shell_group() {
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "shell", "--group", "users", "id -Gn"]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("users"));
+}
+
+#[test]
+fn test_guest_shell_quota() {
+  let mut cmd = sudo_run_userkit_command(vec![
+    "guest",
+    "shell",
+    "--quota",
+    "1M",
+    "head -c 2000000 /dev/zero > big",
+  ]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("No space left on device"));
+}
+
+#[test]
+fn test_guest_create_privileged_group() {
+  let mut cmd = sudo_run_userkit_command(vec!["guest", "create", "sudoguest", "--group", "sudo"]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Error"));
+}
+
+#[test]
+fn test_guest_create_invalid_limit() {
+  let mut cmd = sudo_run_userkit_command(vec![
+    "guest",
+    "create",
+    "limitguest",
+    "--limit",
+    "nproc=lots",
+  ]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Error"));
+}
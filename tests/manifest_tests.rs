@@ -0,0 +1,164 @@
+use assert_cmd::prelude::*;
+use predicates::prelude::*;
+mod test_utils;
+use test_utils::{run_userkit_command, sudo_run_userkit_command, testuser_root};
+
+#[test]
+fn test_plan_nonexistent_manifest() {
+  let mut cmd = run_userkit_command(vec!["plan", "/nonexistent/manifest.toml"]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Error: Failed to read"));
+}
+
+#[test]
+fn test_plan_empty_manifest() {
+  let mut cmd = sudo_run_userkit_command(vec!["plan", "/dev/null"]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("No changes"));
+}
+
+#[test]
+fn test_export() {
+  let mut cmd = sudo_run_userkit_command(vec!["export"]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("# Exported by userkit"));
+}
+
+#[test]
+fn test_plan_rejects_newline_in_shell() {
+  let dir = std::env::temp_dir().join("userkit_manifest_test");
+  std::fs::create_dir_all(&dir).unwrap();
+  let path = dir.join("newline.toml");
+  std::fs::write(
+    &path,
+    "[[user]]\nname = \"injected\"\nshell = \"/bin/sh\\nx::0:0::/:/bin/sh\"\n",
+  )
+  .unwrap();
+  let mut cmd = sudo_run_userkit_command(vec!["plan", path.to_str().unwrap()]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Invalid path"));
+}
+
+#[test]
+fn test_plan_yaml_unknown_field() {
+  let dir = std::env::temp_dir().join("userkit_manifest_test");
+  std::fs::create_dir_all(&dir).unwrap();
+  let path = dir.join("unknown.yaml");
+  std::fs::write(&path, "user:\n  - name: alice\n    homedir: /home/alice\n").unwrap();
+  let mut cmd = run_userkit_command(vec!["plan", path.to_str().unwrap()]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("unknown field `homedir`"));
+}
+
+/// Lists the existing testuser next to two new users and a group.
+const MANIFEST: &str = r#"
+[[group]]
+name = "devs"
+gid = 1600
+
+[[user]]
+name = "testuser"
+groups = ["devs"]
+
+[[user]]
+name = "alice"
+uid = 1500
+home = "/home/alice"
+shell = "/bin/sh"
+groups = ["devs"]
+
+[[user]]
+name = "bob"
+uid = 1501
+home = "/home/bob"
+shell = "/bin/sh"
+"#;
+
+/// A root with testuser, homes for alice and bob that apply leaves as they
+/// are, and `manifest` written to manifest.toml in it.
+fn manifest_root(name: &str, manifest: &str) -> (String, String) {
+  let root = testuser_root(name);
+  for home in ["alice", "bob"] {
+    std::fs::create_dir_all(format!("{}/home/{}", root, home)).unwrap();
+  }
+  let path = format!("{}/manifest.toml", root);
+  std::fs::write(&path, manifest).unwrap();
+  (root, path)
+}
+
+fn read_root(root: &str, file: &str) -> String {
+  std::fs::read_to_string(format!("{}/etc/{}", root, file)).unwrap()
+}
+
+#[test]
+fn test_apply_manifest() {
+  let (root, path) = manifest_root("manifest_apply", MANIFEST);
+  let mut cmd = sudo_run_userkit_command(vec!["apply", &path, "--root", &root]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("Apply complete: 5 changes made."));
+  assert_eq!(
+    read_root(&root, "passwd"),
+    "root:x:0:0:root:/root:/bin/sh\n\
+     testuser:x:1000:1000::/home/testuser:/bin/sh\n\
+     alice:x:1500:1500::/home/alice:/bin/sh\n\
+     bob:x:1501:1501::/home/bob:/bin/sh\n"
+  );
+  assert_eq!(
+    read_root(&root, "group"),
+    "root:x:0:\ntestuser:x:1000:\ndevs:x:1600:testuser,alice\nalice:x:1500:\nbob:x:1501:\n"
+  );
+
+  // Applying it again has nothing left to do.
+  let mut again = sudo_run_userkit_command(vec!["apply", &path, "--root", &root]);
+  again
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("No changes"));
+}
+
+#[test]
+fn test_apply_prune_removes_only_what_apply_created() {
+  let (root, path) = manifest_root("manifest_prune", MANIFEST);
+  let mut apply = sudo_run_userkit_command(vec!["apply", &path, "--root", &root]);
+  apply.assert().success();
+
+  // Neither root nor testuser is listed any more, but apply did not create
+  // them, so only bob and devs go.
+  std::fs::write(&path, "[[user]]\nname = \"alice\"\n").unwrap();
+  let mut cmd = sudo_run_userkit_command(vec!["apply", "--prune", &path, "--root", &root]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("User bob removed"))
+    .stdout(predicate::str::contains("Group devs removed"));
+  assert_eq!(
+    read_root(&root, "passwd"),
+    "root:x:0:0:root:/root:/bin/sh\n\
+     testuser:x:1000:1000::/home/testuser:/bin/sh\n\
+     alice:x:1500:1500::/home/alice:/bin/sh\n"
+  );
+  // bob's private group goes with it.
+  assert_eq!(
+    read_root(&root, "group"),
+    "root:x:0:\ntestuser:x:1000:\nalice:x:1500:\n"
+  );
+}
@@ -1,10 +1,27 @@
 use assert_cmd::prelude::*;
 use predicates::prelude::*;
 mod test_utils;
-use test_utils::run_userkit_command;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use test_utils::{run_userkit_command, sudo_run_userkit_command, testuser_root};
+
+/// Creates the shared /tmp/testfile and /tmp/testdir targets if missing.
+fn ensure_fixtures() {
+  fs::create_dir_all("/tmp/testdir").unwrap();
+  fs::OpenOptions::new()
+    .create(true)
+    .append(true)
+    .open("/tmp/testfile")
+    .unwrap();
+}
+
+fn mode_of(path: &str) -> u32 {
+  fs::symlink_metadata(path).unwrap().mode() & 0o7777
+}
 
 #[test]
 fn test_perm_set() {
+  ensure_fixtures();
   let mut cmd = run_userkit_command(vec!["perm", "set", "/tmp/testfile", "755"]);
 
   cmd.assert().success().stdout(predicate::str::contains(
@@ -14,6 +31,7 @@ fn test_perm_set() {
 
 #[test]
 fn test_perm_set_recursive() {
+  ensure_fixtures();
   let mut cmd = run_userkit_command(vec!["perm", "set", "/tmp/testdir", "755", "--recursive"]);
 
   cmd.assert().success().stdout(predicate::str::contains(
@@ -23,6 +41,7 @@ fn test_perm_set_recursive() {
 
 #[test]
 fn test_perm_get() {
+  ensure_fixtures();
   let mut cmd = run_userkit_command(vec!["perm", "get", "/tmp/testfile"]);
 
   cmd
@@ -33,7 +52,16 @@ fn test_perm_get() {
 
 #[test]
 fn test_perm_check() {
-  let mut cmd = run_userkit_command(vec!["perm", "check", "testuser", "/tmp/testfile"]);
+  ensure_fixtures();
+  let root = testuser_root("perm_check");
+  let mut cmd = run_userkit_command(vec![
+    "perm",
+    "check",
+    "testuser",
+    "/tmp/testfile",
+    "--root",
+    &root,
+  ]);
 
   cmd
     .assert()
@@ -43,7 +71,8 @@ fn test_perm_check() {
 
 #[test]
 fn test_perm_sudo_enable() {
-  let mut cmd = run_userkit_command(vec!["perm", "sudo", "testuser", "enable"]);
+  let root = testuser_root("perm_sudo_enable");
+  let mut cmd = run_userkit_command(vec!["perm", "sudo", "testuser", "enable", "--root", &root]);
 
   cmd
     .assert()
@@ -53,7 +82,11 @@ fn test_perm_sudo_enable() {
 
 #[test]
 fn test_perm_sudo_disable() {
-  let mut cmd = run_userkit_command(vec!["perm", "sudo", "testuser", "disable"]);
+  let root = testuser_root("perm_sudo_disable");
+  let mut enable = run_userkit_command(vec!["perm", "sudo", "testuser", "enable", "--root", &root]);
+  enable.assert().success();
+
+  let mut cmd = run_userkit_command(vec!["perm", "sudo", "testuser", "disable", "--root", &root]);
 
   cmd.assert().success().stdout(predicate::str::contains(
     "Sudo access disabled for testuser",
@@ -62,6 +95,7 @@ fn test_perm_sudo_disable() {
 
 #[test]
 fn test_perm_set_invalid_permissions() {
+  ensure_fixtures();
   let mut cmd = run_userkit_command(vec!["perm", "set", "/tmp/testfile", "999"]); // Invalid permission value
 
   cmd
@@ -79,3 +113,355 @@ fn test_perm_check_nonexistent_user() {
     .failure()
     .stderr(predicate::str::contains("Error"));
 }
+
+#[test]
+fn test_perm_set_symbolic() {
+  let path = "/tmp/userkit-perm-symbolic";
+  fs::write(path, "").unwrap();
+  fs::set_permissions(path, fs::Permissions::from_mode(0o644)).unwrap();
+
+  let mut cmd = run_userkit_command(vec!["perm", "set", path, "u+x,g=u,o-r"]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(format!(
+      "Permissions set to u+x,g=u,o-r for {}",
+      path
+    )));
+  assert_eq!(mode_of(path), 0o770);
+  fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_perm_set_invalid_symbolic() {
+  let mut cmd = run_userkit_command(vec!["perm", "set", "/tmp", "u+q"]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Error: Invalid mode 'u+q'"));
+}
+
+#[test]
+fn test_perm_set_files_and_dirs() {
+  let root = "/tmp/userkit-perm-tree";
+  let _ = fs::remove_dir_all(root);
+  fs::create_dir_all(format!("{}/sub", root)).unwrap();
+  fs::write(format!("{}/sub/file", root), "").unwrap();
+  std::os::unix::fs::symlink("/tmp/testfile", format!("{}/link", root)).unwrap();
+  ensure_fixtures();
+  let outside = mode_of("/tmp/testfile");
+
+  let mut cmd = run_userkit_command(vec![
+    "perm",
+    "set",
+    root,
+    "750",
+    "--recursive",
+    "--files-only",
+    "600",
+  ]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(format!(
+      "Permissions set recursively to 750 for directories and 600 for files in {}",
+      root
+    )));
+  assert_eq!(mode_of(&format!("{}/sub", root)), 0o750);
+  assert_eq!(mode_of(&format!("{}/sub/file", root)), 0o600);
+  // The symlink is not followed out of the tree.
+  assert_eq!(mode_of("/tmp/testfile"), outside);
+  fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_perm_set_owner() {
+  // Names are looked up under the root; the file is kept there too, so the
+  // test can remove it again once it belongs to testuser.
+  let root = testuser_root("perm_set_owner");
+  let path = format!("{}/owned", root);
+  fs::write(&path, "").unwrap();
+
+  let mut cmd = sudo_run_userkit_command(vec![
+    "perm",
+    "set",
+    &path,
+    "--owner",
+    "testuser:",
+    "--root",
+    &root,
+  ]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(format!(
+      "Owner set to testuser: for {}",
+      path
+    )));
+  let metadata = fs::metadata(&path).unwrap();
+  assert_eq!(metadata.uid(), 1000);
+  assert_eq!(metadata.gid(), 1000);
+  fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_perm_set_nonexistent_owner() {
+  let mut cmd = run_userkit_command(vec!["perm", "set", "/tmp", "--owner", "nonexistentuser"]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Error"));
+}
+
+#[test]
+fn test_perm_get_json() {
+  let path = "/tmp/userkit-perm-get";
+  fs::write(path, "").unwrap();
+  fs::set_permissions(path, fs::Permissions::from_mode(0o4751)).unwrap();
+
+  let mut cmd = run_userkit_command(vec!["perm", "get", path, "--format", "json"]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("\"mode\": \"4751\""))
+    .stdout(predicate::str::contains("\"symbolic\": \"-rwsr-x--x\""))
+    .stdout(predicate::str::contains("\"setuid\""));
+  fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_perm_get_nonexistent_target() {
+  let mut cmd = run_userkit_command(vec!["perm", "get", "/tmp/userkit-perm-missing"]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Error"));
+}
+
+#[test]
+fn test_perm_check_as_group() {
+  let path = "/tmp/userkit-perm-check";
+  fs::write(path, "").unwrap();
+  fs::set_permissions(path, fs::Permissions::from_mode(0o640)).unwrap();
+
+  let mut cmd = run_userkit_command(vec!["perm", "check", "nobody", path]);
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(format!(
+      "User nobody has no access to {}",
+      path
+    )));
+
+  let mut cmd = run_userkit_command(vec!["perm", "check", "nobody", path, "--as-group", "root"]);
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(format!(
+      "User nobody has read access to {}",
+      path
+    )))
+    .stdout(predicate::str::contains(
+      "group root permissions r-- do not allow write",
+    ));
+  fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_perm_check_unsearchable_directory() {
+  let dir = "/tmp/userkit-perm-private";
+  fs::create_dir_all(dir).unwrap();
+  fs::set_permissions(dir, fs::Permissions::from_mode(0o700)).unwrap();
+  let path = format!("{}/file", dir);
+  fs::write(&path, "").unwrap();
+  fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+  let mut cmd = run_userkit_command(vec!["perm", "check", "nobody", &path]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("User nobody has no access"))
+    .stdout(predicate::str::contains(format!("cannot search {}", dir)));
+  fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn test_perm_check_symlink_in_unsearchable_directory() {
+  // The symlink points somewhere open, but lookup still has to pass
+  // through the directory that holds it
+  let dir = "/tmp/userkit-perm-private-link";
+  let open = "/tmp/userkit-perm-open";
+  fs::create_dir_all(dir).unwrap();
+  fs::set_permissions(dir, fs::Permissions::from_mode(0o700)).unwrap();
+  fs::create_dir_all(open).unwrap();
+  fs::set_permissions(open, fs::Permissions::from_mode(0o755)).unwrap();
+  fs::write(format!("{}/file", open), "").unwrap();
+  fs::set_permissions(format!("{}/file", open), fs::Permissions::from_mode(0o644)).unwrap();
+  let link = format!("{}/link", dir);
+  let _ = fs::remove_file(&link);
+  std::os::unix::fs::symlink(open, &link).unwrap();
+
+  let path = format!("{}/file", link);
+  let mut cmd = run_userkit_command(vec!["perm", "check", "nobody", &path]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("User nobody has no access"))
+    .stdout(predicate::str::contains(format!("cannot search {}", dir)));
+  fs::remove_dir_all(dir).unwrap();
+  fs::remove_dir_all(open).unwrap();
+}
+
+#[test]
+fn test_perm_acl_add_list_remove() {
+  let path = "/tmp/userkit-perm-acl";
+  fs::write(path, "").unwrap();
+  fs::set_permissions(path, fs::Permissions::from_mode(0o640)).unwrap();
+
+  let mut cmd = run_userkit_command(vec!["perm", "acl", "add", path, "u:nobody:rw"]);
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(format!(
+      "ACL entry u:nobody:rw added to {}",
+      path
+    )));
+  // The group class bits of the mode now hold the mask.
+  assert_eq!(mode_of(path), 0o660);
+
+  let mut cmd = run_userkit_command(vec!["perm", "acl", "list", path, "--format", "csv"]);
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("access,user,nobody,rw-,rw-"))
+    .stdout(predicate::str::contains("access,mask,,rw-,rw-"));
+
+  let mut cmd = run_userkit_command(vec!["perm", "acl", "remove", path, "u:nobody"]);
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(format!(
+      "ACL entry u:nobody removed from {}",
+      path
+    )));
+  assert_eq!(mode_of(path), 0o640);
+  fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_perm_acl_default_requires_directory() {
+  let path = "/tmp/userkit-perm-acl-default";
+  fs::write(path, "").unwrap();
+
+  let mut cmd = run_userkit_command(vec![
+    "perm",
+    "acl",
+    "add",
+    path,
+    "--default",
+    "g:nogroup:rX",
+  ]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Error: Default ACLs can only be set on directories",
+  ));
+  fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_perm_acl_invalid_entry() {
+  let mut cmd = run_userkit_command(vec!["perm", "acl", "add", "/tmp", "o::rwx"]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Error: Invalid ACL entry 'o::rwx'",
+  ));
+}
+
+#[test]
+fn test_perm_sudo_status() {
+  let mut cmd = run_userkit_command(vec!["perm", "sudo", "status", "root"]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("Sudo access for root:"));
+}
+
+#[test]
+fn test_perm_sudo_nonexistent_user() {
+  let mut cmd = run_userkit_command(vec!["perm", "sudo", "nonexistentuser", "enable"]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Error: User nonexistentuser does not exist",
+  ));
+}
+
+#[test]
+fn test_perm_sudo_grant_nonexistent_user() {
+  let mut cmd = run_userkit_command(vec![
+    "perm",
+    "sudo",
+    "grant",
+    "nonexistentuser",
+    "--command",
+    "/usr/bin/systemctl",
+  ]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Error: User nonexistentuser does not exist",
+  ));
+}
+
+#[test]
+fn test_perm_sudo_grant_newline_in_command() {
+  let mut cmd = sudo_run_userkit_command(vec![
+    "perm",
+    "sudo",
+    "grant",
+    "root",
+    "--command",
+    "/bin/true\nALL ALL=(ALL) NOPASSWD: ALL",
+  ]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Invalid command"));
+}
+
+#[test]
+fn test_perm_sudo_revoke_nonexistent_rule() {
+  let mut cmd = run_userkit_command(vec!["perm", "sudo", "revoke", "999999"]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Error: Sudo rule 999999 does not exist",
+  ));
+}
+
+#[test]
+fn test_perm_sudo_audit() {
+  let mut cmd = run_userkit_command(vec!["perm", "sudo", "audit", "root", "--format", "json"]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::starts_with("["));
+}
+
+#[test]
+fn test_perm_sudo_audit_nonexistent_user() {
+  let mut cmd = run_userkit_command(vec!["perm", "sudo", "audit", "nonexistentuser"]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Error: User nonexistentuser does not exist",
+  ));
+}
@@ -1,11 +1,22 @@
 use assert_cmd::prelude::*;
 use predicates::prelude::*;
 mod test_utils;
-use test_utils::run_userkit_command;
+use test_utils::{run_userkit_command, temp_root, testuser_root};
+
+/// A root with the user testuser and the given roles already created.
+fn role_root(name: &str, roles: &[&str]) -> String {
+  let root = testuser_root(name);
+  for role in roles {
+    let mut create = run_userkit_command(vec!["role", "create", role, "--root", &root]);
+    create.assert().success();
+  }
+  root
+}
 
 #[test]
 fn test_role_create() {
-  let mut cmd = run_userkit_command(vec!["role", "create", "testrole"]);
+  let root = role_root("role_create", &[]);
+  let mut cmd = run_userkit_command(vec!["role", "create", "testrole", "--root", &root]);
 
   cmd
     .assert()
@@ -15,12 +26,15 @@ fn test_role_create() {
 
 #[test]
 fn test_role_create_with_description() {
+  let root = role_root("role_create_with_description", &[]);
   let mut cmd = run_userkit_command(vec![
     "role",
     "create",
     "testrole2",
     "--description",
     "A test role with description",
+    "--root",
+    &root,
   ]);
 
   cmd
@@ -31,7 +45,8 @@ fn test_role_create_with_description() {
 
 #[test]
 fn test_role_delete() {
-  let mut cmd = run_userkit_command(vec!["role", "delete", "testrole"]);
+  let root = role_root("role_delete", &["testrole"]);
+  let mut cmd = run_userkit_command(vec!["role", "delete", "testrole", "--root", &root]);
 
   cmd
     .assert()
@@ -41,7 +56,15 @@ fn test_role_delete() {
 
 #[test]
 fn test_role_assign() {
-  let mut cmd = run_userkit_command(vec!["role", "assign", "testrole2", "testuser"]);
+  let root = role_root("role_assign", &["testrole2"]);
+  let mut cmd = run_userkit_command(vec![
+    "role",
+    "assign",
+    "testrole2",
+    "testuser",
+    "--root",
+    &root,
+  ]);
 
   cmd.assert().success().stdout(predicate::str::contains(
     "Role testrole2 assigned to user testuser",
@@ -50,7 +73,25 @@ fn test_role_assign() {
 
 #[test]
 fn test_role_revoke() {
-  let mut cmd = run_userkit_command(vec!["role", "revoke", "testrole2", "testuser"]);
+  let root = role_root("role_revoke", &["testrole2"]);
+  let mut assign = run_userkit_command(vec![
+    "role",
+    "assign",
+    "testrole2",
+    "testuser",
+    "--root",
+    &root,
+  ]);
+  assign.assert().success();
+
+  let mut cmd = run_userkit_command(vec![
+    "role",
+    "revoke",
+    "testrole2",
+    "testuser",
+    "--root",
+    &root,
+  ]);
 
   cmd.assert().success().stdout(predicate::str::contains(
     "Role testrole2 revoked from user testuser",
@@ -76,7 +117,8 @@ fn test_role_list_json_format() {
 
 #[test]
 fn test_role_info() {
-  let mut cmd = run_userkit_command(vec!["role", "info", "testrole2"]);
+  let root = role_root("role_info", &["testrole2"]);
+  let mut cmd = run_userkit_command(vec!["role", "info", "testrole2", "--root", &root]);
 
   cmd
     .assert()
@@ -86,7 +128,15 @@ fn test_role_info() {
 
 #[test]
 fn test_role_addperm() {
-  let mut cmd = run_userkit_command(vec!["role", "addperm", "testrole2", "read:/tmp/testfile"]);
+  let root = role_root("role_addperm", &["testrole2"]);
+  let mut cmd = run_userkit_command(vec![
+    "role",
+    "addperm",
+    "testrole2",
+    "read:/tmp/testfile",
+    "--root",
+    &root,
+  ]);
 
   cmd.assert().success().stdout(predicate::str::contains(
     "Permission read:/tmp/testfile added to role testrole2",
@@ -122,3 +172,111 @@ fn test_role_assign_nonexistent_user() {
     .failure()
     .stderr(predicate::str::contains("Error"));
 }
+
+#[test]
+fn test_role_removeperm_nonexistent_role() {
+  let mut cmd = run_userkit_command(vec![
+    "role",
+    "remove-perm",
+    "nonexistentrole",
+    "read:/tmp/testfile",
+  ]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Error: Role nonexistentrole does not exist",
+  ));
+}
+
+#[test]
+fn test_role_addperm_nonexistent_group() {
+  let root = role_root("role_addperm_nonexistent_group", &["testrole3"]);
+
+  let mut cmd = run_userkit_command(vec![
+    "role",
+    "add-perm",
+    "testrole3",
+    "group:nonexistentgroup",
+    "--root",
+    &root,
+  ]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Error: Group nonexistentgroup does not exist",
+  ));
+}
+
+#[test]
+fn test_role_sync() {
+  let root = temp_root("role_sync");
+  let mut cmd = run_userkit_command(vec!["role", "sync", "--root", &root]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("Roles"));
+}
+
+#[test]
+fn test_role_create_inherits_nonexistent_parent() {
+  let mut cmd = run_userkit_command(vec![
+    "role",
+    "create",
+    "testchildrole",
+    "--inherits",
+    "nonexistentrole",
+  ]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Error: Role nonexistentrole does not exist",
+  ));
+}
+
+#[test]
+fn test_role_tree() {
+  let mut cmd = run_userkit_command(vec!["role", "tree"]);
+
+  cmd.assert().success();
+}
+
+#[test]
+fn test_role_who_has_json_format() {
+  let mut cmd = run_userkit_command(vec!["role", "who-has", "group:users", "--format", "json"]);
+
+  cmd.assert().success().stdout(predicate::str::contains("["));
+}
+
+#[test]
+fn test_role_diff_nonexistent_role() {
+  let mut cmd = run_userkit_command(vec!["role", "diff", "nonexistentrole", "testrole2"]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Error: Role nonexistentrole does not exist",
+  ));
+}
+
+#[test]
+fn test_role_assign_until_past() {
+  let root = role_root("role_assign_until_past", &["testrole4"]);
+
+  let mut cmd = run_userkit_command(vec![
+    "role",
+    "assign",
+    "testrole4",
+    "root",
+    "--until",
+    "2020-01-01",
+    "--root",
+    &root,
+  ]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Error: The assignment would end in the past",
+  ));
+}
+
+#[test]
+fn test_role_reap_dry_run() {
+  let mut cmd = run_userkit_command(vec!["role", "reap", "--dry-run"]);
+
+  cmd.assert().success();
+}
@@ -1,5 +1,6 @@
 use std::process::Command;
 
+#[allow(dead_code)]
 pub fn run_userkit_command(subcommands: Vec<&str>) -> Command {
   let mut cmd = Command::new("./target/debug/userkit");
 
@@ -10,6 +11,7 @@ pub fn run_userkit_command(subcommands: Vec<&str>) -> Command {
   cmd
 }
 
+#[allow(dead_code)]
 pub fn sudo_run_userkit_command(subcommands: Vec<&str>) -> Command {
   let mut cmd = Command::new("sudo");
   cmd.arg("./target/debug/userkit");
@@ -20,3 +22,27 @@ pub fn sudo_run_userkit_command(subcommands: Vec<&str>) -> Command {
 
   cmd
 }
+
+/// A fresh, empty directory to pass as `--root`, so that a test does not
+/// change the system it runs on.
+#[allow(dead_code)]
+pub fn temp_root(name: &str) -> String {
+  let root = std::env::temp_dir().join("userkit_test_root").join(name);
+  let _ = std::fs::remove_dir_all(&root);
+  std::fs::create_dir_all(root.join("etc")).unwrap();
+  root.to_string_lossy().into_owned()
+}
+
+/// A `temp_root` whose account databases hold the user testuser.
+#[allow(dead_code)]
+pub fn testuser_root(name: &str) -> String {
+  let root = temp_root(name);
+  let etc = std::path::Path::new(&root).join("etc");
+  std::fs::write(
+    etc.join("passwd"),
+    "root:x:0:0:root:/root:/bin/sh\ntestuser:x:1000:1000::/home/testuser:/bin/sh\n",
+  )
+  .unwrap();
+  std::fs::write(etc.join("group"), "root:x:0:\ntestuser:x:1000:\n").unwrap();
+  root
+}
@@ -1,7 +1,7 @@
 use assert_cmd::prelude::*;
 use predicates::prelude::*;
 mod test_utils;
-use test_utils::{run_userkit_command, sudo_run_userkit_command};
+use test_utils::{run_userkit_command, sudo_run_userkit_command, testuser_root};
 
 #[test]
 fn test_user_add() {
@@ -165,3 +165,293 @@ fn test_user_shell_nonexistent_profile() {
     .failure()
     .stderr(predicate::str::contains("Error"));
 }
+
+#[test]
+fn test_user_roles_nonexistent_user() {
+  let mut cmd = run_userkit_command(vec!["user", "roles", "nonexistentuser"]);
+
+  cmd.assert().failure().stderr(predicate::str::contains(
+    "Error: User nonexistentuser does not exist",
+  ));
+}
+
+#[test]
+fn test_user_import_unknown_column() {
+  let dir = std::env::temp_dir().join("userkit_import_test");
+  std::fs::create_dir_all(&dir).unwrap();
+  let path = dir.join("users.csv");
+  std::fs::write(&path, "username,nickname\nalice,al\n").unwrap();
+  let mut cmd = sudo_run_userkit_command(vec!["user", "import", path.to_str().unwrap()]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("Unknown CSV column 'nickname'"));
+}
+
+/// A root with testuser, homes for alice and bob that imports leave as
+/// they are, and `input` written to `file` in it.
+fn import_root(name: &str, file: &str, input: &str) -> (String, String) {
+  let root = testuser_root(name);
+  for home in ["alice", "bob"] {
+    std::fs::create_dir_all(format!("{}/home/{}", root, home)).unwrap();
+  }
+  let path = format!("{}/{}", root, file);
+  std::fs::write(&path, input).unwrap();
+  (root, path)
+}
+
+fn read_root(root: &str, file: &str) -> String {
+  std::fs::read_to_string(format!("{}/etc/{}", root, file)).unwrap()
+}
+
+#[test]
+fn test_user_import_csv() {
+  let (root, path) = import_root(
+    "import_csv",
+    "users.csv",
+    "username,uid,shell,home_dir\nalice,1500,/bin/sh,/home/alice\nbob,1501,/bin/sh,/home/bob\n",
+  );
+  let mut cmd = sudo_run_userkit_command(vec!["user", "import", &path, "--root", &root]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("2 users created"));
+  assert_eq!(
+    read_root(&root, "passwd"),
+    "root:x:0:0:root:/root:/bin/sh\n\
+     testuser:x:1000:1000::/home/testuser:/bin/sh\n\
+     alice:x:1500:1500::/home/alice:/bin/sh\n\
+     bob:x:1501:1501::/home/bob:/bin/sh\n"
+  );
+  assert_eq!(
+    read_root(&root, "group"),
+    "root:x:0:\ntestuser:x:1000:\nalice:x:1500:\nbob:x:1501:\n"
+  );
+}
+
+#[test]
+fn test_user_import_newusers() {
+  let (root, path) = import_root(
+    "import_newusers",
+    "users.txt",
+    "alice:secret:1500:1500:Alice:/home/alice:/bin/sh\n",
+  );
+  let mut cmd = sudo_run_userkit_command(vec!["user", "import", &path, "--root", &root]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("1 users created"));
+  assert!(read_root(&root, "passwd").ends_with("\nalice:x:1500:1500:Alice:/home/alice:/bin/sh\n"));
+  assert!(read_root(&root, "group").ends_with("\nalice:x:1500:\n"));
+}
+
+#[test]
+fn test_user_import_bad_row_rolls_back() {
+  let (root, path) = import_root(
+    "import_rollback",
+    "users.csv",
+    "username,uid,home_dir\nalice,1500,/home/alice\ntestuser,1501,/home/bob\n",
+  );
+  let passwd = read_root(&root, "passwd");
+  let group = read_root(&root, "group");
+  let mut cmd = sudo_run_userkit_command(vec!["user", "import", &path, "--root", &root]);
+
+  cmd
+    .assert()
+    .failure()
+    .stdout(predicate::str::contains("rolled back"))
+    .stderr(predicate::str::contains(
+      "Import aborted; no users were created",
+    ));
+  assert_eq!(read_root(&root, "passwd"), passwd);
+  assert_eq!(read_root(&root, "group"), group);
+}
+
+#[test]
+fn test_user_import_json_report() {
+  let (root, path) = import_root(
+    "import_json",
+    "users.csv",
+    "username,uid,home_dir\nalice,1500,/home/alice\ntestuser,1501,/home/bob\n",
+  );
+  let mut cmd = sudo_run_userkit_command(vec![
+    "user",
+    "import",
+    &path,
+    "--continue-on-error",
+    "--format",
+    "json",
+    "--root",
+    &root,
+  ]);
+
+  cmd
+    .assert()
+    .failure()
+    .stdout(predicate::str::contains(
+      "\"username\": \"alice\",\n    \"status\": \"created\",\n    \"uid\": 1500",
+    ))
+    .stdout(predicate::str::contains(
+      "\"username\": \"testuser\",\n    \"status\": \"failed\",\n    \"message\": \"User testuser already exists\"",
+    ));
+  assert!(read_root(&root, "passwd").contains("\nalice:x:1500:"));
+}
+
+#[test]
+fn test_check_yes_requires_fix() {
+  let mut cmd = run_userkit_command(vec!["check", "--yes"]);
+
+  cmd
+    .assert()
+    .failure()
+    .stderr(predicate::str::contains("--fix"));
+}
+
+/// Writes account databases under a fresh directory for `check --root`.
+fn check_fixture(name: &str, passwd: &str, shadow: &str, group: &str, gshadow: &str) -> String {
+  use std::os::unix::fs::PermissionsExt;
+
+  let root = std::env::temp_dir().join("userkit_check_test").join(name);
+  let _ = std::fs::remove_dir_all(&root);
+  std::fs::create_dir_all(root.join("etc")).unwrap();
+  std::fs::create_dir_all(root.join("bin")).unwrap();
+  std::fs::write(root.join("bin/sh"), "").unwrap();
+  std::fs::set_permissions(root.join("bin/sh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+  for (file, content) in [
+    ("passwd", passwd),
+    ("shadow", shadow),
+    ("group", group),
+    ("gshadow", gshadow),
+  ] {
+    std::fs::write(root.join("etc").join(file), content).unwrap();
+  }
+  root.to_string_lossy().into_owned()
+}
+
+#[test]
+fn test_check_clean_fixture() {
+  let root = check_fixture(
+    "clean",
+    "root:x:0:0:root:/root:/bin/sh\ndaemon:x:1:1::/:/bin/sh\n",
+    "root:*:19000:0:99999:7:::\ndaemon:*:19000:0:99999:7:::\n",
+    "root:x:0:\ndaemon:x:1:\n",
+    "root:*::\ndaemon:*::\n",
+  );
+  let mut cmd = run_userkit_command(vec!["check", "--root", &root]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("No problems found"));
+}
+
+#[test]
+fn test_check_duplicates() {
+  let root = check_fixture(
+    "duplicates",
+    "root:x:0:0:root:/root:/bin/sh\ndaemon:x:1:1::/:/bin/sh\ndaemon:x:1:1::/:/bin/sh\nbin:x:1:1::/:/bin/sh\n",
+    "root:*:19000:0:99999:7:::\ndaemon:*:19000:0:99999:7:::\nbin:*:19000:0:99999:7:::\n",
+    "root:x:0:\ndaemon:x:1:\n",
+    "root:*::\ndaemon:*::\n",
+  );
+  let mut cmd = run_userkit_command(vec!["check", "--root", &root]);
+
+  cmd
+    .assert()
+    .failure()
+    .stdout(predicate::str::contains(
+      "line 3: Duplicate user daemon (first on line 2)",
+    ))
+    .stdout(predicate::str::contains(
+      "User bin has UID 1, as does daemon",
+    ));
+}
+
+#[test]
+fn test_check_missing_entries_and_members() {
+  let root = check_fixture(
+    "missing",
+    "root:x:0:0:root:/root:/bin/sh\ndaemon:x:1:1::/:/bin/sh\n",
+    "root:*:19000:0:99999:7:::\n",
+    "root:x:0:\ndaemon:x:1:ghost\n",
+    "root:*::\n",
+  );
+  let mut cmd = run_userkit_command(vec!["check", "--root", &root]);
+
+  cmd
+    .assert()
+    .failure()
+    .stdout(predicate::str::contains("User daemon has no shadow entry"))
+    .stdout(predicate::str::contains(
+      "Group daemon has no gshadow entry",
+    ))
+    .stdout(predicate::str::contains(
+      "Member ghost of group daemon does not exist",
+    ));
+}
+
+#[test]
+fn test_check_sort_order() {
+  let root = check_fixture(
+    "unsorted",
+    "daemon:x:1:1::/:/bin/sh\nroot:x:0:0:root:/root:/bin/sh\n",
+    "daemon:*:19000:0:99999:7:::\nroot:*:19000:0:99999:7:::\n",
+    "daemon:x:1:\nroot:x:0:\n",
+    "daemon:*::\nroot:*::\n",
+  );
+  let mut cmd = run_userkit_command(vec!["check", "--root", &root]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("Entries are not sorted by UID"))
+    .stdout(predicate::str::contains("Entries are not sorted by GID"));
+}
+
+#[test]
+fn test_check_fix_missing_shadow() {
+  let root = check_fixture(
+    "fix",
+    "root:x:0:0:root:/root:/bin/sh\ndaemon:x:1:1::/:/bin/sh\n",
+    "root:*:19000:0:99999:7:::\n",
+    "root:x:0:\ndaemon:x:1:\n",
+    "root:*::\ndaemon:*::\n",
+  );
+  let mut cmd = run_userkit_command(vec!["check", "--root", &root, "--fix", "--yes"]);
+
+  cmd.assert().success().stdout(predicate::str::contains(
+    "Fixed: add a locked shadow entry for daemon",
+  ));
+
+  let mut cmd = run_userkit_command(vec!["check", "--root", &root]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains("No problems found"));
+}
+
+#[test]
+fn test_check_home_owner_not_fixable() {
+  let root = check_fixture(
+    "owner",
+    "root:x:0:0:root:/root:/bin/sh\nalice:x:59999:59999::/home/alice:/bin/sh\n",
+    "root:*:19000:0:99999:7:::\nalice:*:19000:0:99999:7:::\n",
+    "root:x:0:\nalice:x:59999:\n",
+    "root:*::\nalice:*::\n",
+  );
+  std::fs::create_dir_all(std::path::Path::new(&root).join("home/alice")).unwrap();
+  let mut cmd = run_userkit_command(vec!["check", "--root", &root]);
+
+  cmd
+    .assert()
+    .success()
+    .stdout(predicate::str::contains(
+      "Home directory /home/alice of user alice is owned by UID",
+    ))
+    .stdout(predicate::str::contains("(warning)"))
+    .stdout(predicate::str::contains("0 can be repaired"));
+}